@@ -26,11 +26,13 @@ fn main() {
     // initalize db harness. if you would like to see how to implement your own, look inside the harness modules.
     let harness = DbHarness::new_sqlite(pool);
 
-    // once the harness is selected, go ahead and initialize tables. Init tables creates a table if user, session and token tables do not already exist.
-    harness.init_tables().unwrap();
+    // once the harness is selected, go ahead and migrate. migrate creates the tables on a new database and upgrades an existing one to the latest schema.
+    harness.migrate().unwrap();
 
-    let user_manager = UserManagerConfig::default().init(harness.user);
-    let session_manager = SessionManagerConfig::default().init(harness.session, harness.token);
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
 
     let mut i = 0;
 
@@ -38,14 +40,14 @@ fn main() {
         let user: MyUser = user_manager
             .create_user(
                 i.to_string(),
-                "pwd".to_string(),
+                "correct horse battery staple".to_string(),
                 Roles::Admin.as_role(),
                 Some(MyPublicUserMetadata),
                 Some(MyPrivateUserMetadata),
             )
             .unwrap();
 
-        let pwd_str = "pwd";
+        let pwd_str = "correct horse battery staple";
 
         match user_manager.login(&session_manager, &user, pwd_str) {
             Ok((mut session, refresh_secret, _access_secret)) => {
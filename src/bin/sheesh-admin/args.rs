@@ -0,0 +1,87 @@
+use std::{collections::HashMap, error, fmt::Display};
+
+// options that do not take a value.
+const FLAGS: [&str; 4] = ["--json", "--must-change", "--dry-run", "--help"];
+
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl error::Error for UsageError {}
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn usage(message: String) -> Box<dyn error::Error> {
+    Box::new(UsageError(message))
+}
+
+/// The command line split into positional arguments and `--options`, which may appear anywhere.
+pub struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, Box<dyn error::Error>> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg);
+            } else if FLAGS.contains(&arg.as_str()) {
+                options.insert(arg, None);
+            } else {
+                match args.next() {
+                    Some(value) => options.insert(arg, Some(value)),
+                    None => return Err(usage(format!("{} needs a value", arg))),
+                };
+            }
+        }
+        positional.reverse();
+
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    /// The next positional argument, `name` is used in the error when there is none.
+    pub fn next(&mut self, name: &str) -> Result<String, Box<dyn error::Error>> {
+        self.positional
+            .pop()
+            .ok_or_else(|| usage(format!("missing {}", name)))
+    }
+
+    pub fn flag(&mut self, name: &str) -> bool {
+        self.options.remove(name).is_some()
+    }
+
+    pub fn option(&mut self, name: &str) -> Option<String> {
+        self.options.remove(name).flatten()
+    }
+
+    pub fn number(&mut self, name: &str, default: usize) -> Result<usize, Box<dyn error::Error>> {
+        match self.option(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| usage(format!("{} must be a number", name))),
+            None => Ok(default),
+        }
+    }
+
+    /// Fails on anything the command did not use.
+    pub fn finish(self) -> Result<(), Box<dyn error::Error>> {
+        if let Some(arg) = self.positional.last() {
+            return Err(usage(format!("unexpected argument {}", arg)));
+        }
+        if let Some(option) = self.options.keys().next() {
+            return Err(usage(format!("unknown option {}", option)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+//! `sheesh-admin` manages the users and sessions of a sheesh SQLite database, see `USAGE`.
+
+mod args;
+
+use std::{error, io, process::ExitCode, sync::Arc};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde_json::{json, Value};
+use sheesh::{
+    harness::{
+        migration::Migration,
+        sqlite::{SqliteAuditSink, SqliteHarnessSession, SqliteHarnessToken, SqliteHarnessUser},
+        DbHarness,
+    },
+    id::DefaultIdGenerator,
+    session::{Session, SessionManager, SessionManagerConfig},
+    sweeper::SweeperConfig,
+    user::{Group, Role, User, UserManager, UserManagerConfig},
+};
+
+use args::{Args, UsageError};
+
+const USAGE: &str = "usage: sheesh-admin --db <path> [--json] <command>
+
+commands:
+    migrate [--dry-run]                     bring the schema to the latest version, or print what that would run
+    sweep [--batch-size <n>]                delete expired tokens and dead sessions
+    user create <username> [--role <role>] [--email <email>]
+    user list [--offset <n>] [--limit <n>]
+    user search <term> [--limit <n>]
+    user show <user>
+    user ban <user>                         also revokes the user's sessions
+    user unban <user>
+    user reset-password <user> [--must-change]
+    user set-role <user> <role>
+    user add-group <user> <group>
+    user remove-group <user> <group>
+    session list <user>
+    session revoke <session id>
+    session revoke-all <user>
+
+<user> is a user id or a username. passwords are read from the first line of stdin, never from the arguments.";
+
+type AdminUser = User<(), ()>;
+
+struct Admin {
+    pool: Pool<SqliteConnectionManager>,
+    users: UserManager<DefaultIdGenerator, SqliteHarnessUser<'static>>,
+    sessions: SessionManager<DefaultIdGenerator, SqliteHarnessSession, SqliteHarnessToken>,
+    json: bool,
+}
+
+impl Admin {
+    fn open(path: &str, json: bool) -> Result<Self, Box<dyn error::Error>> {
+        let pool = r2d2::Pool::new(SqliteConnectionManager::file(path))?;
+        let harness = DbHarness::new_sqlite(pool.clone());
+
+        let mut user_config = UserManagerConfig::default();
+        let mut session_config = SessionManagerConfig::default();
+        // changes made here end up in the same audit log as the application's.
+        if has_table(&pool, "audit_log")? {
+            let sink = Arc::new(SqliteAuditSink::new(pool.clone()));
+            user_config = user_config.with_audit_sink(sink.clone());
+            session_config = session_config.with_audit_sink(sink);
+        }
+
+        Ok(Self {
+            users: user_config.init(harness.user)?,
+            sessions: session_config.init(harness.session, harness.token)?,
+            pool,
+            json,
+        })
+    }
+
+    fn run(&self, mut args: Args) -> Result<(), Box<dyn error::Error>> {
+        match args.next("command")?.as_str() {
+            "migrate" => {
+                let dry_run = args.flag("--dry-run");
+                args.finish()?;
+                self.migrate(dry_run)
+            }
+            "sweep" => {
+                let batch_size = args.number("--batch-size", 500)?;
+                args.finish()?;
+                self.sweep(batch_size)
+            }
+            "user" => self.user(args),
+            "session" => self.session(args),
+            command => Err(UsageError(format!("unknown command {}", command)).into()),
+        }
+    }
+
+    fn migrate(&self, dry_run: bool) -> Result<(), Box<dyn error::Error>> {
+        let harness = DbHarness::new_sqlite(self.pool.clone());
+        let migrations = match dry_run {
+            true => harness.pending_migrations()?,
+            false => harness.migrate()?,
+        };
+        let version = harness.schema_version()?;
+
+        match self.json {
+            true => {
+                let migrations: Vec<Value> =
+                    migrations.iter().copied().map(migration_json).collect();
+                println!(
+                    "{}",
+                    json!({"version": version, "dry_run": dry_run, "migrations": migrations})
+                );
+            }
+            false if dry_run => {
+                for migration in &migrations {
+                    println!("{}\n", migration);
+                }
+            }
+            false => {
+                for migration in &migrations {
+                    println!("applied {}: {}", migration.version(), migration.name());
+                }
+                println!("schema version {}", version);
+            }
+        }
+        Ok(())
+    }
+
+    fn sweep(&self, batch_size: usize) -> Result<(), Box<dyn error::Error>> {
+        let report = SweeperConfig::default()
+            .with_batch_size(batch_size)
+            .init(
+                SqliteHarnessSession::new(self.pool.clone()),
+                SqliteHarnessToken::new(self.pool.clone()),
+            )?
+            .sweep()?;
+        self.done(
+            &format!(
+                "deleted {} tokens and {} sessions",
+                report.tokens, report.sessions
+            ),
+            json!({"tokens": report.tokens, "sessions": report.sessions}),
+        );
+        Ok(())
+    }
+
+    fn user(&self, mut args: Args) -> Result<(), Box<dyn error::Error>> {
+        let command = args.next("user command")?;
+        match command.as_str() {
+            "create" => {
+                let username = args.next("username")?;
+                let role = args.option("--role").unwrap_or("user".to_string());
+                let email = args.option("--email");
+                args.finish()?;
+                let password = read_password()?;
+                let user: AdminUser = match email {
+                    Some(email) => self.users.create_user_with_email(
+                        username,
+                        email,
+                        password,
+                        Role::from_string(role),
+                        None,
+                        None,
+                    ),
+                    None => self.users.create_user(
+                        username,
+                        password,
+                        Role::from_string(role),
+                        None,
+                        None,
+                    ),
+                }?;
+                self.print_user(&user);
+            }
+            "list" => {
+                let offset = args.number("--offset", 0)?;
+                let limit = args.number("--limit", 100)?;
+                args.finish()?;
+                self.print_users(&self.users.list_users(offset, limit)?);
+            }
+            "search" => {
+                let term = args.next("search term")?;
+                let limit = args.number("--limit", 100)?;
+                args.finish()?;
+                self.print_users(&self.users.search_users(&term, limit)?);
+            }
+            "show" => {
+                let user = self.find_user(&args.next("user")?)?;
+                args.finish()?;
+                self.print_user(&user);
+            }
+            "ban" | "unban" => {
+                let mut user = self.find_user(&args.next("user")?)?;
+                args.finish()?;
+                match command == "ban" {
+                    true => user.ban(),
+                    false => user.unban(),
+                }
+                self.users.update_user(user.clone())?;
+                if user.is_banned() {
+                    self.sessions.invalidate_user_sessions(user.id())?;
+                }
+                self.print_user(&user);
+            }
+            "reset-password" => {
+                let user = self.find_user(&args.next("user")?)?;
+                let must_change = args.flag("--must-change");
+                args.finish()?;
+                let password = read_password()?;
+                self.users.update_password(user.clone(), password)?;
+                // update_password clears the flag, it has to be set on the stored user afterwards.
+                let mut user = self.find_user(&user.id().to_string())?;
+                if must_change {
+                    user.set_must_change_password(true);
+                    self.users.update_user(user.clone())?;
+                }
+                self.sessions.invalidate_user_sessions(user.id())?;
+                self.print_user(&user);
+            }
+            "set-role" => {
+                let mut user = self.find_user(&args.next("user")?)?;
+                let role = args.next("role")?;
+                args.finish()?;
+                user.set_role(Role::from_string(role));
+                self.users.update_user(user.clone())?;
+                self.print_user(&user);
+            }
+            "add-group" | "remove-group" => {
+                let mut user = self.find_user(&args.next("user")?)?;
+                let group = Group::from_string(args.next("group")?);
+                args.finish()?;
+                match command == "add-group" {
+                    true => user.add_group(group),
+                    false => user.remove_group(group),
+                }
+                self.users.update_user(user.clone())?;
+                self.print_user(&user);
+            }
+            command => return Err(UsageError(format!("unknown user command {}", command)).into()),
+        }
+        Ok(())
+    }
+
+    fn session(&self, mut args: Args) -> Result<(), Box<dyn error::Error>> {
+        match args.next("session command")?.as_str() {
+            "list" => {
+                let user = self.find_user(&args.next("user")?)?;
+                args.finish()?;
+                self.print_sessions(&self.sessions.get_user_sessions(user.id())?);
+            }
+            "revoke" => {
+                let id = args.next("session id")?;
+                args.finish()?;
+                let id: i64 = id
+                    .parse()
+                    .map_err(|_| UsageError(format!("{} is not a session id", id)))?;
+                let session = self.sessions.get_session(id)?;
+                self.sessions.invalidate_session(session)?;
+                self.done(&format!("revoked session {}", id), json!({"session": id}));
+            }
+            "revoke-all" => {
+                let user = self.find_user(&args.next("user")?)?;
+                args.finish()?;
+                self.sessions.invalidate_user_sessions(user.id())?;
+                self.done(
+                    &format!("revoked every session of {}", user.username()),
+                    json!({"user": user.id()}),
+                );
+            }
+            command => {
+                return Err(UsageError(format!("unknown session command {}", command)).into())
+            }
+        }
+        Ok(())
+    }
+
+    // a number is taken as an id first, then as a username.
+    fn find_user(&self, user: &str) -> Result<AdminUser, Box<dyn error::Error>> {
+        if let Ok(id) = user.parse::<i64>() {
+            if let Some(found) = self.users.get_user(&id)? {
+                return Ok(found);
+            }
+        }
+        match self.users.get_user_by_username(user)? {
+            Some(found) => Ok(found),
+            None => Err(format!("no user {}", user).into()),
+        }
+    }
+
+    fn print_user(&self, user: &AdminUser) {
+        match self.json {
+            true => println!("{}", user_json(user)),
+            false => {
+                println!("id:                   {}", user.id());
+                println!("username:             {}", user.username());
+                println!("email:                {}", user.email().unwrap_or("-"));
+                println!("role:                 {}", user.role().as_str());
+                println!("groups:               {}", user.groups().to_string());
+                println!("status:               {}", user_state(user));
+                println!(
+                    "password changed:     {}",
+                    user.password_changed_at()
+                        .map(|time| time.to_rfc3339())
+                        .unwrap_or("-".to_string())
+                );
+                println!("must change password: {}", user.must_change_password());
+            }
+        }
+    }
+
+    fn print_users(&self, users: &[AdminUser]) {
+        match self.json {
+            true => println!("{}", Value::Array(users.iter().map(user_json).collect())),
+            false => {
+                for user in users {
+                    println!(
+                        "{:<20} {:<32} {:<12} {}",
+                        user.id(),
+                        user.username(),
+                        user.role().as_str(),
+                        user_state(user)
+                    );
+                }
+            }
+        }
+    }
+
+    fn print_sessions(&self, sessions: &[Session]) {
+        match self.json {
+            true => {
+                let sessions = sessions
+                    .iter()
+                    .map(|session| {
+                        json!({
+                            "id": session.id(),
+                            "user_id": session.user_id(),
+                            "refresh_token": session.refresh_token(),
+                            "access_token": session.access_token(),
+                        })
+                    })
+                    .collect();
+                println!("{}", Value::Array(sessions));
+            }
+            false => {
+                for session in sessions {
+                    let state = match session.refresh_token().or(session.access_token()) {
+                        Some(_) => "active",
+                        None => "revoked",
+                    };
+                    println!("{:<20} {}", session.id(), state);
+                }
+            }
+        }
+    }
+
+    fn done(&self, message: &str, details: Value) {
+        match self.json {
+            true => println!("{}", details),
+            false => println!("{}", message),
+        }
+    }
+}
+
+fn user_json(user: &AdminUser) -> Value {
+    json!({
+        "id": user.id(),
+        "username": user.username(),
+        "email": user.email(),
+        "role": user.role().as_str(),
+        "groups": user.groups().iter().map(|group| group.as_str()).collect::<Vec<&str>>(),
+        "banned": user.is_banned(),
+        "status": user.status().as_str(),
+        "password_changed_at": user.password_changed_at().map(|time| time.to_rfc3339()),
+        "must_change_password": user.must_change_password(),
+    })
+}
+
+fn migration_json(migration: &Migration) -> Value {
+    json!({
+        "version": migration.version(),
+        "name": migration.name(),
+        "sql": migration.sql().trim(),
+        "skip_if": migration.skip_if(),
+    })
+}
+
+fn user_state(user: &AdminUser) -> String {
+    match user.is_banned() {
+        true => "banned".to_string(),
+        false => user.status().as_str().to_string(),
+    }
+}
+
+fn has_table(
+    pool: &Pool<SqliteConnectionManager>,
+    name: &str,
+) -> Result<bool, Box<dyn error::Error>> {
+    let count: i64 = pool.get()?.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn read_password() -> Result<String, Box<dyn error::Error>> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(UsageError("expected the password on stdin".to_string()).into());
+    }
+    Ok(password.to_string())
+}
+
+fn main() -> ExitCode {
+    let result = Args::parse(std::env::args().skip(1)).and_then(|mut args| {
+        if args.flag("--help") {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        let json = args.flag("--json");
+        let db = args
+            .option("--db")
+            .ok_or_else(|| UsageError("missing --db".to_string()))?;
+        Admin::open(&db, json)?.run(args)
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if err.is::<UsageError>() => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
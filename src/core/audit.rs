@@ -0,0 +1,279 @@
+use std::{error, io, sync::Arc};
+
+use chrono::{DateTime, Utc};
+
+use super::{
+    clock::Clock,
+    pepper::{self, Pepper},
+    Error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventKind {
+    UserCreated,
+    UserDeleted,
+    LoginSucceeded,
+    LoginFailed,
+    Logout,
+    TokenRefreshed,
+    TokenRevoked,
+    PasswordChanged,
+    Banned,
+    Unbanned,
+    RoleChanged,
+}
+
+impl AuditEventKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::UserCreated => "user_created",
+            Self::UserDeleted => "user_deleted",
+            Self::LoginSucceeded => "login_succeeded",
+            Self::LoginFailed => "login_failed",
+            Self::Logout => "logout",
+            Self::TokenRefreshed => "token_refreshed",
+            Self::TokenRevoked => "token_revoked",
+            Self::PasswordChanged => "password_changed",
+            Self::Banned => "banned",
+            Self::Unbanned => "unbanned",
+            Self::RoleChanged => "role_changed",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "user_created" => Some(Self::UserCreated),
+            "user_deleted" => Some(Self::UserDeleted),
+            "login_succeeded" => Some(Self::LoginSucceeded),
+            "login_failed" => Some(Self::LoginFailed),
+            "logout" => Some(Self::Logout),
+            "token_refreshed" => Some(Self::TokenRefreshed),
+            "token_revoked" => Some(Self::TokenRevoked),
+            "password_changed" => Some(Self::PasswordChanged),
+            "banned" => Some(Self::Banned),
+            "unbanned" => Some(Self::Unbanned),
+            "role_changed" => Some(Self::RoleChanged),
+            _ => None,
+        }
+    }
+}
+
+/// Something that happened to an account. `user_id` is None for events about clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    kind: AuditEventKind,
+    user_id: Option<i64>,
+    time: DateTime<Utc>,
+    detail: Option<String>,
+}
+
+impl AuditEvent {
+    /// An event that happens now according to `clock`, the managers pass the clock they are configured with.
+    pub fn new(
+        clock: &dyn Clock,
+        kind: AuditEventKind,
+        user_id: Option<i64>,
+        detail: Option<String>,
+    ) -> Self {
+        Self {
+            kind,
+            user_id,
+            time: clock.now(),
+            detail,
+        }
+    }
+
+    pub fn from_values(
+        kind: AuditEventKind,
+        user_id: Option<i64>,
+        time: DateTime<Utc>,
+        detail: Option<String>,
+    ) -> Self {
+        Self {
+            kind,
+            user_id,
+            time,
+            detail,
+        }
+    }
+
+    pub fn kind(&self) -> AuditEventKind {
+        self.kind
+    }
+
+    pub fn user_id(&self) -> Option<i64> {
+        self.user_id
+    }
+
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+}
+
+/// Filters for `AuditSink::query`. Every filter that is set has to match, an empty query matches every event.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    user_id: Option<i64>,
+    kinds: Vec<AuditEventKind>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user(mut self, user_id: i64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// Can be given more than once, the event has to be of one of the kinds.
+    pub fn with_kind(mut self, kind: AuditEventKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Inclusive.
+    pub fn with_since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Exclusive.
+    pub fn with_until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn user_id(&self) -> Option<i64> {
+        self.user_id
+    }
+
+    pub fn kinds(&self) -> &[AuditEventKind] {
+        &self.kinds
+    }
+
+    pub fn since(&self) -> Option<DateTime<Utc>> {
+        self.since
+    }
+
+    pub fn until(&self) -> Option<DateTime<Utc>> {
+        self.until
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Whether `event` passes the filters, the limit is not taken into account.
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.user_id.is_none_or(|id| event.user_id == Some(id))
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+            && self.since.is_none_or(|since| event.time >= since)
+            && self.until.is_none_or(|until| event.time < until)
+    }
+}
+
+/// Where the managers send their audit events, see `with_audit_sink` on the manager configs.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: &AuditEvent) -> Result<(), Box<dyn error::Error + Send + Sync>>;
+
+    /// Matching events, oldest first. Sinks that only forward events do not have to support this.
+    fn query(
+        &self,
+        _query: &AuditQuery,
+    ) -> Result<Vec<AuditEvent>, Box<dyn error::Error + Send + Sync>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "audit sink can not be queried").into())
+    }
+}
+
+impl<S: AuditSink + ?Sized> AuditSink for Arc<S> {
+    fn record(&self, event: &AuditEvent) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        (**self).record(event)
+    }
+
+    fn query(
+        &self,
+        query: &AuditQuery,
+    ) -> Result<Vec<AuditEvent>, Box<dyn error::Error + Send + Sync>> {
+        (**self).query(query)
+    }
+}
+
+/// The hash of the first record's predecessor.
+pub const GENESIS_HASH: &str = "";
+
+/// Chains a record to the one before it: the hash covers the previous hash, the record's position and every field
+/// of the event, so editing, removing or reordering a record breaks every hash after it.
+///
+/// With a pepper the hash is an HMAC under its current key, stored as "pepper:<id>:<mac>" like peppered token
+/// digests. Without one anybody with write access to the log can rewrite it and recompute every hash after the
+/// edit, the chain then only holds up against a `head` that was stored somewhere else.
+pub fn chain_hash(
+    pepper: Option<&Pepper>,
+    prev_hash: &str,
+    seq: i64,
+    event: &AuditEvent,
+) -> Result<String, Error> {
+    pepper::digest_token(pepper, &chain_input(prev_hash, seq, event))
+}
+
+/// Whether `hash` is the `chain_hash` of the record, under the pepper key it names. Retired keys still verify, an
+/// unkeyed hash does not once there is a pepper: anybody could have computed it.
+pub fn verify_chain_hash(
+    pepper: Option<&Pepper>,
+    prev_hash: &str,
+    seq: i64,
+    event: &AuditEvent,
+    hash: &str,
+) -> bool {
+    if pepper.is_some() && pepper::decode(hash).is_none() {
+        return false;
+    }
+    pepper::verify_token_digest(pepper, &chain_input(prev_hash, seq, event), hash).is_ok()
+}
+
+fn chain_input(prev_hash: &str, seq: i64, event: &AuditEvent) -> String {
+    // every field is length prefixed and a missing field is "-", no choice of detail can make two different
+    // records hash the same.
+    let fields = [
+        Some(prev_hash.to_string()),
+        Some(seq.to_string()),
+        Some(event.time.timestamp_micros().to_string()),
+        Some(event.kind.as_str().to_string()),
+        event.user_id.map(|id| id.to_string()),
+        event.detail.clone(),
+    ];
+    fields
+        .iter()
+        .map(|field| match field {
+            Some(field) => format!("{}:{}", field.len(), field),
+            None => "-".to_string(),
+        })
+        .collect()
+}
+
+// auditing is best effort: a sink that is down must not lock every user out.
+pub(crate) fn record(
+    sink: &Option<Arc<dyn AuditSink>>,
+    clock: &dyn Clock,
+    kind: AuditEventKind,
+    user_id: Option<i64>,
+    detail: Option<String>,
+) {
+    if let Some(sink) = sink {
+        let _ = sink.record(&AuditEvent::new(clock, kind, user_id, detail));
+    }
+}
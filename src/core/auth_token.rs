@@ -1,47 +1,63 @@
 use crate::harness::DbHarnessToken;
-use std::{
-    error,
-    fmt::{Debug, Display},
-};
+use std::{fmt::Debug, sync::Arc};
 
 use super::{
+    audit::AuditSink,
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
     default_hash_fn, default_rng_salt_fn, default_rng_token_fn, default_verify_token_fn,
     id::{DefaultIdGenerator, IdGenerator},
+    pepper::{self, Pepper},
+    scope::Scopes,
+    Error, ErrorKind, GenerateFn, HashFn, VerifyFn,
 };
-use chrono::{offset::LocalResult, DateTime, TimeDelta, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 
 #[derive(Debug, Clone)]
 pub enum TokenType {
     Refresh { secret: String },
+    // a digest of the token, the token itself is never stored.
     Access { token: String },
 }
 
 pub enum TokenTtl {
-    Refresh(i64),
+    Refresh(TimeDelta),
     Access,
 }
 
+/// Who a token was issued to. User tokens come from a `Session`, client tokens from the client credentials grant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSubject {
+    User(i64),
+    Client(i64),
+}
+
 pub struct AuthTokenManagerConfig<T>
 where
     T: IdGenerator,
 {
-    ttl: i64,
+    ttl: TimeDelta,
     id_generator: T,
-    salt_fn: fn() -> String,
-    token_fn: fn() -> String,
-    hash_fn: fn(&str, &str) -> Result<String, AuthTokenError>,
-    verify_token_fn: fn(&str, &str) -> Result<(), AuthTokenError>,
+    salt_fn: GenerateFn,
+    token_fn: GenerateFn,
+    hash_fn: HashFn,
+    verify_token_fn: VerifyFn,
+    pepper: Option<Arc<Pepper>>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl AuthTokenManagerConfig<DefaultIdGenerator> {
     pub fn default() -> Self {
         return Self {
-            ttl: 30,
+            ttl: TimeDelta::minutes(30),
             id_generator: DefaultIdGenerator {},
-            salt_fn: default_rng_salt_fn,
-            token_fn: default_rng_token_fn,
-            hash_fn: default_hash_fn,
-            verify_token_fn: default_verify_token_fn,
+            salt_fn: Arc::new(default_rng_salt_fn),
+            token_fn: Arc::new(default_rng_token_fn),
+            hash_fn: Arc::new(default_hash_fn),
+            verify_token_fn: Arc::new(default_verify_token_fn),
+            pepper: None,
+            audit_sink: None,
+            clock: Arc::new(SystemClock),
         };
     }
 }
@@ -50,16 +66,83 @@ impl<T> AuthTokenManagerConfig<T>
 where
     T: IdGenerator + Copy,
 {
-    pub fn init<V: DbHarnessToken>(&self, harness: V) -> AuthTokenManager<T, V> {
-        AuthTokenManager {
+    /// Fails with `ErrorKind::InvalidConfig` when the access token ttl is not positive or too long.
+    pub fn init<V: DbHarnessToken>(&self, harness: V) -> Result<AuthTokenManager<T, V>, Error> {
+        clock::check_ttl(self.clock.as_ref(), self.ttl, "access token ttl")?;
+
+        Ok(AuthTokenManager {
             ttl: self.ttl,
             id_generator: self.id_generator,
             harness,
-            salt_fn: self.salt_fn,
-            token_fn: self.token_fn,
-            hash_fn: self.hash_fn,
-            verify_token_fn: self.verify_token_fn,
-        }
+            salt_fn: self.salt_fn.clone(),
+            token_fn: self.token_fn.clone(),
+            hash_fn: self.hash_fn.clone(),
+            verify_token_fn: self.verify_token_fn.clone(),
+            pepper: self.pepper.clone(),
+            audit_sink: self.audit_sink.clone(),
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// How long an access token is valid, 30 minutes by default.
+    pub fn with_access_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.ttl = ttl.into_time_delta();
+        self
+    }
+
+    /// Generates the random part of every token. The closure may capture state, e.g. a seeded generator.
+    pub fn with_token_fn(mut self, token_fn: GenerateFn) -> Self {
+        self.token_fn = token_fn;
+        self
+    }
+
+    pub fn with_salt_fn(mut self, salt_fn: GenerateFn) -> Self {
+        self.salt_fn = salt_fn;
+        self
+    }
+
+    /// Hashes refresh token secrets, called with the secret and a salt from the salt function.
+    pub fn with_hash_fn(mut self, hash_fn: HashFn) -> Self {
+        self.hash_fn = hash_fn;
+        self
+    }
+
+    /// Checks a refresh token secret against a hash made by the hash function.
+    pub fn with_verify_fn(mut self, verify_fn: VerifyFn) -> Self {
+        self.verify_token_fn = verify_fn;
+        self
+    }
+
+    pub fn with_id_gen<X: IdGenerator + Copy>(&self, id_generator: X) -> AuthTokenManagerConfig<X> {
+        return AuthTokenManagerConfig {
+            ttl: self.ttl,
+            id_generator,
+            salt_fn: self.salt_fn.clone(),
+            token_fn: self.token_fn.clone(),
+            hash_fn: self.hash_fn.clone(),
+            verify_token_fn: self.verify_token_fn.clone(),
+            pepper: self.pepper.clone(),
+            audit_sink: self.audit_sink.clone(),
+            clock: self.clock.clone(),
+        };
+    }
+
+    /// Keys refresh token hashes and access token digests with `pepper`.
+    pub fn with_pepper(mut self, pepper: Pepper) -> Self {
+        self.pepper = Some(Arc::new(pepper));
+        self
+    }
+
+    /// Records token revocations, see `AuditSink`.
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Issues and checks token expiry against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 }
 
@@ -68,12 +151,15 @@ where
     T: IdGenerator,
     V: DbHarnessToken,
 {
-    ttl: i64,
+    ttl: TimeDelta,
     id_generator: T,
-    salt_fn: fn() -> String,
-    token_fn: fn() -> String,
-    hash_fn: fn(&str, &str) -> Result<String, AuthTokenError>,
-    verify_token_fn: fn(&str, &str) -> Result<(), AuthTokenError>,
+    salt_fn: GenerateFn,
+    token_fn: GenerateFn,
+    hash_fn: HashFn,
+    verify_token_fn: VerifyFn,
+    pepper: Option<Arc<Pepper>>,
+    pub(crate) audit_sink: Option<Arc<dyn AuditSink>>,
+    clock: Arc<dyn Clock>,
     harness: V,
 }
 
@@ -86,7 +172,40 @@ where
         &self,
         user_id: i64,
         ttl: TokenTtl,
-    ) -> Result<(AuthToken, String), TokenManagerError> {
+        scopes: Scopes,
+    ) -> Result<(AuthToken, String), Error> {
+        self.next_subject_token(TokenSubject::User(user_id), ttl, scopes)
+    }
+
+    /// Clients only ever receive access tokens, there is no refresh step in the client credentials grant.
+    pub fn next_client_token(
+        &self,
+        client_id: i64,
+        scopes: Scopes,
+    ) -> Result<(AuthToken, String), Error> {
+        self.next_subject_token(TokenSubject::Client(client_id), TokenTtl::Access, scopes)
+    }
+
+    fn next_subject_token(
+        &self,
+        subject: TokenSubject,
+        ttl: TokenTtl,
+        scopes: Scopes,
+    ) -> Result<(AuthToken, String), Error> {
+        let (auth_token, secret) = self.build_token(subject, ttl, scopes)?;
+        match self.harness.insert(&auth_token) {
+            Ok(()) => return Ok((auth_token, secret)),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    // a new token that is not stored yet, for callers that write it as part of a `DbTransaction`.
+    pub(crate) fn build_token(
+        &self,
+        subject: TokenSubject,
+        ttl: TokenTtl,
+        scopes: Scopes,
+    ) -> Result<(AuthToken, String), Error> {
         let id = i64::from_be_bytes(self.id_generator.new_u64().to_be_bytes());
         let token = (self.token_fn)();
         let auth_token: AuthToken;
@@ -96,28 +215,33 @@ where
             // if we have a longer lived session token, we want to salt it.
             TokenTtl::Refresh(ttl) => {
                 let salt = (self.salt_fn)();
-                secret = (self.hash_fn)(&token, &salt)?;
                 let token_type = TokenType::Refresh {
-                    secret: secret.clone(),
+                    secret: pepper::hash_secret(
+                        self.pepper.as_deref(),
+                        &self.hash_fn,
+                        &token,
+                        &salt,
+                    )?,
                 };
 
-                auth_token = AuthToken::new(id, user_id, token_type, ttl)?;
+                // only the hash is stored, the caller receives the token itself.
+                secret = token;
+                auth_token =
+                    AuthToken::new(id, subject, token_type, ttl, self.clock.now(), scopes)?;
             }
 
-            // if we have a access token, we only want the RNG string to be stored.
+            // access tokens are checked on every request, a digest is cheap to verify and worthless if leaked.
             TokenTtl::Access => {
                 let token_type = TokenType::Access {
-                    token: token.clone(),
+                    token: pepper::digest_token(self.pepper.as_deref(), &token)?,
                 };
 
                 secret = token;
-                auth_token = AuthToken::new(id, user_id, token_type, self.ttl)?;
+                auth_token =
+                    AuthToken::new(id, subject, token_type, self.ttl, self.clock.now(), scopes)?;
             }
         }
-        match self.harness.insert(&auth_token) {
-            Ok(()) => return Ok((auth_token, secret)),
-            Err(err) => return Err(TokenManagerError::Harness(err)),
-        }
+        return Ok((auth_token, secret));
     }
 
     pub fn trusted_verify_refresh_token(
@@ -125,16 +249,16 @@ where
         token_id: i64,
         user_id: i64,
         token_str: &str,
-    ) -> Result<(), TokenManagerError> {
+    ) -> Result<(), Error> {
         match self.harness.read_refresh_token(token_id) {
             Ok(token_opt) => match token_opt {
                 Some(auth_token) => match self.verify_token(auth_token, user_id, token_str) {
                     Ok(()) => return Ok(()),
-                    Err(err) => return Err(err.into()),
+                    Err(err) => return Err(err),
                 },
-                None => return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized).into()),
+                None => return Err(Error::new(ErrorKind::NotAuthorized)),
             },
-            Err(err) => return Err(TokenManagerError::Harness(err)),
+            Err(err) => return Err(err.into()),
         }
     }
 
@@ -143,16 +267,88 @@ where
         token_id: i64,
         user_id: i64,
         token_str: &str,
-    ) -> Result<(), TokenManagerError> {
+    ) -> Result<(), Error> {
         match self.harness.read_access_token(token_id) {
             Ok(token_opt) => match token_opt {
                 Some(auth_token) => match self.verify_token(auth_token, user_id, token_str) {
                     Ok(()) => return Ok(()),
-                    Err(err) => return Err(err.into()),
+                    Err(err) => return Err(err),
                 },
-                None => return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized).into()),
+                None => return Err(Error::new(ErrorKind::NotAuthorized)),
             },
-            Err(err) => return Err(TokenManagerError::Harness(err)),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    /// Like `trusted_verify_access_token`, but the token must also have been issued with every scope in `required`.
+    pub fn trusted_verify_access_token_with_scope(
+        &self,
+        token_id: i64,
+        user_id: i64,
+        token_str: &str,
+        required: &[&str],
+    ) -> Result<(), Error> {
+        self.verify_access_token_scope(token_id, TokenSubject::User(user_id), token_str, required)
+    }
+
+    pub fn trusted_verify_client_access_token_with_scope(
+        &self,
+        token_id: i64,
+        client_id: i64,
+        token_str: &str,
+        required: &[&str],
+    ) -> Result<(), Error> {
+        self.verify_access_token_scope(
+            token_id,
+            TokenSubject::Client(client_id),
+            token_str,
+            required,
+        )
+    }
+
+    fn verify_access_token_scope(
+        &self,
+        token_id: i64,
+        subject: TokenSubject,
+        token_str: &str,
+        required: &[&str],
+    ) -> Result<(), Error> {
+        let auth_token = match self.harness.read_access_token(token_id) {
+            Ok(Some(auth_token)) => auth_token,
+            Ok(None) => return Err(Error::new(ErrorKind::NotAuthorized)),
+            Err(err) => return Err(err.into()),
+        };
+
+        // the scope check runs last so that an insufficient scope never hints at a valid token for an unauthenticated caller.
+        let scopes = auth_token.scopes().clone();
+        self.verify_subject_token(auth_token, subject, token_str)?;
+
+        if scopes.contains_all(required) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InsufficientScope))
+        }
+    }
+
+    pub fn trusted_verify_client_access_token(
+        &self,
+        token_id: i64,
+        client_id: i64,
+        token_str: &str,
+    ) -> Result<(), Error> {
+        match self.harness.read_access_token(token_id) {
+            Ok(token_opt) => match token_opt {
+                Some(auth_token) => match self.verify_subject_token(
+                    auth_token,
+                    TokenSubject::Client(client_id),
+                    token_str,
+                ) {
+                    Ok(()) => return Ok(()),
+                    Err(err) => return Err(err),
+                },
+                None => return Err(Error::new(ErrorKind::NotAuthorized)),
+            },
+            Err(err) => return Err(err.into()),
         }
     }
 
@@ -161,124 +357,132 @@ where
         auth_token: AuthToken,
         user_id: i64,
         token_str: &str,
-    ) -> Result<(), AuthTokenError> {
-        if user_id != auth_token.user_id {
-            return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized));
+    ) -> Result<(), Error> {
+        self.verify_subject_token(auth_token, TokenSubject::User(user_id), token_str)
+    }
+
+    /// a user token is never accepted in place of a client token (and vice versa), even when the ids collide.
+    pub fn verify_subject_token(
+        &self,
+        auth_token: AuthToken,
+        subject: TokenSubject,
+        token_str: &str,
+    ) -> Result<(), Error> {
+        if subject != auth_token.subject {
+            return Err(Error::new(ErrorKind::NotAuthorized));
         } else if auth_token.valid == false {
-            return Err(AuthTokenError::new(AuthTokenErrorKind::Invalid));
+            return Err(Error::new(ErrorKind::TokenInvalid));
         }
 
         match &auth_token.token_type {
             TokenType::Access { token } => {
-                if token != &token_str {
-                    return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized));
-                } else if auth_token.is_expired() {
-                    // try to clean up, if fails, we can clean up later with a cron job.
+                pepper::verify_token_digest(self.pepper.as_deref(), token_str, token)?;
+                if auth_token.is_expired(self.clock.now()) {
+                    // try to clean up, if it fails the `Sweeper` removes it later.
                     let _ = self.harness.delete_access_token(auth_token.id());
-                    return Err(AuthTokenError::new(AuthTokenErrorKind::Expired));
+                    return Err(Error::new(ErrorKind::TokenExpired));
                 } else {
                     return Ok(());
                 }
             }
             TokenType::Refresh { secret } => {
-                if auth_token.is_expired() {
-                    return Err(AuthTokenError::new(AuthTokenErrorKind::Expired));
+                if auth_token.is_expired(self.clock.now()) {
+                    return Err(Error::new(ErrorKind::TokenExpired));
                 } else {
-                    return (self.verify_token_fn)(&token_str, &secret).into();
+                    return pepper::verify_secret(
+                        self.pepper.as_deref(),
+                        &self.verify_token_fn,
+                        token_str,
+                        secret,
+                    );
                 }
             }
         }
     }
 
-    pub fn update_token(&self, token: &AuthToken) -> Result<(), Box<dyn error::Error>> {
-        self.harness.update(token)
+    pub fn ttl(&self) -> TimeDelta {
+        self.ttl
     }
 
-    pub fn get_access_token(&self, id: i64) -> Result<Option<AuthToken>, Box<dyn error::Error>> {
-        self.harness.read_access_token(id)
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
     }
 
-    // ewww....
-    pub fn invalidate_token(&self, mut token: AuthToken) -> Result<(), Box<dyn error::Error>> {
-        token.valid = false;
-        self.harness.update(&token)?;
-        return Ok(());
+    pub fn update_token(&self, token: &AuthToken) -> Result<(), Error> {
+        Ok(self.harness.update(token)?)
     }
 
-    pub fn get_refresh_token(&self, id: i64) -> Result<Option<AuthToken>, Box<dyn error::Error>> {
-        self.harness.read_refresh_token(id)
+    pub fn get_access_token(&self, id: i64) -> Result<Option<AuthToken>, Error> {
+        Ok(self.harness.read_access_token(id)?)
     }
 
-    pub fn delete_access_token(&self, id: i64) -> Result<(), Box<dyn error::Error>> {
-        self.harness.delete_access_token(id)
+    // ewww....
+    pub fn invalidate_token(&self, mut token: AuthToken) -> Result<(), Error> {
+        token.valid = false;
+        self.harness.update(&token)?;
+        return Ok(());
     }
 
-    pub fn delete_resfresh_token(&self, id: i64) -> Result<(), Box<dyn error::Error>> {
-        self.harness.delete_resfresh_token(id)
+    pub fn get_refresh_token(&self, id: i64) -> Result<Option<AuthToken>, Error> {
+        Ok(self.harness.read_refresh_token(id)?)
     }
-}
 
-#[derive(Debug)]
-pub enum TokenManagerError {
-    Harness(Box<dyn error::Error>),
-    AuthToken(AuthTokenError),
-}
-
-impl From<AuthTokenError> for TokenManagerError {
-    fn from(value: AuthTokenError) -> Self {
-        return Self::AuthToken(value);
+    pub fn delete_access_token(&self, id: i64) -> Result<(), Error> {
+        Ok(self.harness.delete_access_token(id)?)
     }
-}
 
-impl Display for TokenManagerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+    pub fn delete_resfresh_token(&self, id: i64) -> Result<(), Error> {
+        Ok(self.harness.delete_resfresh_token(id)?)
     }
 }
 
-impl error::Error for TokenManagerError {}
-
 #[derive(Clone, Debug)]
 pub struct AuthToken {
     id: i64,
-    user_id: i64,
+    subject: TokenSubject,
     token_type: TokenType,
     expires: DateTime<Utc>,
     valid: bool,
+    scopes: Scopes,
 }
 
 impl AuthToken {
-    /// AuthToken::new() should only be called from AuthTokenManager.
+    /// AuthToken::new() should only be called from AuthTokenManager. The token expires `ttl` after `now`.
     pub fn new(
         id: i64,
-        user_id: i64,
+        subject: TokenSubject,
         token_type: TokenType,
-        ttl: i64,
-    ) -> Result<Self, AuthTokenError> {
-        let expires = get_token_expiry(ttl)?;
+        ttl: TimeDelta,
+        now: DateTime<Utc>,
+        scopes: Scopes,
+    ) -> Result<Self, Error> {
+        let expires = get_token_expiry(now, ttl)?;
 
         return Ok(AuthToken {
             id,
-            user_id,
+            subject,
             token_type,
             expires,
             valid: true,
+            scopes,
         });
     }
 
     pub fn from_values(
         id: i64,
-        user_id: i64,
+        subject: TokenSubject,
         token_type: TokenType,
         expires: DateTime<Utc>,
         valid: bool,
+        scopes: Scopes,
     ) -> Self {
         return Self {
             id,
-            user_id,
+            subject,
             token_type,
             expires,
             valid,
+            scopes,
         };
     }
 
@@ -290,12 +494,29 @@ impl AuthToken {
         return self.id;
     }
 
-    pub fn user_id(&self) -> i64 {
-        return self.user_id;
+    pub fn subject(&self) -> TokenSubject {
+        return self.subject;
     }
 
-    pub fn is_expired(&self) -> bool {
-        return Utc::now() > self.expires;
+    /// None when the token was issued to a client.
+    pub fn user_id(&self) -> Option<i64> {
+        match self.subject {
+            TokenSubject::User(id) => Some(id),
+            TokenSubject::Client(_) => None,
+        }
+    }
+
+    /// None when the token was issued to a user.
+    pub fn client_id(&self) -> Option<i64> {
+        match self.subject {
+            TokenSubject::Client(id) => Some(id),
+            TokenSubject::User(_) => None,
+        }
+    }
+
+    /// `now` comes from the manager's `Clock`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        return now > self.expires;
     }
 
     pub fn expires(&self) -> DateTime<Utc> {
@@ -305,70 +526,30 @@ impl AuthToken {
     pub fn token_type(&self) -> TokenType {
         return self.token_type.clone();
     }
-}
 
-#[derive(Debug)]
-pub enum AuthTokenErrorKind {
-    Expired,
-    Invalid,
-    NotAuthorized,
-    DateTime,
-    Create,
-    InvalidFormat,
-}
-
-impl Display for AuthTokenErrorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Expired => write!(f, "Expired"),
-            Self::Invalid => write!(f, "Invalid"),
-            Self::NotAuthorized => write!(f, "Not Authorized"),
-            Self::DateTime => write!(f, "Error in token DateTime expiration check"),
-            Self::Create => write!(f, "Could not generate token."),
-            Self::InvalidFormat => write!(f, "Token stored in invalid format."),
-        }
+    pub fn scopes(&self) -> &Scopes {
+        return &self.scopes;
     }
 }
 
-#[derive(Debug)]
-pub struct AuthTokenError {
-    pub kind: AuthTokenErrorKind,
+/// Joins a token id and its secret into the single opaque string handed to clients, "<id>.<secret>".
+/// Anything that only receives a token string (bearer headers, introspection, revocation) can look the token up again.
+pub fn encode_token(id: i64, secret: &str) -> String {
+    format!("{}.{}", id, secret)
 }
 
-impl AuthTokenError {
-    pub fn new(kind: AuthTokenErrorKind) -> Self {
-        return Self { kind };
+/// Splits a string produced by `encode_token` back into the token id and secret.
+pub fn decode_token(token: &str) -> Option<(i64, &str)> {
+    let (id, secret) = token.split_once('.')?;
+    match id.parse::<i64>() {
+        Ok(id) if !secret.is_empty() => Some((id, secret)),
+        _ => None,
     }
 }
 
-impl Display for AuthTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "AuthTokenError: {}", self.kind)
-    }
-}
-
-impl error::Error for AuthTokenError {}
-
-fn get_token_expiry(ttl: i64) -> Result<DateTime<Utc>, AuthTokenError> {
-    let now = Utc::now();
-    let time_delta = TimeDelta::minutes(ttl);
-    let (new_time, rem) = now.time().overflowing_add_signed(time_delta);
-    let rem = chrono::Days::new(rem as u64);
-    let now_add_day = now.checked_add_days(rem);
-
-    match now_add_day {
-        Some(some_now_add_day) => {
-            let expires = some_now_add_day.with_time(new_time);
-            match expires {
-                LocalResult::Single(expires) => {
-                    return Ok(expires);
-                }
-                LocalResult::Ambiguous(expires, _) => Ok(expires),
-                LocalResult::None => {
-                    return Err(AuthTokenError::new(AuthTokenErrorKind::DateTime));
-                }
-            }
-        }
-        None => Err(AuthTokenError::new(AuthTokenErrorKind::DateTime)),
+fn get_token_expiry(now: DateTime<Utc>, ttl: TimeDelta) -> Result<DateTime<Utc>, Error> {
+    match now.checked_add_signed(ttl) {
+        Some(expires) => Ok(expires),
+        None => Err(Error::new(ErrorKind::DateTime)),
     }
 }
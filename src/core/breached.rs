@@ -0,0 +1,153 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use sha1::{Digest, Sha1};
+
+// The index splits the corpus into buckets by the first five hex digits of the digest, the same ranges the
+// online Pwned Passwords API serves. A lookup reads a single bucket, a few dozen kilobytes for the full corpus.
+const PREFIX_HEX: usize = 5;
+const BUCKETS: usize = 1 << (PREFIX_HEX * 4);
+const DIGEST_HEX: usize = 40;
+
+/// A local copy of a breached password corpus in the Pwned Passwords format: one SHA-1 hex digest per line,
+/// optionally followed by ":<count>", sorted by digest.
+pub struct BreachedPasswords {
+    file: Mutex<File>,
+    // BUCKETS + 1 offsets, bucket `n` spans `index[n]..index[n + 1]`. The last entry is the corpus length.
+    index: Vec<u64>,
+}
+
+impl BreachedPasswords {
+    /// Opens the corpus and its index, stored next to it as "<path>.idx". A missing or stale index is built
+    /// with one pass over the corpus and saved; if it can not be saved it is only kept in memory.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let index_path = Self::index_path(path);
+        let index = match Self::read_index(&index_path, len) {
+            Some(index) => index,
+            None => {
+                let index = Self::build_index(&file, len)?;
+                let _ = Self::write_index(&index_path, &index);
+                index
+            }
+        };
+
+        Ok(Self {
+            file: Mutex::new(file),
+            index,
+        })
+    }
+
+    pub fn contains(&self, pwd: &str) -> io::Result<bool> {
+        let digest: String = Sha1::digest(pwd.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        self.contains_digest(&digest)
+    }
+
+    /// Looks up a hex encoded SHA-1 digest, in either case.
+    pub fn contains_digest(&self, digest: &str) -> io::Result<bool> {
+        let bucket = match prefix(digest.as_bytes()) {
+            Some(bucket) if digest.len() == DIGEST_HEX => bucket,
+            _ => return Ok(false),
+        };
+
+        let (start, end) = (self.index[bucket], self.index[bucket + 1]);
+        let mut bytes = vec![0; (end - start) as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut bytes)?;
+        }
+
+        Ok(bytes.split(|byte| *byte == b'\n').any(|line| {
+            line.len() >= DIGEST_HEX && line[..DIGEST_HEX].eq_ignore_ascii_case(digest.as_bytes())
+        }))
+    }
+
+    fn index_path(path: &Path) -> PathBuf {
+        let mut index_path = path.as_os_str().to_owned();
+        index_path.push(".idx");
+        PathBuf::from(index_path)
+    }
+
+    fn build_index(file: &File, len: u64) -> io::Result<Vec<u64>> {
+        let mut index = vec![0; BUCKETS + 1];
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut line = Vec::new();
+        let mut offset = 0;
+        // the first bucket not yet assigned an offset.
+        let mut next = 0;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+
+            if let Some(bucket) = prefix(&line) {
+                if bucket + 1 < next {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "breached password corpus is not sorted",
+                    ));
+                }
+                while next <= bucket {
+                    index[next] = offset;
+                    next += 1;
+                }
+            }
+            offset += read as u64;
+        }
+
+        for entry in index.iter_mut().skip(next) {
+            *entry = len;
+        }
+        Ok(index)
+    }
+
+    fn read_index(path: &Path, len: u64) -> Option<Vec<u64>> {
+        let mut bytes = Vec::new();
+        File::open(path).ok()?.read_to_end(&mut bytes).ok()?;
+        if bytes.len() != (BUCKETS + 1) * 8 {
+            return None;
+        }
+
+        let index: Vec<u64> = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        // an index of a different (e.g. updated) corpus.
+        if index[BUCKETS] != len {
+            return None;
+        }
+        Some(index)
+    }
+
+    fn write_index(path: &Path, index: &[u64]) -> io::Result<()> {
+        let bytes: Vec<u8> = index
+            .iter()
+            .flat_map(|offset| offset.to_le_bytes())
+            .collect();
+        File::create(path)?.write_all(&bytes)
+    }
+}
+
+// the bucket of a line or digest, None for anything that does not start with a hex digest.
+fn prefix(line: &[u8]) -> Option<usize> {
+    if line.len() < PREFIX_HEX || !line[..PREFIX_HEX].iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let prefix = std::str::from_utf8(&line[..PREFIX_HEX]).ok()?;
+    usize::from_str_radix(prefix, 16).ok()
+}
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use crate::harness::{DbHarnessClient, DbHarnessToken};
+
+use super::{
+    audit::AuditSink,
+    auth_token::{AuthToken, AuthTokenManager, AuthTokenManagerConfig},
+    clock::{Clock, IntoTimeDelta},
+    default_hash_fn, default_rng_salt_fn, default_rng_token_fn, default_verify_token_fn,
+    id::{DefaultIdGenerator, IdGenerator},
+    pepper::{self, Pepper},
+    scope::Scopes,
+    Error, ErrorKind, GenerateFn, HashFn, VerifyFn,
+};
+
+// Machine clients authenticate with the OAuth2 client credentials grant. They are not users, they have no
+// password, no session and no refresh token. They trade their id and secret for a short lived access token.
+pub struct ClientManagerConfig<T>
+where
+    T: IdGenerator,
+{
+    id_generator: T,
+    token_manager_config: AuthTokenManagerConfig<T>,
+    salt_fn: GenerateFn,
+    secret_fn: GenerateFn,
+    hash_fn: HashFn,
+    verify_secret_fn: VerifyFn,
+    pepper: Option<Arc<Pepper>>,
+}
+
+impl Default for ClientManagerConfig<DefaultIdGenerator> {
+    fn default() -> Self {
+        Self {
+            id_generator: DefaultIdGenerator {},
+            token_manager_config: AuthTokenManagerConfig::default(),
+            salt_fn: Arc::new(default_rng_salt_fn),
+            secret_fn: Arc::new(default_rng_token_fn),
+            hash_fn: Arc::new(default_hash_fn),
+            verify_secret_fn: Arc::new(default_verify_token_fn),
+            pepper: None,
+        }
+    }
+}
+
+impl<T> ClientManagerConfig<T>
+where
+    T: IdGenerator + Copy,
+{
+    /// Fails when the token settings are rejected, see `AuthTokenManagerConfig::init`.
+    pub fn init<V: DbHarnessClient, X: DbHarnessToken>(
+        &self,
+        client_harness: V,
+        token_harness: X,
+    ) -> Result<ClientManager<T, V, X>, Error> {
+        Ok(ClientManager {
+            id_generator: self.id_generator,
+            token_manager: self.token_manager_config.init(token_harness)?,
+            harness: client_harness,
+            salt_fn: self.salt_fn.clone(),
+            secret_fn: self.secret_fn.clone(),
+            hash_fn: self.hash_fn.clone(),
+            verify_secret_fn: self.verify_secret_fn.clone(),
+            pepper: self.pepper.clone(),
+        })
+    }
+
+    /// Keys client secret hashes and the client access token digests with `pepper`, see `Pepper`.
+    pub fn with_pepper(mut self, pepper: Pepper) -> Self {
+        self.token_manager_config = self.token_manager_config.with_pepper(pepper.clone());
+        self.pepper = Some(Arc::new(pepper));
+        self
+    }
+
+    /// Records revocations of client tokens, see `AuditSink`.
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.token_manager_config = self.token_manager_config.with_audit_sink(sink);
+        self
+    }
+
+    /// How long a client access token is valid, 30 minutes by default.
+    pub fn with_access_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.token_manager_config = self.token_manager_config.with_access_ttl(ttl);
+        self
+    }
+
+    /// Issues and checks client token expiry against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.token_manager_config = self.token_manager_config.with_clock(clock);
+        self
+    }
+
+    /// Client and token ids both come from `id_generator`.
+    pub fn with_id_gen<X: IdGenerator + Copy>(&self, id_generator: X) -> ClientManagerConfig<X> {
+        ClientManagerConfig {
+            id_generator,
+            token_manager_config: self.token_manager_config.with_id_gen(id_generator),
+            salt_fn: self.salt_fn.clone(),
+            secret_fn: self.secret_fn.clone(),
+            hash_fn: self.hash_fn.clone(),
+            verify_secret_fn: self.verify_secret_fn.clone(),
+            pepper: self.pepper.clone(),
+        }
+    }
+}
+
+pub struct ClientManager<T, V, X>
+where
+    T: IdGenerator,
+    V: DbHarnessClient,
+    X: DbHarnessToken,
+{
+    id_generator: T,
+    token_manager: AuthTokenManager<T, X>,
+    harness: V,
+    salt_fn: GenerateFn,
+    secret_fn: GenerateFn,
+    hash_fn: HashFn,
+    verify_secret_fn: VerifyFn,
+    pepper: Option<Arc<Pepper>>,
+}
+
+impl<T, V, X> ClientManager<T, V, X>
+where
+    T: IdGenerator,
+    V: DbHarnessClient,
+    X: DbHarnessToken,
+{
+    /// Registers a new client. The returned secret is only available here, only its hash is stored.
+    pub fn create_client(&self, name: String, scopes: Scopes) -> Result<(Client, String), Error> {
+        let id = i64::from_be_bytes(self.id_generator.new_u64().to_be_bytes());
+        let client_secret = (self.secret_fn)();
+        let salt = (self.salt_fn)();
+        let secret =
+            pepper::hash_secret(self.pepper.as_deref(), &self.hash_fn, &client_secret, &salt)?;
+
+        let client = Client {
+            id,
+            name,
+            secret,
+            scopes,
+        };
+
+        self.harness.insert(&client)?;
+        Ok((client, client_secret))
+    }
+
+    /// The client credentials grant. When `requested` is None the client receives every scope it is allowed,
+    /// otherwise the request must be a subset of the client's scopes.
+    ///
+    /// Returns the access token, its secret and the scopes that were granted.
+    pub fn client_credentials(
+        &self,
+        client_id: i64,
+        client_secret: &str,
+        requested: Option<&Scopes>,
+    ) -> Result<(AuthToken, String, Scopes), Error> {
+        let client = self.authenticate(client_id, client_secret)?;
+
+        let granted = match requested {
+            Some(requested) => {
+                if !requested.is_subset(&client.scopes) {
+                    return Err(Error::new(ErrorKind::InvalidScope));
+                }
+                requested.clone()
+            }
+            None => client.scopes.clone(),
+        };
+
+        let (token, secret) = self
+            .token_manager
+            .next_client_token(client.id, granted.clone())?;
+        Ok((token, secret, granted))
+    }
+
+    pub fn verify_access_token(
+        &self,
+        token_id: i64,
+        client_id: i64,
+        client_token_atmpt: &str,
+    ) -> Result<(), Error> {
+        self.token_manager.trusted_verify_client_access_token(
+            token_id,
+            client_id,
+            client_token_atmpt,
+        )
+    }
+
+    pub fn verify_access_token_with_scope(
+        &self,
+        token_id: i64,
+        client_id: i64,
+        client_token_atmpt: &str,
+        required: &[&str],
+    ) -> Result<(), Error> {
+        self.token_manager
+            .trusted_verify_client_access_token_with_scope(
+                token_id,
+                client_id,
+                client_token_atmpt,
+                required,
+            )
+    }
+
+    /// Issues a new secret, the old secret stops working immediately. Access tokens already issued stay valid until they expire.
+    pub fn rotate_secret(&self, mut client: Client) -> Result<String, Error> {
+        let client_secret = (self.secret_fn)();
+        let salt = (self.salt_fn)();
+        client.secret =
+            pepper::hash_secret(self.pepper.as_deref(), &self.hash_fn, &client_secret, &salt)?;
+
+        self.harness.update(&client)?;
+        Ok(client_secret)
+    }
+
+    pub fn update_client(&self, client: &Client) -> Result<(), Error> {
+        Ok(self.harness.update(client)?)
+    }
+
+    pub fn get_client(&self, id: i64) -> Result<Option<Client>, Error> {
+        Ok(self.harness.read(id)?)
+    }
+
+    /// Deletes the client, the access tokens issued to it stop working with it.
+    pub fn delete_client(&self, id: i64) -> Result<(), Error> {
+        Ok(self.harness.delete(id)?)
+    }
+
+    fn authenticate(&self, client_id: i64, client_secret: &str) -> Result<Client, Error> {
+        let client = match self.harness.read(client_id)? {
+            Some(client) => client,
+            None => return Err(Error::new(ErrorKind::InvalidClient)),
+        };
+
+        match pepper::verify_secret(
+            self.pepper.as_deref(),
+            &self.verify_secret_fn,
+            client_secret,
+            &client.secret,
+        ) {
+            Ok(()) => Ok(client),
+            Err(err) => match err.kind {
+                ErrorKind::NotAuthorized => Err(Error::new(ErrorKind::InvalidClient)),
+                _ => Err(err),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    id: i64,
+    name: String,
+    secret: String,
+    scopes: Scopes,
+}
+
+impl Client {
+    pub fn from_values(id: i64, name: String, secret: String, scopes: Scopes) -> Self {
+        Self {
+            id,
+            name,
+            secret,
+            scopes,
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    pub fn scopes(&self) -> &Scopes {
+        &self.scopes
+    }
+
+    pub fn set_scopes(&mut self, scopes: Scopes) {
+        self.scopes = scopes;
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use super::{Error, ErrorKind};
+
+/// Where the managers read the current time from, set with `with_clock` on `AuthTokenManagerConfig`,
+/// `SessionManagerConfig` and `UserManagerConfig`. Token expiry and password age are checked against it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The wall clock, used unless another clock is configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests. Clones share the same time, so a test can keep one and hand
+/// another to the managers.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: TimeDelta) {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+    }
+}
+
+impl Default for MockClock {
+    // starts at the current time, so stored timestamps still look plausible.
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A length of time taken by the config builders, a `chrono::Duration` or a `std::time::Duration`.
+pub trait IntoTimeDelta {
+    fn into_time_delta(self) -> TimeDelta;
+}
+
+impl IntoTimeDelta for TimeDelta {
+    fn into_time_delta(self) -> TimeDelta {
+        self
+    }
+}
+
+impl IntoTimeDelta for std::time::Duration {
+    // too long to represent, `init` rejects it.
+    fn into_time_delta(self) -> TimeDelta {
+        TimeDelta::from_std(self).unwrap_or(TimeDelta::max_value())
+    }
+}
+
+// a ttl has to be positive and small enough that an expiry can be computed from `clock`.
+pub(crate) fn check_ttl(
+    clock: &dyn Clock,
+    ttl: TimeDelta,
+    setting: &'static str,
+) -> Result<(), Error> {
+    match ttl > TimeDelta::zero() && clock.now().checked_add_signed(ttl).is_some() {
+        true => Ok(()),
+        false => Err(Error::new(ErrorKind::InvalidConfig(setting))),
+    }
+}
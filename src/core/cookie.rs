@@ -0,0 +1,285 @@
+use std::fmt::Display;
+
+use crate::harness::{DbHarnessSession, DbHarnessToken};
+
+use super::{
+    auth_token::encode_token,
+    constant_time_eq,
+    id::IdGenerator,
+    session::{Session, SessionManager},
+    sha256_hex, Error, ErrorKind,
+};
+
+// Cookie mode keeps the tokens out of reach of JavaScript. The access and refresh tokens are set as HttpOnly cookies,
+// the CSRF token is set as a readable cookie and must be echoed back by the client, usually in a header.
+//
+// The CSRF token is derived from the session and its current access token, nothing is stored. A cross site request
+// carries the cookies but can not read them, so it can not produce the matching token. It changes with every new
+// access token, hand the client the new one together with the new cookies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Strict => write!(f, "Strict"),
+            Self::Lax => write!(f, "Lax"),
+            Self::None => write!(f, "None"),
+        }
+    }
+}
+
+/// Everything needed to set a cookie, framework agnostic. `to_header_value` renders a `Set-Cookie` header value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CookieDescriptor {
+    pub name: String,
+    pub value: String,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub path: String,
+    // seconds.
+    pub max_age: i64,
+}
+
+impl CookieDescriptor {
+    pub fn to_header_value(&self) -> String {
+        let mut header = format!(
+            "{}={}; Path={}; Max-Age={}; SameSite={}",
+            self.name, self.value, self.path, self.max_age, self.same_site
+        );
+        if self.http_only {
+            header += "; HttpOnly";
+        }
+        if self.secure {
+            header += "; Secure";
+        }
+        header
+    }
+
+    /// The same cookie, emptied and expired. Setting it makes the browser drop the cookie.
+    pub fn removal(&self) -> Self {
+        Self {
+            value: String::new(),
+            max_age: 0,
+            ..self.clone()
+        }
+    }
+}
+
+pub struct CookieConfig {
+    access_name: String,
+    refresh_name: String,
+    csrf_name: String,
+    path: String,
+    refresh_path: String,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            access_name: String::from("sheesh_access"),
+            refresh_name: String::from("sheesh_refresh"),
+            csrf_name: String::from("sheesh_csrf"),
+            path: String::from("/"),
+            refresh_path: String::from("/"),
+            secure: true,
+            same_site: SameSite::Strict,
+        }
+    }
+}
+
+impl CookieConfig {
+    pub fn with_access_name(mut self, name: &str) -> Self {
+        self.access_name = name.to_string();
+        self
+    }
+
+    pub fn with_refresh_name(mut self, name: &str) -> Self {
+        self.refresh_name = name.to_string();
+        self
+    }
+
+    pub fn with_csrf_name(mut self, name: &str) -> Self {
+        self.csrf_name = name.to_string();
+        self
+    }
+
+    /// Path of the access and CSRF cookies.
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// The refresh token is only needed by the refresh endpoint, scoping it there keeps it out of every other request.
+    pub fn with_refresh_path(mut self, path: &str) -> Self {
+        self.refresh_path = path.to_string();
+        self
+    }
+
+    /// Only turn this off for local development over plain http.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn access_name(&self) -> &str {
+        &self.access_name
+    }
+
+    pub fn refresh_name(&self) -> &str {
+        &self.refresh_name
+    }
+
+    pub fn csrf_name(&self) -> &str {
+        &self.csrf_name
+    }
+
+    fn cookie(
+        &self,
+        name: &str,
+        value: String,
+        path: &str,
+        http_only: bool,
+        max_age: i64,
+    ) -> CookieDescriptor {
+        CookieDescriptor {
+            name: name.to_string(),
+            value,
+            http_only,
+            secure: self.secure,
+            same_site: self.same_site,
+            path: path.to_string(),
+            max_age,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionCookies {
+    pub access: CookieDescriptor,
+    pub refresh: CookieDescriptor,
+    pub csrf: CookieDescriptor,
+}
+
+impl SessionCookies {
+    /// The value the client has to send back with every state changing request.
+    pub fn csrf_token(&self) -> &str {
+        &self.csrf.value
+    }
+
+    pub fn to_header_values(&self) -> [String; 3] {
+        [
+            self.access.to_header_value(),
+            self.refresh.to_header_value(),
+            self.csrf.to_header_value(),
+        ]
+    }
+}
+
+impl<T, V, X> SessionManager<T, V, X>
+where
+    T: IdGenerator,
+    V: DbHarnessSession,
+    X: DbHarnessToken,
+{
+    /// Cookie mode. Call after `new_session` or `create_new_refresh_token` with the secrets they returned.
+    /// Max-Age follows the access token and session ttls.
+    pub fn session_cookies(
+        &self,
+        config: &CookieConfig,
+        session: &Session,
+        refresh_secret: &str,
+        access_secret: &str,
+    ) -> Result<SessionCookies, Error> {
+        let (refresh_token, access_token) = match (session.refresh_token(), session.access_token())
+        {
+            (Some(refresh_token), Some(access_token)) => (refresh_token, access_token),
+            _ => return Err(Error::new(ErrorKind::NotAuthorized)),
+        };
+
+        let access_token = encode_token(access_token, access_secret);
+        let csrf_token = self.csrf_token(session, &access_token);
+        let access_max_age = self.access_ttl().num_seconds();
+
+        Ok(SessionCookies {
+            access: config.cookie(
+                &config.access_name,
+                access_token,
+                &config.path,
+                true,
+                access_max_age,
+            ),
+            refresh: config.cookie(
+                &config.refresh_name,
+                encode_token(refresh_token, refresh_secret),
+                &config.refresh_path,
+                true,
+                self.refresh_ttl().num_seconds(),
+            ),
+            // readable by design, the client echoes it back.
+            csrf: config.cookie(
+                &config.csrf_name,
+                csrf_token,
+                &config.path,
+                false,
+                access_max_age,
+            ),
+        })
+    }
+
+    /// Expired cookies for every cookie of the session, to be set on logout.
+    pub fn removal_cookies(&self, config: &CookieConfig) -> SessionCookies {
+        SessionCookies {
+            access: config.cookie(&config.access_name, String::new(), &config.path, true, 0),
+            refresh: config.cookie(
+                &config.refresh_name,
+                String::new(),
+                &config.refresh_path,
+                true,
+                0,
+            ),
+            csrf: config.cookie(&config.csrf_name, String::new(), &config.path, false, 0),
+        }
+    }
+
+    /// The CSRF token bound to `session` and its current "<id>.<secret>" access token.
+    pub fn csrf_token(&self, session: &Session, access_token: &str) -> String {
+        sha256_hex(&format!("csrf:{}:{}", session.id(), access_token))
+    }
+
+    pub fn verify_csrf_token(
+        &self,
+        session: &Session,
+        access_token: &str,
+        csrf_token_atmpt: &str,
+    ) -> Result<(), Error> {
+        if constant_time_eq(&self.csrf_token(session, access_token), csrf_token_atmpt) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::NotAuthorized))
+        }
+    }
+
+    /// `authenticate` for cookie mode, the access token comes from its cookie and the CSRF token from the request.
+    pub fn authenticate_with_csrf(
+        &self,
+        access_token: &str,
+        csrf_token_atmpt: &str,
+    ) -> Result<Session, Error> {
+        let session = self.authenticate(access_token)?;
+        self.verify_csrf_token(&session, access_token, csrf_token_atmpt)?;
+        Ok(session)
+    }
+}
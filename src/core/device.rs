@@ -0,0 +1,437 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use scrypt::password_hash::rand_core::{OsRng, RngCore};
+
+use crate::harness::{DbHarnessDevice, DbHarnessSession, DbHarnessToken, DbHarnessUser};
+
+use super::{
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
+    default_rng_token_fn,
+    id::{DefaultIdGenerator, IdGenerator},
+    session::{Session, SessionManager},
+    sha256_hex,
+    user::{PrivateUserMeta, PublicUserMeta, User, UserManager},
+    Error, ErrorKind, GenerateFn,
+};
+
+// RFC 8628 section 6.1: consonants only, no vowels to avoid spelling words and no characters that are easily confused.
+pub const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+// RFC 8628 section 3.5: every slow_down response increases the polling interval by 5 seconds.
+const SLOW_DOWN_INCREMENT: i64 = 5;
+
+pub struct DeviceManagerConfig<T>
+where
+    T: IdGenerator,
+{
+    id_generator: T,
+    verification_uri: String,
+    ttl: TimeDelta,
+    // seconds
+    interval: i64,
+    user_code_len: usize,
+    device_code_fn: GenerateFn,
+    clock: Arc<dyn Clock>,
+}
+
+impl DeviceManagerConfig<DefaultIdGenerator> {
+    pub fn new(verification_uri: &str) -> Self {
+        Self {
+            id_generator: DefaultIdGenerator {},
+            verification_uri: verification_uri.to_owned(),
+            ttl: TimeDelta::minutes(10),
+            interval: 5,
+            user_code_len: 8,
+            device_code_fn: Arc::new(default_rng_token_fn),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl<T> DeviceManagerConfig<T>
+where
+    T: IdGenerator + Copy,
+{
+    /// How long the device and user codes stay valid, 10 minutes by default.
+    pub fn with_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.ttl = ttl.into_time_delta();
+        self
+    }
+
+    pub fn with_interval(mut self, interval: i64) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_user_code_len(mut self, user_code_len: usize) -> Self {
+        self.user_code_len = user_code_len;
+        self
+    }
+
+    /// Dates authorizations and polls with `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Fails with `ErrorKind::InvalidConfig` when the ttl is not positive or too long, or when the polling interval
+    /// or the user code length is not positive.
+    pub fn init<V: DbHarnessDevice>(&self, harness: V) -> Result<DeviceManager<T, V>, Error> {
+        clock::check_ttl(self.clock.as_ref(), self.ttl, "device ttl")?;
+        if self.interval <= 0 {
+            return Err(Error::new(ErrorKind::InvalidConfig(
+                "device polling interval not positive",
+            )));
+        } else if self.user_code_len == 0 {
+            return Err(Error::new(ErrorKind::InvalidConfig(
+                "device user code length not positive",
+            )));
+        }
+        Ok(DeviceManager {
+            id_generator: self.id_generator,
+            verification_uri: self.verification_uri.clone(),
+            ttl: self.ttl,
+            interval: self.interval,
+            user_code_len: self.user_code_len,
+            device_code_fn: self.device_code_fn.clone(),
+            clock: self.clock.clone(),
+            harness,
+        })
+    }
+}
+
+pub struct DeviceManager<T, V>
+where
+    T: IdGenerator,
+    V: DbHarnessDevice,
+{
+    id_generator: T,
+    verification_uri: String,
+    ttl: TimeDelta,
+    interval: i64,
+    user_code_len: usize,
+    device_code_fn: GenerateFn,
+    clock: Arc<dyn Clock>,
+    harness: V,
+}
+
+impl<T, V> DeviceManager<T, V>
+where
+    T: IdGenerator,
+    V: DbHarnessDevice,
+{
+    /// The device authorization request. Hand the response to the device, it shows the user code and
+    /// verification uri to the user and starts polling with the device code.
+    pub fn start(&self) -> Result<DeviceAuthorizationResponse, Error> {
+        let id = i64::from_be_bytes(self.id_generator.new_u64().to_be_bytes());
+        let device_code = (self.device_code_fn)();
+        let user_code = new_user_code(self.user_code_len);
+        let expires = match self.clock.now().checked_add_signed(self.ttl) {
+            Some(expires) => expires,
+            None => return Err(Error::new(ErrorKind::DateTime)),
+        };
+
+        let authorization = DeviceAuthorization {
+            id,
+            device_code: hash_device_code(&device_code),
+            user_code: user_code.clone(),
+            expires,
+            interval: self.interval,
+            last_polled: None,
+            status: DeviceAuthorizationStatus::Pending,
+        };
+        self.harness.insert(&authorization)?;
+
+        let display_code = format_user_code(&user_code);
+        Ok(DeviceAuthorizationResponse {
+            verification_uri_complete: format!(
+                "{}?user_code={}",
+                self.verification_uri, display_code
+            ),
+            verification_uri: self.verification_uri.clone(),
+            device_code,
+            user_code: display_code,
+            expires_in: self.ttl.num_seconds(),
+            interval: self.interval,
+        })
+    }
+
+    /// Looks up a pending authorization by the code the user typed in. Dashes, spaces and casing are ignored.
+    pub fn get_by_user_code(&self, user_code: &str) -> Result<DeviceAuthorization, Error> {
+        match self
+            .harness
+            .read_by_user_code(&normalize_user_code(user_code))?
+        {
+            Some(authorization) if authorization.is_expired(self.clock.now()) => {
+                Err(Error::new(ErrorKind::TokenExpired))
+            }
+            Some(authorization) => Ok(authorization),
+            None => Err(Error::new(ErrorKind::InvalidUserCode)),
+        }
+    }
+
+    /// Binds the user to the device. The caller is responsible for the user being logged in,
+    /// i.e. their access token was verified through the `SessionManager` before calling this.
+    pub fn approve<Pu, Pr>(&self, user: &User<Pu, Pr>, user_code: &str) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        if user.check_status().is_err() {
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+
+        self.decide(user_code, DeviceAuthorizationStatus::Approved(user.id()))
+    }
+
+    pub fn deny(&self, user_code: &str) -> Result<(), Error> {
+        self.decide(user_code, DeviceAuthorizationStatus::Denied)
+    }
+
+    // a code can only be decided on once, of two concurrent decisions only the first is stored.
+    fn decide(&self, user_code: &str, status: DeviceAuthorizationStatus) -> Result<(), Error> {
+        let mut authorization = self.get_by_user_code(user_code)?;
+        authorization.status = status;
+        match self.harness.decide(&authorization)? {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::InvalidUserCode)),
+        }
+    }
+
+    /// The device access token request. Returns the same tuple as `UserManager::login` once the user approved the code.
+    ///
+    /// Until then the error kind tells the device what to do next, mirroring the RFC 8628 error codes:
+    /// keep polling on `AuthorizationPending`, back off on `SlowDown`, and stop on anything else. A user that may no
+    /// longer log in after approving, e.g. banned, deactivated or due a password change, gets `AccessDenied`.
+    pub fn poll<Ui, Uh, Si, Sh, Th>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        session_manager: &SessionManager<Si, Sh, Th>,
+        device_code: &str,
+    ) -> Result<(Session, String, String), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+        Si: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        let mut authorization = match self
+            .harness
+            .read_by_device_code(&hash_device_code(device_code))?
+        {
+            Some(authorization) => authorization,
+            None => return Err(Error::new(ErrorKind::InvalidDeviceCode)),
+        };
+
+        let now = self.clock.now();
+        if authorization.is_expired(now) {
+            // try to clean up, the record is useless now.
+            let _ = self.harness.delete(authorization.id);
+            return Err(Error::new(ErrorKind::TokenExpired));
+        }
+
+        let too_fast = match authorization.last_polled {
+            Some(last_polled) => now < last_polled + TimeDelta::seconds(authorization.interval),
+            None => false,
+        };
+        authorization.last_polled = Some(now);
+
+        if too_fast {
+            authorization.interval += SLOW_DOWN_INCREMENT;
+            self.harness.record_poll(&authorization)?;
+            return Err(Error::new(ErrorKind::SlowDown));
+        }
+
+        match authorization.status {
+            DeviceAuthorizationStatus::Pending => {
+                self.harness.record_poll(&authorization)?;
+                Err(Error::new(ErrorKind::AuthorizationPending))
+            }
+            DeviceAuthorizationStatus::Denied => {
+                self.harness.delete(authorization.id)?;
+                Err(Error::new(ErrorKind::AccessDenied))
+            }
+            DeviceAuthorizationStatus::Approved(user_id) => {
+                self.redeem(user_manager, session_manager, authorization.id, user_id)
+            }
+        }
+    }
+
+    fn redeem<Ui, Uh, Si, Sh, Th>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        session_manager: &SessionManager<Si, Sh, Th>,
+        id: i64,
+        user_id: i64,
+    ) -> Result<(Session, String, String), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+        Si: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        // the account may have changed since the user approved the code, the device can not act on the reason.
+        let user: Option<User<(), ()>> = user_manager.get_user(&user_id)?;
+        let refused = match user {
+            Some(user) => match user_manager.check_login(&user) {
+                Ok(()) => false,
+                Err(err) => match err.kind {
+                    ErrorKind::Banned
+                    | ErrorKind::PendingVerification
+                    | ErrorKind::Suspended
+                    | ErrorKind::Deactivated
+                    | ErrorKind::PasswordChangeRequired(_)
+                    | ErrorKind::Vetoed(_) => true,
+                    _ => return Err(err),
+                },
+            },
+            None => true,
+        };
+        if refused {
+            self.harness.delete(id)?;
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+
+        // the session comes first, if it can not be created the approval is still there for the next poll.
+        let (session, refresh, access) = session_manager.new_session(user_id)?;
+        // the device code is single use, when a concurrent poll removed it first this session is dropped again.
+        match self.harness.delete(id) {
+            Ok(true) => {}
+            Ok(false) => {
+                let _ = session_manager.invalidate_session(session);
+                return Err(Error::new(ErrorKind::InvalidDeviceCode));
+            }
+            Err(err) => {
+                let _ = session_manager.invalidate_session(session);
+                return Err(err.into());
+            }
+        }
+        user_manager.logged_in(&session);
+        Ok((session, refresh, access))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceAuthorizationStatus {
+    Pending,
+    Approved(i64),
+    Denied,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceAuthorization {
+    id: i64,
+    // sha256 of the device code, the device code itself is never stored.
+    device_code: String,
+    user_code: String,
+    expires: DateTime<Utc>,
+    interval: i64,
+    last_polled: Option<DateTime<Utc>>,
+    status: DeviceAuthorizationStatus,
+}
+
+impl DeviceAuthorization {
+    pub fn from_values(
+        id: i64,
+        device_code: String,
+        user_code: String,
+        expires: DateTime<Utc>,
+        interval: i64,
+        last_polled: Option<DateTime<Utc>>,
+        status: DeviceAuthorizationStatus,
+    ) -> Self {
+        Self {
+            id,
+            device_code,
+            user_code,
+            expires,
+            interval,
+            last_polled,
+            status,
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn device_code(&self) -> &str {
+        &self.device_code
+    }
+
+    pub fn user_code(&self) -> &str {
+        &self.user_code
+    }
+
+    pub fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+
+    /// `now` comes from the manager's `Clock`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now > self.expires
+    }
+
+    pub fn interval(&self) -> i64 {
+        self.interval
+    }
+
+    pub fn last_polled(&self) -> Option<DateTime<Utc>> {
+        self.last_polled
+    }
+
+    pub fn status(&self) -> DeviceAuthorizationStatus {
+        self.status
+    }
+}
+
+/// What the device receives from the device authorization request, field names follow RFC 8628 section 3.2.
+#[derive(Clone, Debug)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    // seconds
+    pub expires_in: i64,
+    // seconds
+    pub interval: i64,
+}
+
+fn new_user_code(len: usize) -> String {
+    let mut code = String::with_capacity(len);
+    let bound = (u8::MAX as usize / USER_CODE_ALPHABET.len()) * USER_CODE_ALPHABET.len();
+
+    while code.len() < len {
+        let mut byte = [0u8; 1];
+        OsRng.fill_bytes(&mut byte);
+        // reject the tail of the byte range so every character is equally likely.
+        if (byte[0] as usize) < bound {
+            code.push(USER_CODE_ALPHABET[byte[0] as usize % USER_CODE_ALPHABET.len()] as char);
+        }
+    }
+
+    code
+}
+
+// users read the code off a tv, let them type it however they like.
+fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// "BCDFGHJK" -> "BCDF-GHJK"
+fn format_user_code(user_code: &str) -> String {
+    let mid = user_code.len() / 2;
+    format!("{}-{}", &user_code[..mid], &user_code[mid..])
+}
+
+fn hash_device_code(device_code: &str) -> String {
+    sha256_hex(device_code)
+}
@@ -0,0 +1,309 @@
+use std::{error, fmt::Display};
+
+use crate::harness::{HarnessError, HarnessErrorKind};
+
+use super::{
+    listener::Veto,
+    password_policy::{PasswordPolicyError, PasswordPolicyErrorKind},
+    user::PasswordChangeReason,
+};
+
+/// Every failure the managers report.
+///
+/// `code` is a stable, machine readable identifier, `public_message` is safe to show to the client and `status`
+/// maps the error to an HTTP status code. `Display` describes the underlying failure and is meant for logs, the
+/// database, delivery or policy error behind it is available through `source`.
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    // wrong password or secret, unknown or forged token, deliberately indistinguishable.
+    NotAuthorized,
+    TokenExpired,
+    // the token was invalidated, or a one time token was already used.
+    TokenInvalid,
+    // the token is valid, but was not issued for what it is being used for.
+    InsufficientScope,
+    // more scopes were asked for than were granted.
+    InvalidScope,
+    AlreadyLoggedOut,
+    UserNotFound,
+    Banned,
+    // the password was correct, but the account is not active.
+    PendingVerification,
+    Suspended,
+    Deactivated,
+    // the password was rejected by the `PasswordPolicy`.
+    PasswordPolicy(PasswordPolicyError),
+    // the password was correct, but has to be changed first, see `UserManager::login_with_password_change`.
+    PasswordChangeRequired(PasswordChangeReason),
+    // an `AuthEventListener` refused the action.
+    Vetoed(Veto),
+    // a one time code was guessed wrong too often and is gone.
+    TooManyAttempts,
+    MissingEmail,
+    // the address belongs to another account.
+    EmailTaken,
+    // unknown client id or wrong secret, deliberately indistinguishable.
+    InvalidClient,
+    AuthorizationPending,
+    SlowDown,
+    AccessDenied,
+    InvalidDeviceCode,
+    InvalidUserCode,
+    // a stored hash could not be parsed.
+    InvalidFormat,
+    // the secret was stored under a pepper key that is not configured.
+    UnknownPepper,
+    // a token, hash or signing key could not be generated.
+    Create,
+    DateTime,
+    // the signing key is not a valid RSA private key.
+    InvalidKey,
+    MissingSigningKey,
+    // a manager config was rejected at `init`, says which setting.
+    InvalidConfig(&'static str),
+    Serialize,
+    // a `TokenDelivery` could not send the token.
+    Delivery(Box<dyn error::Error + Send + Sync>),
+    Harness(HarnessError),
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        return Self { kind };
+    }
+
+    /// Stable identifier of the error, suitable for clients to branch on.
+    pub fn code(&self) -> &'static str {
+        match &self.kind {
+            ErrorKind::NotAuthorized => "invalid_credentials",
+            ErrorKind::TokenExpired => "token_expired",
+            ErrorKind::TokenInvalid => "token_invalid",
+            ErrorKind::InsufficientScope => "insufficient_scope",
+            ErrorKind::InvalidScope => "invalid_scope",
+            ErrorKind::AlreadyLoggedOut => "already_logged_out",
+            ErrorKind::UserNotFound => "user_not_found",
+            ErrorKind::Banned => "account_locked",
+            ErrorKind::PendingVerification => "account_pending_verification",
+            ErrorKind::Suspended => "account_suspended",
+            ErrorKind::Deactivated => "account_deactivated",
+            ErrorKind::PasswordPolicy(err) => match err.kind {
+                PasswordPolicyErrorKind::Violations(_) => "password_policy",
+                PasswordPolicyErrorKind::Corpus(_) => "internal_error",
+            },
+            ErrorKind::PasswordChangeRequired(PasswordChangeReason::Required) => {
+                "password_change_required"
+            }
+            ErrorKind::PasswordChangeRequired(PasswordChangeReason::Expired) => "password_expired",
+            ErrorKind::Vetoed(_) => "forbidden",
+            ErrorKind::TooManyAttempts => "too_many_attempts",
+            ErrorKind::MissingEmail => "missing_email",
+            ErrorKind::EmailTaken => "email_taken",
+            ErrorKind::InvalidClient => "invalid_client",
+            ErrorKind::AuthorizationPending => "authorization_pending",
+            ErrorKind::SlowDown => "slow_down",
+            ErrorKind::AccessDenied => "access_denied",
+            ErrorKind::InvalidDeviceCode => "invalid_device_code",
+            ErrorKind::InvalidUserCode => "invalid_user_code",
+            ErrorKind::Delivery(_) => "delivery_failed",
+            ErrorKind::Harness(err) => match err.kind {
+                HarnessErrorKind::NotFound => "not_found",
+                HarnessErrorKind::Conflict => "conflict",
+                HarnessErrorKind::Unavailable => "unavailable",
+                HarnessErrorKind::Corrupt | HarnessErrorKind::Other => "internal_error",
+            },
+            ErrorKind::InvalidFormat
+            | ErrorKind::UnknownPepper
+            | ErrorKind::Create
+            | ErrorKind::DateTime
+            | ErrorKind::InvalidKey
+            | ErrorKind::MissingSigningKey
+            | ErrorKind::InvalidConfig(_)
+            | ErrorKind::Serialize => "internal_error",
+        }
+    }
+
+    /// HTTP status code for the error.
+    pub fn status(&self) -> u16 {
+        match &self.kind {
+            ErrorKind::NotAuthorized
+            | ErrorKind::TokenExpired
+            | ErrorKind::TokenInvalid
+            | ErrorKind::InvalidClient => 401,
+            ErrorKind::InsufficientScope
+            | ErrorKind::Banned
+            | ErrorKind::PendingVerification
+            | ErrorKind::Suspended
+            | ErrorKind::Deactivated
+            | ErrorKind::PasswordChangeRequired(_)
+            | ErrorKind::Vetoed(_) => 403,
+            ErrorKind::InvalidScope
+            | ErrorKind::AuthorizationPending
+            | ErrorKind::SlowDown
+            | ErrorKind::AccessDenied
+            | ErrorKind::InvalidDeviceCode
+            | ErrorKind::InvalidUserCode => 400,
+            ErrorKind::UserNotFound => 404,
+            ErrorKind::AlreadyLoggedOut | ErrorKind::EmailTaken => 409,
+            ErrorKind::PasswordPolicy(err) => match err.kind {
+                PasswordPolicyErrorKind::Violations(_) => 422,
+                PasswordPolicyErrorKind::Corpus(_) => 500,
+            },
+            ErrorKind::MissingEmail => 422,
+            ErrorKind::TooManyAttempts => 429,
+            ErrorKind::Delivery(_) => 502,
+            ErrorKind::Harness(err) => match err.kind {
+                HarnessErrorKind::NotFound => 404,
+                HarnessErrorKind::Conflict => 409,
+                HarnessErrorKind::Unavailable => 503,
+                HarnessErrorKind::Corrupt | HarnessErrorKind::Other => 500,
+            },
+            ErrorKind::InvalidFormat
+            | ErrorKind::UnknownPepper
+            | ErrorKind::Create
+            | ErrorKind::DateTime
+            | ErrorKind::InvalidKey
+            | ErrorKind::MissingSigningKey
+            | ErrorKind::InvalidConfig(_)
+            | ErrorKind::Serialize => 500,
+        }
+    }
+
+    /// A message that is safe to show to the client. Internal failures and veto reasons are left out, only the
+    /// password policy violations are passed on so the user can pick a better password.
+    pub fn public_message(&self) -> String {
+        let message = match &self.kind {
+            ErrorKind::NotAuthorized => "Not Authorized",
+            ErrorKind::TokenExpired => "Token expired",
+            ErrorKind::TokenInvalid => "Token invalid",
+            ErrorKind::InsufficientScope => "Insufficient scope",
+            ErrorKind::InvalidScope => "Invalid scope",
+            ErrorKind::AlreadyLoggedOut => "Already logged out",
+            ErrorKind::UserNotFound => "User not found",
+            ErrorKind::Banned => "Account locked",
+            ErrorKind::PendingVerification => "Account pending verification",
+            ErrorKind::Suspended => "Account suspended",
+            ErrorKind::Deactivated => "Account deactivated",
+            ErrorKind::PasswordPolicy(err) => match &err.kind {
+                PasswordPolicyErrorKind::Violations(_) => return err.kind.to_string(),
+                PasswordPolicyErrorKind::Corpus(_) => "Internal Server Error",
+            },
+            ErrorKind::PasswordChangeRequired(PasswordChangeReason::Required) => {
+                "Password change required"
+            }
+            ErrorKind::PasswordChangeRequired(PasswordChangeReason::Expired) => "Password expired",
+            ErrorKind::Vetoed(_) => "Forbidden",
+            ErrorKind::TooManyAttempts => "Too many failed attempts",
+            ErrorKind::MissingEmail => "No email address",
+            ErrorKind::EmailTaken => "Email address already in use",
+            ErrorKind::InvalidClient => "Invalid client",
+            ErrorKind::AuthorizationPending => "Authorization pending",
+            ErrorKind::SlowDown => "Slow down",
+            ErrorKind::AccessDenied => "Access denied",
+            ErrorKind::InvalidDeviceCode => "Invalid device code",
+            ErrorKind::InvalidUserCode => "Invalid user code",
+            ErrorKind::Delivery(_) => "Could not deliver the token",
+            ErrorKind::Harness(err) => match err.kind {
+                HarnessErrorKind::NotFound => "Not found",
+                HarnessErrorKind::Conflict => "Conflict",
+                HarnessErrorKind::Unavailable => "Service unavailable",
+                HarnessErrorKind::Corrupt | HarnessErrorKind::Other => "Internal Server Error",
+            },
+            ErrorKind::InvalidFormat
+            | ErrorKind::UnknownPepper
+            | ErrorKind::Create
+            | ErrorKind::DateTime
+            | ErrorKind::InvalidKey
+            | ErrorKind::MissingSigningKey
+            | ErrorKind::InvalidConfig(_)
+            | ErrorKind::Serialize => "Internal Server Error",
+        };
+        return message.to_string();
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAuthorized => write!(f, "Not Authorized"),
+            Self::TokenExpired => write!(f, "Token expired."),
+            Self::TokenInvalid => write!(f, "Token invalidated or already used."),
+            Self::InsufficientScope => write!(f, "Token does not have the required scope."),
+            Self::InvalidScope => write!(f, "Requested scope exceeds the granted scope."),
+            Self::AlreadyLoggedOut => write!(f, "Session already logged out."),
+            Self::UserNotFound => write!(f, "User not found."),
+            Self::Banned => write!(f, "User is banned."),
+            Self::PendingVerification => write!(f, "Account pending verification."),
+            Self::Suspended => write!(f, "Account suspended."),
+            Self::Deactivated => write!(f, "Account deactivated."),
+            Self::PasswordPolicy(err) => write!(f, "{}", err),
+            Self::PasswordChangeRequired(reason) => write!(f, "{}", reason.as_str()),
+            Self::Vetoed(veto) => write!(f, "{}", veto),
+            Self::TooManyAttempts => write!(f, "Too many failed attempts."),
+            Self::MissingEmail => write!(f, "User has no email address."),
+            Self::EmailTaken => write!(f, "Email address belongs to another account."),
+            Self::InvalidClient => write!(f, "Client authentication failed."),
+            Self::AuthorizationPending => write!(f, "Device authorization pending."),
+            Self::SlowDown => write!(f, "Device polled too fast."),
+            Self::AccessDenied => write!(f, "Device authorization denied."),
+            Self::InvalidDeviceCode => write!(f, "Unknown device code."),
+            Self::InvalidUserCode => write!(f, "Unknown or already used user code."),
+            Self::InvalidFormat => write!(f, "Token stored in invalid format."),
+            Self::UnknownPepper => write!(f, "Secret stored under an unknown pepper key."),
+            Self::Create => write!(f, "Could not generate token."),
+            Self::DateTime => write!(f, "Error computing expiration."),
+            Self::InvalidKey => write!(f, "Signing key is not a valid RSA private key."),
+            Self::MissingSigningKey => write!(f, "No signing key configured."),
+            Self::InvalidConfig(reason) => write!(f, "Invalid configuration: {}.", reason),
+            Self::Serialize => write!(f, "Could not serialize document."),
+            Self::Delivery(err) => write!(f, "Could not deliver token: {}", err),
+            Self::Harness(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error ({}): {}", self.code(), self.kind)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::PasswordPolicy(err) => Some(err),
+            ErrorKind::Vetoed(veto) => Some(veto),
+            ErrorKind::Delivery(err) => Some(err.as_ref()),
+            ErrorKind::Harness(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(value: ErrorKind) -> Self {
+        return Self::new(value);
+    }
+}
+
+impl From<HarnessError> for Error {
+    fn from(value: HarnessError) -> Self {
+        return Self::new(ErrorKind::Harness(value));
+    }
+}
+
+impl From<PasswordPolicyError> for Error {
+    fn from(value: PasswordPolicyError) -> Self {
+        return Self::new(ErrorKind::PasswordPolicy(value));
+    }
+}
+
+impl From<Veto> for Error {
+    fn from(value: Veto) -> Self {
+        return Self::new(ErrorKind::Vetoed(value));
+    }
+}
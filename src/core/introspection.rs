@@ -0,0 +1,190 @@
+use crate::harness::DbHarnessToken;
+
+use super::{
+    audit::{self, AuditEventKind},
+    auth_token::{decode_token, AuthToken, AuthTokenManager, TokenSubject, TokenType},
+    id::IdGenerator,
+    Error, ErrorKind,
+};
+
+/// Which table to look in first, the RFC 7662 / RFC 7009 `token_type_hint`.
+/// The hint is only an optimization, the other table is still searched when the token is not found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+}
+
+impl TokenTypeHint {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::AccessToken => "access_token",
+            Self::RefreshToken => "refresh_token",
+        }
+    }
+}
+
+/// The RFC 7662 introspection response. Everything but `active` is None for inactive tokens,
+/// so that nothing about unknown, expired or revoked tokens leaks to the caller.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Introspection {
+    pub active: bool,
+    pub sub: Option<String>,
+    // seconds since the unix epoch.
+    pub exp: Option<i64>,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub token_type: Option<TokenTypeHint>,
+}
+
+impl Introspection {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    fn active(token: &AuthToken) -> Self {
+        let (sub, client_id) = match token.subject() {
+            TokenSubject::User(user_id) => (user_id.to_string(), None),
+            TokenSubject::Client(client_id) => (client_id.to_string(), Some(client_id.to_string())),
+        };
+        let token_type = match token.token_type() {
+            TokenType::Access { .. } => TokenTypeHint::AccessToken,
+            TokenType::Refresh { .. } => TokenTypeHint::RefreshToken,
+        };
+
+        Self {
+            active: true,
+            sub: Some(sub),
+            exp: Some(token.expires().timestamp()),
+            scope: if token.scopes().is_empty() {
+                None
+            } else {
+                Some(token.scopes().to_string())
+            },
+            client_id,
+            token_type: Some(token_type),
+        }
+    }
+}
+
+impl<T, V> AuthTokenManager<T, V>
+where
+    T: IdGenerator,
+    V: DbHarnessToken,
+{
+    /// Token introspection (RFC 7662). `token` is the "<id>.<secret>" string from `encode_token`.
+    ///
+    /// A token that does not verify is reported as inactive rather than as an error.
+    pub fn introspect(
+        &self,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<Introspection, Error> {
+        let (token_id, secret) = match decode_token(token) {
+            Some(decoded) => decoded,
+            None => return Ok(Introspection::inactive()),
+        };
+
+        for token_type in search_order(hint) {
+            if let Some(auth_token) = self.verified_token(token_type, token_id, secret)? {
+                return Ok(Introspection::active(&auth_token));
+            }
+        }
+
+        Ok(Introspection::inactive())
+    }
+
+    /// Token revocation (RFC 7009). Access tokens are deleted, refresh tokens are invalidated so that a later
+    /// refresh attempt with the same token is detected by `SessionManager::create_new_refresh_token`.
+    ///
+    /// Revoking an unknown or already revoked token succeeds, the caller can not tell the difference.
+    pub fn revoke(&self, token: &str, hint: Option<TokenTypeHint>) -> Result<(), Error> {
+        self.revoke_token(token, hint).map(|_| ())
+    }
+
+    // like `revoke`, returns the token that was revoked.
+    pub(crate) fn revoke_token(
+        &self,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<Option<AuthToken>, Error> {
+        let (token_id, secret) = match decode_token(token) {
+            Some(decoded) => decoded,
+            None => return Ok(None),
+        };
+
+        for token_type in search_order(hint) {
+            if let Some(auth_token) = self.verified_token(token_type, token_id, secret)? {
+                let subject = auth_token.subject();
+                let res = match token_type {
+                    TokenTypeHint::AccessToken => self.delete_access_token(auth_token.id()),
+                    TokenTypeHint::RefreshToken => self.invalidate_token(auth_token.clone()),
+                };
+                res?;
+
+                let (user_id, detail) = match subject {
+                    TokenSubject::User(user_id) => (Some(user_id), token_type.as_str().to_string()),
+                    TokenSubject::Client(client_id) => (
+                        None,
+                        format!("{} of client {}", token_type.as_str(), client_id),
+                    ),
+                };
+                audit::record(
+                    &self.audit_sink,
+                    self.clock(),
+                    AuditEventKind::TokenRevoked,
+                    user_id,
+                    Some(detail),
+                );
+                return Ok(Some(auth_token));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // reads the token and runs it through the same checks as every other verification path.
+    fn verified_token(
+        &self,
+        token_type: TokenTypeHint,
+        token_id: i64,
+        secret: &str,
+    ) -> Result<Option<AuthToken>, Error> {
+        let auth_token = match token_type {
+            TokenTypeHint::AccessToken => self.get_access_token(token_id)?,
+            TokenTypeHint::RefreshToken => self.get_refresh_token(token_id)?,
+        };
+        let auth_token = match auth_token {
+            Some(auth_token) => auth_token,
+            None => return Ok(None),
+        };
+
+        // clients never hold refresh tokens.
+        if let (TokenTypeHint::RefreshToken, TokenSubject::Client(_)) =
+            (token_type, auth_token.subject())
+        {
+            return Ok(None);
+        }
+        let verified = self.verify_subject_token(auth_token.clone(), auth_token.subject(), secret);
+
+        match verified {
+            Ok(()) => Ok(Some(auth_token)),
+            Err(err) => match err.kind {
+                ErrorKind::TokenExpired
+                | ErrorKind::TokenInvalid
+                | ErrorKind::NotAuthorized
+                | ErrorKind::InvalidFormat => Ok(None),
+                _ => Err(err),
+            },
+        }
+    }
+}
+
+fn search_order(hint: Option<TokenTypeHint>) -> [TokenTypeHint; 2] {
+    match hint {
+        Some(TokenTypeHint::RefreshToken) => {
+            [TokenTypeHint::RefreshToken, TokenTypeHint::AccessToken]
+        }
+        _ => [TokenTypeHint::AccessToken, TokenTypeHint::RefreshToken],
+    }
+}
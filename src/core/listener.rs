@@ -0,0 +1,102 @@
+use std::{error, fmt::Display, sync::Arc};
+
+use super::{session::Session, user::Role};
+
+/// Why a listener refused an action. The reason is meant for logs, it is not shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Veto {
+    reason: String,
+}
+
+impl Veto {
+    pub fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl Display for Veto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Vetoed: {}", self.reason)
+    }
+}
+
+impl error::Error for Veto {}
+
+/// Hooks into the account and session lifecycle, registered with `with_listener` on `UserManagerConfig` and
+/// `SessionManagerConfig`. Every callback does nothing by default.
+///
+/// The `before_*` callbacks run once every other check passed and before anything is written. The first listener
+/// returning a `Veto` stops the action, which then fails with a `Vetoed` error. The `on_*` callbacks run after the
+/// action was committed and can not undo it.
+pub trait AuthEventListener: Send + Sync {
+    fn before_user_created(&self, _username: &str) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    fn on_user_created(&self, _user_id: i64) {}
+
+    fn on_user_deleted(&self, _user_id: i64) {}
+
+    /// The password (or passwordless token) was verified and the account is active.
+    fn before_login(&self, _user_id: i64) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    fn on_login(&self, _user_id: i64, _session: &Session) {}
+
+    /// Wrong password, inactive account, a password that has to be changed or a veto.
+    fn on_login_failed(&self, _user_id: i64) {}
+
+    fn before_refresh(&self, _session: &Session) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    fn on_refresh(&self, _session: &Session) {}
+
+    /// Logout, or any other way the session's tokens were invalidated.
+    fn on_session_revoked(&self, _session: &Session) {}
+
+    fn before_password_change(&self, _user_id: i64) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    fn on_password_changed(&self, _user_id: i64) {}
+
+    fn before_ban(&self, _user_id: i64) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    fn on_banned(&self, _user_id: i64) {}
+
+    fn on_unbanned(&self, _user_id: i64) {}
+
+    fn before_role_change(&self, _user_id: i64, _from: &Role, _to: &Role) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    fn on_role_changed(&self, _user_id: i64, _from: &Role, _to: &Role) {}
+}
+
+pub(crate) type Listeners = Vec<Arc<dyn AuthEventListener>>;
+
+// listeners are asked in the order they were registered, the first veto wins.
+pub(crate) fn check(
+    listeners: &Listeners,
+    hook: impl Fn(&dyn AuthEventListener) -> Result<(), Veto>,
+) -> Result<(), Veto> {
+    listeners
+        .iter()
+        .try_for_each(|listener| hook(listener.as_ref()))
+}
+
+pub(crate) fn notify(listeners: &Listeners, hook: impl Fn(&dyn AuthEventListener)) {
+    for listener in listeners {
+        hook(listener.as_ref());
+    }
+}
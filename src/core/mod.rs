@@ -1,14 +1,43 @@
-use auth_token::{AuthTokenError, AuthTokenErrorKind};
 use scrypt::password_hash::rand_core::RngCore;
 use scrypt::password_hash::{
     rand_core::OsRng, Encoding, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
 };
 use scrypt::{Params, Scrypt};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
 
+pub mod audit;
 pub mod auth_token;
+pub mod breached;
+pub mod client;
+pub mod clock;
+pub mod cookie;
+pub mod device;
+pub mod error;
 pub mod id;
+pub mod introspection;
+pub mod listener;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+pub mod one_time;
+pub mod password_policy;
+pub mod password_reset;
+pub mod passwordless;
+pub mod pepper;
+pub mod scope;
 pub mod session;
+pub mod sweeper;
 pub mod user;
+pub mod verification;
+
+pub use error::{Error, ErrorKind};
+
+/// Generates a random value: a salt, a token or a code.
+pub type GenerateFn = Arc<dyn Fn() -> String + Send + Sync>;
+/// Hashes a secret, called with the secret and a salt.
+pub type HashFn = Arc<dyn Fn(&str, &str) -> Result<String, Error> + Send + Sync>;
+/// Checks a secret against a hash made by a `HashFn`.
+pub type VerifyFn = Arc<dyn Fn(&str, &str) -> Result<(), Error> + Send + Sync>;
 
 // using pub static mut declaration here is doable, but would require an unsafe block.
 pub fn default_rng_salt_fn() -> String {
@@ -16,7 +45,7 @@ pub fn default_rng_salt_fn() -> String {
 }
 
 // This function takes in a user provided password and a salt, then creates the hash to be stored inside of the database.
-pub fn default_hash_fn<'a>(pwd: &'a str, salt: &'a str) -> Result<String, AuthTokenError> {
+pub fn default_hash_fn(pwd: &str, salt: &str) -> Result<String, Error> {
     let salt = SaltString::from_b64(salt);
     if let Ok(salt) = salt {
         // lowering params from the recommended could be useful for tokens that expire quickly
@@ -29,22 +58,22 @@ pub fn default_hash_fn<'a>(pwd: &'a str, salt: &'a str) -> Result<String, AuthTo
         );
         match res {
             Ok(secret) => return Ok(secret.to_string()),
-            Err(_) => return Err(AuthTokenError::new(AuthTokenErrorKind::Create)),
+            Err(_) => return Err(Error::new(ErrorKind::Create)),
         }
     } else {
-        return Err(AuthTokenError::new(AuthTokenErrorKind::Create));
+        return Err(Error::new(ErrorKind::Create));
     }
 }
 
 // default implementation stores the salt inside the secret, preventing required storage of the salt in a seperat field.
-pub fn default_verify_token_fn(token: &str, hash: &str) -> Result<(), AuthTokenError> {
+pub fn default_verify_token_fn(token: &str, hash: &str) -> Result<(), Error> {
     let hash_res = PasswordHash::parse(hash, Encoding::B64);
     match hash_res {
         Ok(hash) => match Scrypt.verify_password(token.as_bytes(), &hash) {
             Ok(_) => return Ok(()),
-            Err(_) => return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized)),
+            Err(_) => return Err(Error::new(ErrorKind::NotAuthorized)),
         },
-        Err(_) => return Err(AuthTokenError::new(AuthTokenErrorKind::InvalidFormat)),
+        Err(_) => return Err(Error::new(ErrorKind::InvalidFormat)),
     }
 }
 
@@ -53,3 +82,34 @@ pub fn default_rng_token_fn() -> String {
     let right = OsRng.next_u64() as u128;
     return ((left << 64) + right).to_string();
 }
+
+// six decimal digits, short enough to type. Rejection sampling keeps every code equally likely.
+pub fn default_rng_code_fn() -> String {
+    const CODES: u32 = 1_000_000;
+    let zone = u32::MAX - u32::MAX % CODES;
+    loop {
+        let value = OsRng.next_u32();
+        if value < zone {
+            return format!("{:06}", value % CODES);
+        }
+    }
+}
+
+// unsalted, only for high entropy values such as one time codes, never for passwords.
+pub(crate) fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// compares every byte regardless of where the first difference is.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
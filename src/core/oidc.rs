@@ -0,0 +1,430 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, TimeDelta, Utc};
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey,
+    pkcs1v15,
+    pkcs8::DecodePrivateKey,
+    signature::{SignatureEncoding, Signer},
+    traits::PublicKeyParts,
+    RsaPrivateKey,
+};
+use scrypt::password_hash::rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+use super::{
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
+    user::{PrivateUserMeta, PublicUserMeta, User},
+    Error, ErrorKind,
+};
+
+const SIGNING_ALG: &str = "RS256";
+const RSA_KEY_BITS: usize = 2048;
+// claims the provider sets itself, a user's meta can not override or duplicate them.
+const RESERVED_CLAIMS: &[&str] = &[
+    "sub",
+    "preferred_username",
+    "iss",
+    "aud",
+    "exp",
+    "iat",
+    "auth_time",
+    "nonce",
+    "amr",
+];
+
+/// Extra claims exposed through the userinfo response.
+/// Implement this on your `PublicUserMeta` type to publish standard claims such as `email` or `name`.
+/// Claims the provider sets itself, such as `sub` and `preferred_username`, are dropped.
+pub trait OidcClaims {
+    fn oidc_claims(&self) -> Map<String, Value>;
+}
+
+/// An RS256 key used to sign ID tokens. The `kid` is published in the JWKS and in every token header,
+/// so keep it stable for the lifetime of the key.
+#[derive(Clone)]
+pub struct SigningKey {
+    kid: String,
+    key: RsaPrivateKey,
+}
+
+impl SigningKey {
+    pub fn generate(kid: &str) -> Result<Self, Error> {
+        match RsaPrivateKey::new(&mut OsRng, RSA_KEY_BITS) {
+            Ok(key) => Ok(Self {
+                kid: kid.to_owned(),
+                key,
+            }),
+            Err(_) => Err(Error::new(ErrorKind::Create)),
+        }
+    }
+
+    /// Accepts either a PKCS#8 (`BEGIN PRIVATE KEY`) or PKCS#1 (`BEGIN RSA PRIVATE KEY`) PEM.
+    pub fn from_pem(kid: &str, pem: &str) -> Result<Self, Error> {
+        let key =
+            RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem));
+        match key {
+            Ok(key) => Ok(Self {
+                kid: kid.to_owned(),
+                key,
+            }),
+            Err(_) => Err(Error::new(ErrorKind::InvalidKey)),
+        }
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn jwk(&self) -> Jwk {
+        Jwk {
+            kty: String::from("RSA"),
+            key_use: String::from("sig"),
+            alg: String::from(SIGNING_ALG),
+            kid: self.kid.clone(),
+            n: URL_SAFE_NO_PAD.encode(self.key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(self.key.e().to_bytes_be()),
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let signer = pkcs1v15::SigningKey::<Sha256>::new(self.key.clone());
+        signer.sign(message).to_vec()
+    }
+}
+
+pub struct OidcProviderConfig {
+    issuer: String,
+    id_token_ttl: TimeDelta,
+    signing_keys: Vec<SigningKey>,
+    clock: Arc<dyn Clock>,
+}
+
+impl OidcProviderConfig {
+    pub fn new(issuer: &str) -> Self {
+        Self {
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            id_token_ttl: TimeDelta::minutes(10),
+            signing_keys: Vec::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// The first key added is used for signing, any additional keys are only published in the JWKS.
+    /// Keep a retired key here until every token it signed has expired.
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_keys.push(key);
+        self
+    }
+
+    /// How long an ID token is valid, 10 minutes by default.
+    pub fn with_id_token_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.id_token_ttl = ttl.into_time_delta();
+        self
+    }
+
+    /// Dates ID tokens with `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Fails with `ErrorKind::MissingSigningKey` without a signing key and with `ErrorKind::InvalidConfig` when the
+    /// ID token ttl is not positive or too long.
+    pub fn init(&self) -> Result<OidcProvider, Error> {
+        if self.signing_keys.is_empty() {
+            return Err(Error::new(ErrorKind::MissingSigningKey));
+        }
+        clock::check_ttl(self.clock.as_ref(), self.id_token_ttl, "id token ttl")?;
+
+        Ok(OidcProvider {
+            issuer: self.issuer.clone(),
+            id_token_ttl: self.id_token_ttl,
+            signing_keys: self.signing_keys.clone(),
+            clock: self.clock.clone(),
+        })
+    }
+}
+
+pub struct OidcProvider {
+    issuer: String,
+    id_token_ttl: TimeDelta,
+    signing_keys: Vec<SigningKey>,
+    clock: Arc<dyn Clock>,
+}
+
+impl OidcProvider {
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// Issues a signed ID token for an authenticated user.
+    ///
+    /// `audience` is the client id of the relying party, `nonce` must be echoed back when the client sent one
+    /// with its authorization request, and `amr` lists the authentication methods used (e.g. "pwd", "otp").
+    pub fn id_token<Pu, Pr>(
+        &self,
+        user: &User<Pu, Pr>,
+        audience: &str,
+        nonce: Option<&str>,
+        auth_time: DateTime<Utc>,
+        amr: &[&str],
+    ) -> Result<String, Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let now = self.clock.now();
+        let expires = match now.checked_add_signed(self.id_token_ttl) {
+            Some(expires) => expires,
+            None => return Err(Error::new(ErrorKind::DateTime)),
+        };
+
+        let claims = IdTokenClaims {
+            iss: self.issuer.clone(),
+            sub: user.id().to_string(),
+            aud: audience.to_owned(),
+            exp: expires.timestamp(),
+            iat: now.timestamp(),
+            auth_time: auth_time.timestamp(),
+            nonce: nonce.map(|nonce| nonce.to_owned()),
+            amr: amr.iter().map(|method| (*method).to_owned()).collect(),
+        };
+
+        self.sign_claims(&claims)
+    }
+
+    /// Signs an arbitrary claim set as a compact JWS with the active signing key.
+    pub fn sign_claims<C: Serialize>(&self, claims: &C) -> Result<String, Error> {
+        let key = &self.signing_keys[0];
+        let header = JwsHeader {
+            alg: SIGNING_ALG,
+            typ: "JWT",
+            kid: key.kid(),
+        };
+
+        let header = serde_json::to_vec(&header).map_err(serialize_error)?;
+        let claims = serde_json::to_vec(claims).map_err(serialize_error)?;
+
+        let mut jws = URL_SAFE_NO_PAD.encode(header);
+        jws.push('.');
+        jws.push_str(&URL_SAFE_NO_PAD.encode(claims));
+
+        let signature = key.sign(jws.as_bytes());
+        jws.push('.');
+        jws.push_str(&URL_SAFE_NO_PAD.encode(signature));
+
+        Ok(jws)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.signing_keys.iter().map(|key| key.jwk()).collect(),
+        }
+    }
+
+    /// Starts a `/.well-known/openid-configuration` document with every endpoint defaulted relative to the issuer.
+    pub fn discovery(&self) -> DiscoveryDocumentBuilder {
+        DiscoveryDocumentBuilder::new(&self.issuer)
+    }
+
+    pub fn userinfo<Pu, Pr>(&self, user: &User<Pu, Pr>) -> UserInfo
+    where
+        Pu: PublicUserMeta + OidcClaims,
+        Pr: PrivateUserMeta,
+    {
+        let mut claims = match user.public() {
+            Some(public) => public.oidc_claims(),
+            None => Map::new(),
+        };
+        claims.retain(|name, _| !RESERVED_CLAIMS.contains(&name.as_str()));
+
+        UserInfo {
+            sub: user.id().to_string(),
+            preferred_username: user.username().to_owned(),
+            claims,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JwsHeader<'a> {
+    alg: &'a str,
+    typ: &'a str,
+    kid: &'a str,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub amr: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+    pub kid: String,
+    pub n: String,
+    pub e: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string(self).map_err(serialize_error)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct UserInfo {
+    pub sub: String,
+    pub preferred_username: String,
+    #[serde(flatten)]
+    pub claims: Map<String, Value>,
+}
+
+impl UserInfo {
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string(self).map_err(serialize_error)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_session_endpoint: Option<String>,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+impl DiscoveryDocument {
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string(self).map_err(serialize_error)
+    }
+}
+
+pub struct DiscoveryDocumentBuilder {
+    document: DiscoveryDocument,
+}
+
+impl DiscoveryDocumentBuilder {
+    fn new(issuer: &str) -> Self {
+        let to_vec = |values: &[&str]| values.iter().map(|value| (*value).to_owned()).collect();
+
+        Self {
+            document: DiscoveryDocument {
+                issuer: issuer.to_owned(),
+                authorization_endpoint: format!("{}/authorize", issuer),
+                token_endpoint: format!("{}/token", issuer),
+                userinfo_endpoint: format!("{}/userinfo", issuer),
+                jwks_uri: format!("{}/.well-known/jwks.json", issuer),
+                end_session_endpoint: None,
+                scopes_supported: to_vec(&["openid", "profile"]),
+                response_types_supported: to_vec(&["code"]),
+                grant_types_supported: to_vec(&["authorization_code", "refresh_token"]),
+                subject_types_supported: to_vec(&["public"]),
+                id_token_signing_alg_values_supported: to_vec(&[SIGNING_ALG]),
+                token_endpoint_auth_methods_supported: to_vec(&["client_secret_basic"]),
+                claims_supported: to_vec(&[
+                    "sub",
+                    "iss",
+                    "aud",
+                    "exp",
+                    "iat",
+                    "auth_time",
+                    "nonce",
+                    "amr",
+                    "preferred_username",
+                ]),
+            },
+        }
+    }
+
+    pub fn authorization_endpoint(mut self, url: &str) -> Self {
+        self.document.authorization_endpoint = url.to_owned();
+        self
+    }
+
+    pub fn token_endpoint(mut self, url: &str) -> Self {
+        self.document.token_endpoint = url.to_owned();
+        self
+    }
+
+    pub fn userinfo_endpoint(mut self, url: &str) -> Self {
+        self.document.userinfo_endpoint = url.to_owned();
+        self
+    }
+
+    pub fn jwks_uri(mut self, url: &str) -> Self {
+        self.document.jwks_uri = url.to_owned();
+        self
+    }
+
+    pub fn end_session_endpoint(mut self, url: &str) -> Self {
+        self.document.end_session_endpoint = Some(url.to_owned());
+        self
+    }
+
+    pub fn scopes_supported(mut self, scopes: &[&str]) -> Self {
+        self.document.scopes_supported = scopes.iter().map(|scope| (*scope).to_owned()).collect();
+        self
+    }
+
+    pub fn grant_types_supported(mut self, grants: &[&str]) -> Self {
+        self.document.grant_types_supported =
+            grants.iter().map(|grant| (*grant).to_owned()).collect();
+        self
+    }
+
+    pub fn token_endpoint_auth_methods_supported(mut self, methods: &[&str]) -> Self {
+        self.document.token_endpoint_auth_methods_supported =
+            methods.iter().map(|method| (*method).to_owned()).collect();
+        self
+    }
+
+    /// Appends to the default claim list, typically with the claims returned by your `OidcClaims` impl.
+    pub fn claims_supported(mut self, claims: &[&str]) -> Self {
+        for claim in claims {
+            if !self.document.claims_supported.iter().any(|c| c == claim) {
+                self.document.claims_supported.push((*claim).to_owned());
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> DiscoveryDocument {
+        self.document
+    }
+}
+
+fn serialize_error(_: serde_json::Error) -> Error {
+    Error::new(ErrorKind::Serialize)
+}
@@ -0,0 +1,391 @@
+use std::{
+    error,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::harness::DbHarnessOneTimeToken;
+
+use super::{
+    auth_token::{decode_token, encode_token},
+    clock::Clock,
+    id::IdGenerator,
+    Error, ErrorKind, GenerateFn, HashFn, VerifyFn,
+};
+
+// One time tokens are short lived, single use secrets tied to a user and handed to them out of band (email, sms).
+// The secret is hashed at rest like a refresh token, the user receives "<id>.<secret>" (see `encode_token`).
+// Login codes are the exception, they are short enough to type and are looked up by user instead of by id,
+// which is why they carry an attempt counter.
+
+/// What a one time token may be redeemed for, a token issued for one purpose is rejected for any other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OneTimePurpose {
+    PasswordReset,
+    EmailVerification,
+    EmailChange,
+    MagicLink,
+    LoginCode,
+}
+
+impl OneTimePurpose {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::PasswordReset => "password_reset",
+            Self::EmailVerification => "email_verification",
+            Self::EmailChange => "email_change",
+            Self::MagicLink => "magic_link",
+            Self::LoginCode => "login_code",
+        }
+    }
+
+    pub fn parse(purpose: &str) -> Option<Self> {
+        match purpose {
+            "password_reset" => Some(Self::PasswordReset),
+            "email_verification" => Some(Self::EmailVerification),
+            "email_change" => Some(Self::EmailChange),
+            "magic_link" => Some(Self::MagicLink),
+            "login_code" => Some(Self::LoginCode),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OneTimeToken {
+    id: i64,
+    user_id: i64,
+    purpose: OneTimePurpose,
+    secret: String,
+    expires: DateTime<Utc>,
+    // purpose specific data, e.g. the address an email change is waiting on.
+    payload: Option<String>,
+    // failed redemptions, only counted for codes.
+    attempts: i64,
+}
+
+impl OneTimeToken {
+    pub fn from_values(
+        id: i64,
+        user_id: i64,
+        purpose: OneTimePurpose,
+        secret: String,
+        expires: DateTime<Utc>,
+        payload: Option<String>,
+        attempts: i64,
+    ) -> Self {
+        Self {
+            id,
+            user_id,
+            purpose,
+            secret,
+            expires,
+            payload,
+            attempts,
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    pub fn purpose(&self) -> OneTimePurpose {
+        self.purpose
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    pub fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+
+    pub fn payload(&self) -> Option<&str> {
+        self.payload.as_deref()
+    }
+
+    pub fn attempts(&self) -> i64 {
+        self.attempts
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires < now
+    }
+}
+
+/// A token on its way to the user. `token` is the plaintext, it is not stored anywhere.
+/// `email` is the address to deliver to, for an email change that is the new address.
+pub struct Delivery<'a> {
+    pub user_id: i64,
+    pub username: &'a str,
+    pub email: Option<&'a str>,
+    pub purpose: OneTimePurpose,
+    pub token: &'a str,
+    pub expires: DateTime<Utc>,
+}
+
+/// How one time tokens reach the user, implemented by the application, e.g. by sending an email.
+pub trait TokenDelivery {
+    fn deliver(&self, delivery: &Delivery) -> Result<(), Box<dyn error::Error + Send + Sync>>;
+}
+
+impl<D: TokenDelivery + ?Sized> TokenDelivery for &D {
+    fn deliver(&self, delivery: &Delivery) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        (**self).deliver(delivery)
+    }
+}
+
+impl<D: TokenDelivery + ?Sized> TokenDelivery for Arc<D> {
+    fn deliver(&self, delivery: &Delivery) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        (**self).deliver(delivery)
+    }
+}
+
+/// An owned copy of a `Delivery`, as kept by `InMemoryDelivery`.
+#[derive(Clone, Debug)]
+pub struct DeliveredToken {
+    pub user_id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub purpose: OneTimePurpose,
+    pub token: String,
+    pub expires: DateTime<Utc>,
+}
+
+/// Keeps every delivery in memory instead of sending it, meant for tests and local development.
+/// Hand the manager a reference or an `Arc` to read the tokens back.
+#[derive(Default)]
+pub struct InMemoryDelivery {
+    delivered: Mutex<Vec<DeliveredToken>>,
+}
+
+impl InMemoryDelivery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn delivered(&self) -> Vec<DeliveredToken> {
+        self.delivered.lock().unwrap().clone()
+    }
+
+    /// The most recent token delivered to the user for `purpose`.
+    pub fn last(&self, user_id: i64, purpose: OneTimePurpose) -> Option<DeliveredToken> {
+        self.delivered
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|delivered| delivered.user_id == user_id && delivered.purpose == purpose)
+            .cloned()
+    }
+
+    pub fn clear(&self) {
+        self.delivered.lock().unwrap().clear();
+    }
+}
+
+impl TokenDelivery for InMemoryDelivery {
+    fn deliver(&self, delivery: &Delivery) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        self.delivered.lock().unwrap().push(DeliveredToken {
+            user_id: delivery.user_id,
+            username: delivery.username.to_string(),
+            email: delivery.email.map(str::to_string),
+            purpose: delivery.purpose,
+            token: delivery.token.to_string(),
+            expires: delivery.expires,
+        });
+        Ok(())
+    }
+}
+
+// The hashing strategy and the clock of the flows built on one time tokens.
+#[derive(Clone)]
+pub(crate) struct OneTimeTokens {
+    pub salt_fn: GenerateFn,
+    pub token_fn: GenerateFn,
+    pub hash_fn: HashFn,
+    pub verify_fn: VerifyFn,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl OneTimeTokens {
+    /// Issues a new token, earlier tokens of the user for the same purpose stop working.
+    pub fn issue<T: IdGenerator, V: DbHarnessOneTimeToken>(
+        &self,
+        id_generator: &T,
+        harness: &V,
+        user_id: i64,
+        purpose: OneTimePurpose,
+        ttl: TimeDelta,
+        payload: Option<String>,
+    ) -> Result<(OneTimeToken, String), Error> {
+        let secret = (self.token_fn)();
+        let token = self.store(
+            id_generator,
+            harness,
+            user_id,
+            purpose,
+            ttl,
+            payload,
+            &secret,
+        )?;
+        let token_str = encode_token(token.id, &secret);
+        Ok((token, token_str))
+    }
+
+    /// Same as `issue`, but the user receives `code` as is. Codes are redeemed with `redeem_code`.
+    pub fn issue_code<T: IdGenerator, V: DbHarnessOneTimeToken>(
+        &self,
+        id_generator: &T,
+        harness: &V,
+        user_id: i64,
+        purpose: OneTimePurpose,
+        ttl: TimeDelta,
+        code: String,
+    ) -> Result<(OneTimeToken, String), Error> {
+        let token = self.store(id_generator, harness, user_id, purpose, ttl, None, &code)?;
+        Ok((token, code))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn store<T: IdGenerator, V: DbHarnessOneTimeToken>(
+        &self,
+        id_generator: &T,
+        harness: &V,
+        user_id: i64,
+        purpose: OneTimePurpose,
+        ttl: TimeDelta,
+        payload: Option<String>,
+        secret: &str,
+    ) -> Result<OneTimeToken, Error> {
+        let id = i64::from_be_bytes(id_generator.new_u64().to_be_bytes());
+        let salt = (self.salt_fn)();
+        let expires = match self.clock.now().checked_add_signed(ttl) {
+            Some(expires) => expires,
+            None => return Err(Error::new(ErrorKind::DateTime)),
+        };
+
+        let token = OneTimeToken {
+            id,
+            user_id,
+            purpose,
+            secret: (self.hash_fn)(secret, &salt)?,
+            expires,
+            payload,
+            attempts: 0,
+        };
+
+        harness.replace(&token)?;
+        Ok(token)
+    }
+
+    /// Verifies and consumes a token, it can not be redeemed a second time.
+    pub fn redeem<V: DbHarnessOneTimeToken>(
+        &self,
+        harness: &V,
+        token: &str,
+        purpose: OneTimePurpose,
+    ) -> Result<OneTimeToken, Error> {
+        let stored = self.verify(harness, token, purpose)?;
+        self.consume(harness, &stored)?;
+        Ok(stored)
+    }
+
+    /// Same as `redeem` without consuming a valid token, for flows that have more checks to pass before the
+    /// token is used up. An expired token is deleted all the same.
+    pub fn verify<V: DbHarnessOneTimeToken>(
+        &self,
+        harness: &V,
+        token: &str,
+        purpose: OneTimePurpose,
+    ) -> Result<OneTimeToken, Error> {
+        let (id, secret) = match decode_token(token) {
+            Some(decoded) => decoded,
+            None => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+
+        let stored = match harness.read(id)? {
+            Some(stored) if stored.purpose == purpose => stored,
+            _ => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+
+        if let Err(err) = (self.verify_fn)(secret, &stored.secret) {
+            return match err.kind {
+                ErrorKind::NotAuthorized => Err(Error::new(ErrorKind::TokenInvalid)),
+                _ => Err(err),
+            };
+        }
+
+        // an expired token is of no further use.
+        if stored.is_expired(self.clock.now()) {
+            harness.delete(stored.id)?;
+            return Err(Error::new(ErrorKind::TokenExpired));
+        }
+
+        Ok(stored)
+    }
+
+    /// Verifies and consumes the code the user currently holds for `purpose`. Every try counts as an attempt,
+    /// once `max_attempts` are used up the code is deleted and the user has to request a new one.
+    pub fn redeem_code<V: DbHarnessOneTimeToken>(
+        &self,
+        harness: &V,
+        user_id: i64,
+        purpose: OneTimePurpose,
+        code: &str,
+        max_attempts: i64,
+    ) -> Result<OneTimeToken, Error> {
+        let stored = match harness.read_by_user(user_id, purpose)? {
+            Some(stored) => stored,
+            None => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+
+        if stored.is_expired(self.clock.now()) {
+            harness.delete(stored.id)?;
+            return Err(Error::new(ErrorKind::TokenExpired));
+        }
+        // the attempt is taken in the harness before the code is checked, concurrent guesses each use up one
+        // and no more than `max_attempts` of them get to the check.
+        let attempts = match harness.increment_attempts(stored.id)? {
+            Some(attempts) => attempts,
+            None => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+        if attempts > max_attempts {
+            harness.delete(stored.id)?;
+            return Err(Error::new(ErrorKind::TooManyAttempts));
+        }
+
+        if let Err(err) = (self.verify_fn)(code, &stored.secret) {
+            return match err.kind {
+                ErrorKind::NotAuthorized if attempts >= max_attempts => {
+                    harness.delete(stored.id)?;
+                    Err(Error::new(ErrorKind::TooManyAttempts))
+                }
+                ErrorKind::NotAuthorized => Err(Error::new(ErrorKind::TokenInvalid)),
+                _ => Err(err),
+            };
+        }
+
+        self.consume(harness, &stored)?;
+        Ok(stored)
+    }
+
+    /// Deletes a verified token. Only one of several concurrent calls for the same token succeeds, the others
+    /// fail with `TokenInvalid` as if the token had already been redeemed.
+    pub fn consume<V: DbHarnessOneTimeToken>(
+        &self,
+        harness: &V,
+        token: &OneTimeToken,
+    ) -> Result<(), Error> {
+        match harness.delete(token.id)? {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::TokenInvalid)),
+        }
+    }
+}
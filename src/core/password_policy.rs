@@ -0,0 +1,395 @@
+use std::{error, fmt::Display, io, sync::Arc};
+
+use super::{breached::BreachedPasswords, Error, ErrorKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    // anything that is not a letter or a digit, including whitespace.
+    Symbol,
+}
+
+impl CharacterClass {
+    pub fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_numeric(),
+            Self::Symbol => !c.is_alphanumeric(),
+        }
+    }
+}
+
+/// What `UserManager` accepts as a password, checked whenever a password is set.
+///
+/// The default only bounds the length and rejects passwords containing the username, the character classes,
+/// strength score and breached corpus are opt in.
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    // in characters, not bytes.
+    min_length: usize,
+    max_length: usize,
+    required_classes: Vec<CharacterClass>,
+    forbid_username: bool,
+    // 0 to 4, see `strength_score`.
+    min_score: u8,
+    breached: Option<Arc<BreachedPasswords>>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            // hashing is expensive, unbounded input would be an easy way to tie up the server.
+            max_length: 128,
+            required_classes: Vec::new(),
+            forbid_username: true,
+            min_score: 0,
+            breached: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Accepts any password, including an empty one.
+    pub fn none() -> Self {
+        Self {
+            min_length: 0,
+            max_length: usize::MAX,
+            forbid_username: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn with_required_class(mut self, class: CharacterClass) -> Self {
+        if !self.required_classes.contains(&class) {
+            self.required_classes.push(class);
+        }
+        self
+    }
+
+    pub fn with_forbid_username(mut self, forbid_username: bool) -> Self {
+        self.forbid_username = forbid_username;
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: u8) -> Self {
+        self.min_score = min_score.min(4);
+        self
+    }
+
+    pub fn with_breached_passwords(mut self, breached: BreachedPasswords) -> Self {
+        self.breached = Some(Arc::new(breached));
+        self
+    }
+
+    // a policy no password can satisfy is a configuration mistake, checked at `UserManagerConfig::init`.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.min_length > self.max_length {
+            return Err(Error::new(ErrorKind::InvalidConfig(
+                "password min length above the max length",
+            )));
+        } else if self.min_score > 4 {
+            return Err(Error::new(ErrorKind::InvalidConfig(
+                "password min score above 4",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks `pwd` against every rule and reports all violations at once, so they can be shown together.
+    pub fn check(&self, username: &str, pwd: &str) -> Result<(), PasswordPolicyError> {
+        let mut violations = Vec::new();
+
+        let length = pwd.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort {
+                min: self.min_length,
+            });
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong {
+                max: self.max_length,
+            });
+        }
+
+        for class in &self.required_classes {
+            if !pwd.chars().any(|c| class.matches(c)) {
+                violations.push(PasswordViolation::MissingClass(*class));
+            }
+        }
+
+        // very short usernames would rule out too many passwords.
+        if self.forbid_username
+            && username.chars().count() >= 3
+            && pwd.to_lowercase().contains(&username.to_lowercase())
+        {
+            violations.push(PasswordViolation::ContainsUsername);
+        }
+
+        if self.min_score > 0 {
+            let score = strength_score(pwd, &[username]);
+            if score < self.min_score {
+                violations.push(PasswordViolation::TooWeak {
+                    score,
+                    min: self.min_score,
+                });
+            }
+        }
+
+        if let Some(breached) = &self.breached {
+            match breached.contains(pwd) {
+                Ok(true) => violations.push(PasswordViolation::Breached),
+                Ok(false) => (),
+                Err(err) => {
+                    return Err(PasswordPolicyError::new(PasswordPolicyErrorKind::Corpus(
+                        err,
+                    )))
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordPolicyError::new(
+                PasswordPolicyErrorKind::Violations(violations),
+            ))
+        }
+    }
+}
+
+// Strength estimation in the spirit of zxcvbn: the password is split into the cheapest sequence of patterns an
+// attacker would try (common passwords, the user's own inputs, repeats, sequences, keyboard runs, years), any
+// leftover character costs a flat 10 guesses. The total guess count is bucketed into a 0 to 4 score.
+
+// most common first, the position is the number of guesses it takes to reach the word.
+#[rustfmt::skip]
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "123456", "qwerty", "letmein", "iloveyou", "admin", "welcome", "monkey", "dragon",
+    "football", "baseball", "abc123", "master", "sunshine", "shadow", "princess", "superman",
+    "batman", "trustno1", "hello", "freedom", "whatever", "login", "starwars", "passw0rd", "access",
+    "mustang", "michael", "charlie", "jordan", "hunter", "ranger", "buster", "soccer", "hockey",
+    "killer", "george", "andrew", "summer", "winter", "spring", "autumn", "secret", "computer",
+    "internet", "pokemon", "cheese", "flower", "orange", "banana", "purple", "silver", "golden",
+    "diamond", "thomas", "jessica", "pepper", "ginger", "maggie", "tigger", "cookie", "money",
+    "love", "god", "sex", "test", "guest", "root", "user", "default", "changeme", "matrix",
+    "zaq1zaq1", "1q2w3e4r", "q1w2e3r4", "asdf", "zxcv", "qazwsx", "password1",
+];
+
+const KEYBOARD_ROWS: &[&str] = &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+// zxcvbn's thresholds, in log10 guesses.
+const SCORE_THRESHOLDS: [f64; 4] = [3.0, 6.0, 8.0, 10.0];
+
+/// Estimates how hard `pwd` is to guess, from 0 (trivial) to 4 (very strong). `user_inputs` are words an
+/// attacker would try first for this user, e.g. the username or email.
+pub fn strength_score(pwd: &str, user_inputs: &[&str]) -> u8 {
+    let log_guesses = estimate_log10_guesses(pwd, user_inputs);
+    SCORE_THRESHOLDS
+        .iter()
+        .filter(|threshold| log_guesses >= **threshold)
+        .count() as u8
+}
+
+fn estimate_log10_guesses(pwd: &str, user_inputs: &[&str]) -> f64 {
+    let chars: Vec<char> = pwd.chars().collect();
+    let user_inputs: Vec<String> = user_inputs
+        .iter()
+        .map(|input| input.to_lowercase())
+        .filter(|input| input.chars().count() >= 3)
+        .collect();
+
+    // best[i] is the cheapest way to guess the first i characters.
+    let mut best = vec![f64::INFINITY; chars.len() + 1];
+    best[0] = 0.0;
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + 1.0;
+        for start in 0..end.saturating_sub(2) {
+            if let Some(guesses) = pattern_guesses(&chars[start..end], &user_inputs) {
+                best[end] = best[end].min(best[start] + guesses.log10());
+            }
+        }
+    }
+    best[chars.len()]
+}
+
+// guesses for a run of at least three characters that forms a known pattern.
+fn pattern_guesses(run: &[char], user_inputs: &[String]) -> Option<f64> {
+    let len = run.len() as f64;
+    let mut guesses: Option<f64> = None;
+    let mut consider = |candidate: f64| {
+        guesses = Some(guesses.map_or(candidate, |guesses| guesses.min(candidate)));
+    };
+
+    let word: String = run.iter().collect::<String>().to_lowercase();
+    let unleeted: String = word.chars().map(unleet).collect();
+    // every upper case letter or l33t substitution roughly doubles the variants to try.
+    let mut variations = 1.0;
+    if run.iter().any(|c| c.is_uppercase()) {
+        variations *= 2.0;
+    }
+    if unleeted != word {
+        variations *= 2.0;
+    }
+    for candidate in [&word, &unleeted] {
+        if let Some(rank) = user_inputs.iter().position(|input| input == candidate) {
+            consider((rank + 1) as f64 * variations);
+        }
+        if let Some(rank) = COMMON_PASSWORDS
+            .iter()
+            .position(|common| *common == candidate)
+        {
+            consider((rank + 1) as f64 * variations);
+        }
+    }
+
+    if run.iter().all(|c| *c == run[0]) {
+        consider(cardinality(run[0]) * len);
+    }
+
+    let delta = run[1] as i64 - run[0] as i64;
+    if (delta == 1 || delta == -1)
+        && run
+            .windows(2)
+            .all(|pair| pair[1] as i64 - pair[0] as i64 == delta)
+    {
+        // sequences starting at an obvious point are tried first.
+        let base = if matches!(run[0], 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9') {
+            4.0
+        } else {
+            cardinality(run[0])
+        };
+        let direction = if delta == 1 { 1.0 } else { 2.0 };
+        consider(base * len * direction);
+    }
+
+    let reversed: String = word.chars().rev().collect();
+    if KEYBOARD_ROWS
+        .iter()
+        .any(|row| row.contains(word.as_str()) || row.contains(reversed.as_str()))
+    {
+        consider(40.0 * len);
+    }
+
+    if run.len() == 4 {
+        if let Ok(year) = word.parse::<u16>() {
+            if (1900..=2050).contains(&year) {
+                consider(150.0);
+            }
+        }
+    }
+
+    guesses
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => c,
+    }
+}
+
+fn cardinality(c: char) -> f64 {
+    if c.is_ascii_digit() {
+        10.0
+    } else if c.is_alphabetic() {
+        26.0
+    } else {
+        33.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PasswordViolation {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    MissingClass(CharacterClass),
+    ContainsUsername,
+    TooWeak { score: u8, min: u8 },
+    // found in the breached password corpus.
+    Breached,
+    // one of the user's recent passwords, see `UserManagerConfig::with_password_history`.
+    Reused,
+}
+
+impl Display for PasswordViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort { min } => write!(f, "Password must be at least {} characters.", min),
+            Self::TooLong { max } => write!(f, "Password must be at most {} characters.", max),
+            Self::MissingClass(class) => {
+                write!(f, "Password must contain a {:?} character.", class)
+            }
+            Self::ContainsUsername => write!(f, "Password must not contain the username."),
+            Self::TooWeak { score, min } => {
+                write!(f, "Password is too weak ({} of at least {}).", score, min)
+            }
+            Self::Breached => write!(f, "Password appears in a known data breach."),
+            Self::Reused => write!(f, "Password was used recently."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PasswordPolicyErrorKind {
+    Violations(Vec<PasswordViolation>),
+    // the breached password corpus could not be read.
+    Corpus(io::Error),
+}
+
+impl Display for PasswordPolicyErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Violations(violations) => {
+                let messages: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", messages.join(" "))
+            }
+            Self::Corpus(err) => write!(f, "Could not read the breached password corpus: {}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PasswordPolicyError {
+    pub kind: PasswordPolicyErrorKind,
+}
+
+impl PasswordPolicyError {
+    pub fn new(kind: PasswordPolicyErrorKind) -> Self {
+        Self { kind }
+    }
+
+    /// The broken rules, empty if the check itself failed.
+    pub fn violations(&self) -> &[PasswordViolation] {
+        match &self.kind {
+            PasswordPolicyErrorKind::Violations(violations) => violations,
+            PasswordPolicyErrorKind::Corpus(_) => &[],
+        }
+    }
+}
+
+impl Display for PasswordPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PasswordPolicyError: {}", self.kind)
+    }
+}
+
+impl error::Error for PasswordPolicyError {}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+
+use crate::harness::{
+    DbHarnessOneTimeToken, DbHarnessSession, DbHarnessToken, DbHarnessUser, DbTransaction,
+};
+
+use super::{
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
+    default_hash_fn, default_rng_salt_fn, default_rng_token_fn, default_verify_token_fn,
+    id::{DefaultIdGenerator, IdGenerator},
+    one_time::{Delivery, OneTimePurpose, OneTimeTokens, TokenDelivery},
+    session::{Session, SessionManager},
+    user::{PrivateUserMeta, PublicUserMeta, User, UserManager},
+    Error, ErrorKind,
+};
+
+pub struct PasswordResetConfig<T>
+where
+    T: IdGenerator,
+{
+    id_generator: T,
+    ttl: TimeDelta,
+    tokens: OneTimeTokens,
+}
+
+impl Default for PasswordResetConfig<DefaultIdGenerator> {
+    fn default() -> Self {
+        Self {
+            id_generator: DefaultIdGenerator {},
+            ttl: TimeDelta::minutes(15),
+            tokens: OneTimeTokens {
+                salt_fn: Arc::new(default_rng_salt_fn),
+                token_fn: Arc::new(default_rng_token_fn),
+                hash_fn: Arc::new(default_hash_fn),
+                verify_fn: Arc::new(default_verify_token_fn),
+                clock: Arc::new(SystemClock),
+            },
+        }
+    }
+}
+
+impl<T> PasswordResetConfig<T>
+where
+    T: IdGenerator + Copy,
+{
+    /// How long a reset token can be redeemed, 15 minutes by default.
+    pub fn with_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.ttl = ttl.into_time_delta();
+        self
+    }
+
+    /// Dates reset tokens and checks their expiry against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.tokens.clock = clock;
+        self
+    }
+
+    /// Fails with `ErrorKind::InvalidConfig` when the ttl is not positive or too long.
+    pub fn init<V: DbHarnessOneTimeToken, D: TokenDelivery>(
+        &self,
+        harness: V,
+        delivery: D,
+    ) -> Result<PasswordResetManager<T, V, D>, Error> {
+        clock::check_ttl(self.tokens.clock.as_ref(), self.ttl, "password reset ttl")?;
+        Ok(PasswordResetManager {
+            id_generator: self.id_generator,
+            ttl: self.ttl,
+            tokens: self.tokens.clone(),
+            harness,
+            delivery,
+        })
+    }
+}
+
+pub struct PasswordResetManager<T, V, D>
+where
+    T: IdGenerator,
+    V: DbHarnessOneTimeToken,
+    D: TokenDelivery,
+{
+    id_generator: T,
+    ttl: TimeDelta,
+    tokens: OneTimeTokens,
+    harness: V,
+    delivery: D,
+}
+
+impl<T, V, D> PasswordResetManager<T, V, D>
+where
+    T: IdGenerator,
+    V: DbHarnessOneTimeToken,
+    D: TokenDelivery,
+{
+    /// Issues a reset token and hands it to the delivery. A previously issued reset token of the user stops working.
+    pub fn request_reset<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let (token, token_str) = self.tokens.issue(
+            &self.id_generator,
+            &self.harness,
+            user.id(),
+            OneTimePurpose::PasswordReset,
+            self.ttl,
+            None,
+        )?;
+
+        let delivery = Delivery {
+            user_id: user.id(),
+            username: user.username(),
+            email: user.email(),
+            purpose: OneTimePurpose::PasswordReset,
+            token: &token_str,
+            expires: token.expires(),
+        };
+        match self.delivery.deliver(&delivery) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Delivery(err))),
+        }
+    }
+
+    /// Same as `request_reset`. An unknown username succeeds without issuing anything, so that the reset form
+    /// can not be used to find out which usernames exist.
+    pub fn request_reset_by_username<Ui, Uh>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        username: &str,
+    ) -> Result<(), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+    {
+        let user: Option<User<(), ()>> = user_manager.get_user_by_username(username)?;
+        match user {
+            Some(user) => self.request_reset(&user),
+            None => Ok(()),
+        }
+    }
+
+    /// Consumes the reset token, sets the new password and logs the user out of every session.
+    pub fn reset_password<Ui, Uh, Si, Sh, Th>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        session_manager: &SessionManager<Si, Sh, Th>,
+        token: &str,
+        new_pwd: &str,
+    ) -> Result<(), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+        Si: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        // the token is only used up once the new password passed the policy.
+        let token = self
+            .tokens
+            .verify(&self.harness, token, OneTimePurpose::PasswordReset)?;
+
+        let mut user: User<(), ()> = match user_manager.get_user(&token.user_id())? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::UserNotFound)),
+        };
+        let (previous, now) = user_manager.prepare_password_change(&mut user, new_pwd)?;
+
+        // the token is used up, the password changed and every session logged out together. None when a
+        // concurrent reset used the token first, nothing is written then.
+        let reset = || -> Result<Option<Vec<Session>>, Error> {
+            let tx = session_manager.transaction()?;
+            if !tx.delete_one_time_token(token.id())? {
+                return Ok(None);
+            }
+            user_manager.store_password_change(&tx, &user, &previous, now)?;
+
+            // read once the transaction holds the write lock, a concurrent refresh can not slip in a new token.
+            let mut sessions = Vec::new();
+            for mut session in session_manager.get_user_sessions(user.id())? {
+                if session.refresh_token().is_some() || session.access_token().is_some() {
+                    session_manager.clear_session_tokens(&tx, &mut session)?;
+                    sessions.push(session);
+                }
+            }
+            tx.commit()?;
+            Ok(Some(sessions))
+        };
+        let sessions = match reset()? {
+            Some(sessions) => sessions,
+            None => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+
+        user_manager.password_changed(user.id());
+        for session in &sessions {
+            session_manager.session_revoked(session);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+
+use crate::harness::{DbHarnessOneTimeToken, DbHarnessSession, DbHarnessToken, DbHarnessUser};
+
+use super::{
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
+    default_hash_fn, default_rng_code_fn, default_rng_salt_fn, default_rng_token_fn,
+    default_verify_token_fn,
+    id::{DefaultIdGenerator, IdGenerator},
+    one_time::{Delivery, OneTimePurpose, OneTimeToken, OneTimeTokens, TokenDelivery},
+    session::{Session, SessionManager},
+    user::{PrivateUserMeta, PublicUserMeta, User, UserManager},
+    Error, ErrorKind, GenerateFn,
+};
+
+// Login without a password, the user proves control over their inbox (or phone) instead.
+// A magic link carries "<id>.<secret>" like any other one time token, a login code is a handful of digits
+// the user types in next to their username.
+
+pub struct PasswordlessConfig<T>
+where
+    T: IdGenerator,
+{
+    id_generator: T,
+    ttl: TimeDelta,
+    // wrong codes before the code is thrown away, links are not guessable and do not count attempts.
+    max_attempts: i64,
+    code_fn: GenerateFn,
+    tokens: OneTimeTokens,
+}
+
+impl Default for PasswordlessConfig<DefaultIdGenerator> {
+    fn default() -> Self {
+        Self {
+            id_generator: DefaultIdGenerator {},
+            ttl: TimeDelta::minutes(10),
+            max_attempts: 5,
+            code_fn: Arc::new(default_rng_code_fn),
+            tokens: OneTimeTokens {
+                salt_fn: Arc::new(default_rng_salt_fn),
+                token_fn: Arc::new(default_rng_token_fn),
+                hash_fn: Arc::new(default_hash_fn),
+                verify_fn: Arc::new(default_verify_token_fn),
+                clock: Arc::new(SystemClock),
+            },
+        }
+    }
+}
+
+impl<T> PasswordlessConfig<T>
+where
+    T: IdGenerator + Copy,
+{
+    /// How long a magic link or login code can be redeemed, 10 minutes by default.
+    pub fn with_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.ttl = ttl.into_time_delta();
+        self
+    }
+
+    /// Dates magic links and login codes and checks their expiry against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.tokens.clock = clock;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: i64) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_code_fn(mut self, code_fn: GenerateFn) -> Self {
+        self.code_fn = code_fn;
+        self
+    }
+
+    /// Fails with `ErrorKind::InvalidConfig` when the ttl is not positive or too long.
+    pub fn init<V: DbHarnessOneTimeToken, D: TokenDelivery>(
+        &self,
+        harness: V,
+        delivery: D,
+    ) -> Result<PasswordlessManager<T, V, D>, Error> {
+        clock::check_ttl(self.tokens.clock.as_ref(), self.ttl, "passwordless ttl")?;
+        Ok(PasswordlessManager {
+            id_generator: self.id_generator,
+            ttl: self.ttl,
+            max_attempts: self.max_attempts,
+            code_fn: self.code_fn.clone(),
+            tokens: self.tokens.clone(),
+            harness,
+            delivery,
+        })
+    }
+}
+
+pub struct PasswordlessManager<T, V, D>
+where
+    T: IdGenerator,
+    V: DbHarnessOneTimeToken,
+    D: TokenDelivery,
+{
+    id_generator: T,
+    ttl: TimeDelta,
+    max_attempts: i64,
+    code_fn: GenerateFn,
+    tokens: OneTimeTokens,
+    harness: V,
+    delivery: D,
+}
+
+impl<T, V, D> PasswordlessManager<T, V, D>
+where
+    T: IdGenerator,
+    V: DbHarnessOneTimeToken,
+    D: TokenDelivery,
+{
+    /// Issues a magic link token and hands it to the delivery, the application builds the link around it.
+    /// A previously issued link of the user stops working.
+    pub fn request_magic_link<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let (token, token_str) = self.tokens.issue(
+            &self.id_generator,
+            &self.harness,
+            user.id(),
+            OneTimePurpose::MagicLink,
+            self.ttl,
+            None,
+        )?;
+        self.deliver(user, &token, &token_str)
+    }
+
+    /// Issues a login code and hands it to the delivery. A previously issued code of the user stops working.
+    pub fn request_code<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let (token, code) = self.tokens.issue_code(
+            &self.id_generator,
+            &self.harness,
+            user.id(),
+            OneTimePurpose::LoginCode,
+            self.ttl,
+            (self.code_fn)(),
+        )?;
+        self.deliver(user, &token, &code)
+    }
+
+    /// Same as `request_magic_link`. An unknown username succeeds without issuing anything, so that the login
+    /// form can not be used to find out which usernames exist.
+    pub fn request_magic_link_by_username<Ui, Uh>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        username: &str,
+    ) -> Result<(), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+    {
+        let user: Option<User<(), ()>> = user_manager.get_user_by_username(username)?;
+        match user {
+            Some(user) => self.request_magic_link(&user),
+            None => Ok(()),
+        }
+    }
+
+    /// Same as `request_code`, silent for unknown usernames.
+    pub fn request_code_by_username<Ui, Uh>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        username: &str,
+    ) -> Result<(), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+    {
+        let user: Option<User<(), ()>> = user_manager.get_user_by_username(username)?;
+        match user {
+            Some(user) => self.request_code(&user),
+            None => Ok(()),
+        }
+    }
+
+    /// Consumes the magic link token and opens a new session for its user. Returns the session with its refresh
+    /// and access secrets, the same as a password login.
+    pub fn redeem_magic_link<Ui, Uh, Si, Sh, Th>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        session_manager: &SessionManager<Si, Sh, Th>,
+        token: &str,
+    ) -> Result<(Session, String, String), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+        Si: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        let token = self
+            .tokens
+            .redeem(&self.harness, token, OneTimePurpose::MagicLink)?;
+        self.login(user_manager, session_manager, token.user_id())
+    }
+
+    /// Checks the code the user typed in and opens a new session. Unknown usernames are rejected like a wrong code.
+    pub fn redeem_code<Ui, Uh, Si, Sh, Th>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        session_manager: &SessionManager<Si, Sh, Th>,
+        username: &str,
+        code: &str,
+    ) -> Result<(Session, String, String), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+        Si: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        let user: User<(), ()> = match user_manager.get_user_by_username(username)? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+
+        self.tokens.redeem_code(
+            &self.harness,
+            user.id(),
+            OneTimePurpose::LoginCode,
+            code,
+            self.max_attempts,
+        )?;
+        self.login(user_manager, session_manager, user.id())
+    }
+
+    fn deliver<Pu, Pr>(
+        &self,
+        user: &User<Pu, Pr>,
+        token: &OneTimeToken,
+        token_str: &str,
+    ) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let delivery = Delivery {
+            user_id: user.id(),
+            username: user.username(),
+            email: user.email(),
+            purpose: token.purpose(),
+            token: token_str,
+            expires: token.expires(),
+        };
+        match self.delivery.deliver(&delivery) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Delivery(err))),
+        }
+    }
+
+    // the same checks as a password login, the token only replaces the password.
+    fn login<Ui, Uh, Si, Sh, Th>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        session_manager: &SessionManager<Si, Sh, Th>,
+        user_id: i64,
+    ) -> Result<(Session, String, String), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+        Si: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        let user: User<(), ()> = match user_manager.get_user(&user_id)? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::UserNotFound)),
+        };
+        user_manager.check_login(&user)?;
+
+        let res = session_manager.new_session(user_id)?;
+        user_manager.logged_in(&res.0);
+        Ok(res)
+    }
+}
@@ -0,0 +1,149 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::{constant_time_eq, sha256_hex, Error, ErrorKind, HashFn, VerifyFn};
+
+const PREFIX: &str = "pepper:";
+
+/// Server-side secret keys applied to passwords and tokens before they are hashed. The keys are never stored in
+/// the database, so a leaked database alone is not enough to brute force the hashes.
+///
+/// Every key has an id that is stored with the hash as "pepper:<id>:<hash>". To rotate, make a new key the current
+/// one and keep the old key around as a retired key until nothing stored under it is left. Passwords move to the
+/// current key at the next login, refresh tokens whenever they are rotated.
+#[derive(Clone)]
+pub struct Pepper {
+    current: u32,
+    keys: Vec<(u32, Vec<u8>)>,
+}
+
+impl Pepper {
+    /// `key` should be at least 32 random bytes.
+    pub fn new(id: u32, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            current: id,
+            keys: vec![(id, key.into())],
+        }
+    }
+
+    /// A key that is only used to verify what was stored under it, never for new hashes.
+    pub fn with_retired_key(mut self, id: u32, key: impl Into<Vec<u8>>) -> Self {
+        if id != self.current {
+            self.keys.retain(|(key_id, _)| *key_id != id);
+            self.keys.push((id, key.into()));
+        }
+        self
+    }
+
+    pub fn current_id(&self) -> u32 {
+        self.current
+    }
+
+    /// HMAC-SHA256 of `value` under the key `id`, hex encoded. None if there is no such key.
+    pub fn mac(&self, id: u32, value: &str) -> Option<String> {
+        let (_, key) = self.keys.iter().find(|(key_id, _)| *key_id == id)?;
+        // HMAC takes keys of any length.
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).ok()?;
+        mac.update(value.as_bytes());
+        Some(
+            mac.finalize()
+                .into_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        )
+    }
+
+    /// Whether `stored` was hashed under the current key.
+    pub fn is_current(&self, stored: &str) -> bool {
+        matches!(decode(stored), Some((id, _)) if id == self.current)
+    }
+}
+
+pub fn encode(id: u32, hash: &str) -> String {
+    format!("{}{}:{}", PREFIX, id, hash)
+}
+
+/// Splits a stored "pepper:<id>:<hash>" into the key id and the hash, None for hashes stored without a pepper.
+pub fn decode(stored: &str) -> Option<(u32, &str)> {
+    let (id, hash) = stored.strip_prefix(PREFIX)?.split_once(':')?;
+    Some((id.parse().ok()?, hash))
+}
+
+// hashes with `hash_fn`, keyed with the current pepper key if there is one.
+pub(crate) fn hash_secret(
+    pepper: Option<&Pepper>,
+    hash_fn: &HashFn,
+    secret: &str,
+    salt: &str,
+) -> Result<String, Error> {
+    match pepper {
+        Some(pepper) => {
+            let id = pepper.current_id();
+            let keyed = pepper
+                .mac(id, secret)
+                .ok_or(Error::new(ErrorKind::UnknownPepper))?;
+            Ok(encode(id, &hash_fn(&keyed, salt)?))
+        }
+        None => hash_fn(secret, salt),
+    }
+}
+
+// hashes stored without a pepper still verify, so a pepper can be introduced on an existing database.
+pub(crate) fn verify_secret(
+    pepper: Option<&Pepper>,
+    verify_fn: &VerifyFn,
+    secret: &str,
+    stored: &str,
+) -> Result<(), Error> {
+    match decode(stored) {
+        Some((id, hash)) => {
+            let keyed = pepper
+                .and_then(|pepper| pepper.mac(id, secret))
+                .ok_or(Error::new(ErrorKind::UnknownPepper))?;
+            verify_fn(&keyed, hash)
+        }
+        None => verify_fn(secret, stored),
+    }
+}
+
+// a pepper is configured, but `stored` is not hashed under its current key.
+pub(crate) fn needs_rehash(pepper: Option<&Pepper>, stored: &str) -> bool {
+    match pepper {
+        Some(pepper) => !pepper.is_current(stored),
+        None => false,
+    }
+}
+
+// access tokens are random and short lived, a fast digest is enough. Keyed when there is a pepper.
+pub(crate) fn digest_token(pepper: Option<&Pepper>, token: &str) -> Result<String, Error> {
+    match pepper {
+        Some(pepper) => {
+            let id = pepper.current_id();
+            let mac = pepper
+                .mac(id, token)
+                .ok_or(Error::new(ErrorKind::UnknownPepper))?;
+            Ok(encode(id, &mac))
+        }
+        None => Ok(sha256_hex(token)),
+    }
+}
+
+pub(crate) fn verify_token_digest(
+    pepper: Option<&Pepper>,
+    token: &str,
+    stored: &str,
+) -> Result<(), Error> {
+    let digest = match decode(stored) {
+        Some((id, _)) => pepper
+            .and_then(|pepper| pepper.mac(id, token))
+            .map(|mac| encode(id, &mac))
+            .ok_or(Error::new(ErrorKind::UnknownPepper))?,
+        None => sha256_hex(token),
+    };
+    if constant_time_eq(&digest, stored) {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::NotAuthorized))
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt::Display;
+
+/// A set of OAuth2 style scopes. Scopes are stored and transmitted as a single space delimited string.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Scopes {
+    scopes: Vec<String>,
+}
+
+impl Scopes {
+    pub fn new() -> Self {
+        Self { scopes: Vec::new() }
+    }
+
+    pub fn parse(scopes: &str) -> Self {
+        let mut out = Self::new();
+        for scope in scopes.split_whitespace() {
+            out.add(scope);
+        }
+        out
+    }
+
+    pub fn from_slice(scopes: &[&str]) -> Self {
+        let mut out = Self::new();
+        for scope in scopes {
+            out.add(scope);
+        }
+        out
+    }
+
+    pub fn add(&mut self, scope: &str) {
+        if !self.contains(scope) {
+            self.scopes.push(scope.to_owned());
+        }
+    }
+
+    pub fn remove(&mut self, scope: &str) {
+        self.scopes.retain(|s| s != scope);
+    }
+
+    pub fn contains(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn contains_all(&self, scopes: &[&str]) -> bool {
+        scopes.iter().all(|scope| self.contains(scope))
+    }
+
+    /// true when every scope in `self` is also in `other`.
+    pub fn is_subset(&self, other: &Scopes) -> bool {
+        self.scopes.iter().all(|scope| other.contains(scope))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scopes.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        self.scopes.iter()
+    }
+}
+
+impl Display for Scopes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.scopes.join(" "))
+    }
+}
@@ -1,13 +1,24 @@
-use std::error;
+use std::sync::Arc;
 
-use crate::harness::{DbHarnessSession, DbHarnessToken};
+use chrono::TimeDelta;
+
+use crate::harness::{
+    DbHarnessSession, DbHarnessToken, DbTransaction, HarnessError, HarnessErrorKind,
+};
 
 use super::{
+    audit::{self, AuditEventKind, AuditSink},
     auth_token::{
-        AuthToken, AuthTokenError, AuthTokenErrorKind, AuthTokenManager, AuthTokenManagerConfig,
-        TokenManagerError, TokenTtl,
+        decode_token, AuthToken, AuthTokenManager, AuthTokenManagerConfig, TokenSubject, TokenTtl,
+        TokenType,
     },
+    clock::{self, Clock, IntoTimeDelta},
     id::{DefaultIdGenerator, IdGenerator},
+    introspection::{Introspection, TokenTypeHint},
+    listener::{self, AuthEventListener, Listeners},
+    pepper::Pepper,
+    scope::Scopes,
+    Error, ErrorKind, GenerateFn, HashFn, VerifyFn,
 };
 
 // Session naming convention may be a bit misleading. it is really to handle the refresh token on the auth server iteself...
@@ -18,7 +29,10 @@ where
 {
     id_generator: T,
     token_manager_config: AuthTokenManagerConfig<T>,
-    ttl: i64,
+    // how long a session lasts without a refresh, every refresh token is issued for this long.
+    ttl: TimeDelta,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    listeners: Listeners,
 }
 
 impl Default for SessionManagerConfig<DefaultIdGenerator> {
@@ -26,7 +40,9 @@ impl Default for SessionManagerConfig<DefaultIdGenerator> {
         return Self {
             id_generator: DefaultIdGenerator {},
             token_manager_config: AuthTokenManagerConfig::default(),
-            ttl: 240,
+            ttl: TimeDelta::minutes(240),
+            audit_sink: None,
+            listeners: Vec::new(),
         };
     }
 }
@@ -35,18 +51,101 @@ impl<T> SessionManagerConfig<T>
 where
     T: IdGenerator + Copy,
 {
+    /// Fails with `ErrorKind::InvalidConfig` when a ttl is not positive or too long, or when access tokens would
+    /// outlive the refresh token of their session.
     pub fn init<V: DbHarnessSession, Y: DbHarnessToken>(
         &self,
         session_harness: V,
         token_harness: Y,
-    ) -> SessionManager<T, V, Y> {
-        return SessionManager {
+    ) -> Result<SessionManager<T, V, Y>, Error> {
+        let token_manager = self.token_manager_config.init(token_harness)?;
+        clock::check_ttl(token_manager.clock(), self.ttl, "refresh token ttl")?;
+        if self.ttl < token_manager.ttl() {
+            return Err(Error::new(ErrorKind::InvalidConfig(
+                "refresh token ttl shorter than the access token ttl",
+            )));
+        }
+
+        return Ok(SessionManager {
             id_generator: self.id_generator,
             harness: session_harness,
             ttl: self.ttl,
-            token_manager: self.token_manager_config.init(token_harness),
+            token_manager,
+            audit_sink: self.audit_sink.clone(),
+            listeners: self.listeners.clone(),
+        });
+    }
+
+    /// How long an access token is valid, 30 minutes by default.
+    pub fn with_access_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.token_manager_config = self.token_manager_config.with_access_ttl(ttl);
+        self
+    }
+
+    /// How long a session lasts without a refresh, 4 hours by default. Each refresh issues a new refresh token
+    /// that is valid for this long.
+    pub fn with_refresh_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.ttl = ttl.into_time_delta();
+        self
+    }
+
+    /// See `AuthTokenManagerConfig::with_token_fn`.
+    pub fn with_token_fn(mut self, token_fn: GenerateFn) -> Self {
+        self.token_manager_config = self.token_manager_config.with_token_fn(token_fn);
+        self
+    }
+
+    pub fn with_salt_fn(mut self, salt_fn: GenerateFn) -> Self {
+        self.token_manager_config = self.token_manager_config.with_salt_fn(salt_fn);
+        self
+    }
+
+    /// Hashes refresh token secrets, see `AuthTokenManagerConfig::with_hash_fn`.
+    pub fn with_hash_fn(mut self, hash_fn: HashFn) -> Self {
+        self.token_manager_config = self.token_manager_config.with_hash_fn(hash_fn);
+        self
+    }
+
+    pub fn with_verify_fn(mut self, verify_fn: VerifyFn) -> Self {
+        self.token_manager_config = self.token_manager_config.with_verify_fn(verify_fn);
+        self
+    }
+
+    /// Session and token ids both come from `id_generator`.
+    pub fn with_id_gen<X: IdGenerator + Copy>(&self, id_generator: X) -> SessionManagerConfig<X> {
+        return SessionManagerConfig {
+            id_generator,
+            token_manager_config: self.token_manager_config.with_id_gen(id_generator),
+            ttl: self.ttl,
+            audit_sink: self.audit_sink.clone(),
+            listeners: self.listeners.clone(),
         };
     }
+
+    /// Keys refresh token hashes and access token digests with `pepper`, see `Pepper`.
+    pub fn with_pepper(mut self, pepper: Pepper) -> Self {
+        self.token_manager_config = self.token_manager_config.with_pepper(pepper);
+        self
+    }
+
+    /// Records refreshes, logouts and token revocations, see `AuditSink`.
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.token_manager_config = self.token_manager_config.with_audit_sink(sink.clone());
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Can be called more than once, see `AuthEventListener`.
+    pub fn with_listener(mut self, listener: Arc<dyn AuthEventListener>) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Checks token expiry against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.token_manager_config = self.token_manager_config.with_clock(clock);
+        self
+    }
 }
 
 pub struct SessionManager<T, V, X>
@@ -58,7 +157,9 @@ where
     id_generator: T,
     token_manager: AuthTokenManager<T, X>,
     harness: V,
-    ttl: i64,
+    ttl: TimeDelta,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    listeners: Listeners,
 }
 
 impl<T, V, X> SessionManager<T, V, X>
@@ -67,18 +168,28 @@ where
     V: DbHarnessSession,
     X: DbHarnessToken,
 {
-    pub fn new_session(
+    pub fn new_session(&self, user_id: i64) -> Result<(Session, String, String), Error> {
+        self.new_scoped_session(user_id, Scopes::new())
+    }
+
+    /// Both tokens of the session carry `scopes`. Later refreshes can narrow them but never widen them.
+    pub fn new_scoped_session(
         &self,
         user_id: i64,
-    ) -> Result<(Session, String, String), Box<dyn error::Error>> {
+        scopes: Scopes,
+    ) -> Result<(Session, String, String), Error> {
         let id = self.id_generator.new_u64();
 
-        let (refresh_token, refresh_secret) = self
-            .token_manager
-            .next_token(user_id, TokenTtl::Refresh(self.ttl))?;
-
-        let (access_token, access_secret) =
-            self.token_manager.next_token(user_id, TokenTtl::Access)?;
+        let (refresh_token, refresh_secret) = self.token_manager.build_token(
+            TokenSubject::User(user_id),
+            TokenTtl::Refresh(self.ttl),
+            scopes.clone(),
+        )?;
+        let (access_token, access_secret) = self.token_manager.build_token(
+            TokenSubject::User(user_id),
+            TokenTtl::Access,
+            scopes,
+        )?;
 
         let session = Session {
             // shift to bytes then into i64 (DO NOT CAST, we want to preserve the bit values)
@@ -87,7 +198,13 @@ where
             refresh_token: Some(refresh_token.id()),
             access_token: Some(access_token.id()),
         };
-        self.harness.insert(&session)?;
+
+        // the tokens are only written together with their session, a failed step leaves no orphaned token behind.
+        let tx = self.harness.transaction()?;
+        tx.insert_token(&refresh_token)?;
+        tx.insert_token(&access_token)?;
+        tx.insert_session(&session)?;
+        tx.commit()?;
 
         return Ok((session, refresh_secret, access_secret));
     }
@@ -97,7 +214,7 @@ where
         token_id: i64,
         user_id: i64,
         user_token_atmpt: &str,
-    ) -> Result<(), TokenManagerError> {
+    ) -> Result<(), Error> {
         self.token_manager
             .trusted_verify_refresh_token(token_id, user_id, user_token_atmpt)
     }
@@ -107,47 +224,153 @@ where
         token_id: i64,
         user_id: i64,
         user_token_atmpt: &str,
-    ) -> Result<(), TokenManagerError> {
+    ) -> Result<(), Error> {
         self.token_manager
             .trusted_verify_access_token(token_id, user_id, user_token_atmpt)
     }
 
+    pub fn verify_access_token_with_scope(
+        &self,
+        token_id: i64,
+        user_id: i64,
+        user_token_atmpt: &str,
+        required: &[&str],
+    ) -> Result<(), Error> {
+        self.token_manager.trusted_verify_access_token_with_scope(
+            token_id,
+            user_id,
+            user_token_atmpt,
+            required,
+        )
+    }
+
     pub fn verify_token(
         &self,
         token: AuthToken,
         user_id: i64,
         user_token_atmpt: &str,
-    ) -> Result<(), AuthTokenError> {
+    ) -> Result<(), Error> {
         self.token_manager
             .verify_token(token, user_id, user_token_atmpt)
     }
 
-    pub fn get_session(&self, id: i64) -> Result<Session, Box<dyn error::Error>> {
-        return self.harness.read(id);
+    /// See `AuthTokenManager::introspect`, works for both user and client tokens.
+    pub fn introspect(
+        &self,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<Introspection, Error> {
+        self.token_manager.introspect(token, hint)
+    }
+
+    /// See `AuthTokenManager::revoke`. Revoking a refresh token also revokes the access token issued with it,
+    /// as RFC 7009 section 2.1 recommends.
+    pub fn revoke(&self, token: &str, hint: Option<TokenTypeHint>) -> Result<(), Error> {
+        let revoked = match self.token_manager.revoke_token(token, hint)? {
+            Some(revoked) => revoked,
+            None => return Ok(()),
+        };
+        if let TokenType::Refresh { .. } = revoked.token_type() {
+            if let Some(session) = self.get_session_by_refresh_token(revoked.id())? {
+                self.invalidate_access_token(session)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lifetime of the session's refresh token.
+    pub fn refresh_ttl(&self) -> TimeDelta {
+        self.ttl
+    }
+
+    /// Lifetime of the session's access tokens.
+    pub fn access_ttl(&self) -> TimeDelta {
+        self.token_manager.ttl()
+    }
+
+    /// Fails with a `not_found` error when there is no such session.
+    pub fn get_session(&self, id: i64) -> Result<Session, Error> {
+        match self.harness.read(id)? {
+            Some(session) => Ok(session),
+            None => Err(HarnessError::new(
+                HarnessErrorKind::NotFound,
+                format!("session {} does not exist", id),
+            )
+            .into()),
+        }
+    }
+
+    pub fn get_user_sessions(&self, user_id: i64) -> Result<Vec<Session>, Error> {
+        Ok(self.harness.read_by_user(user_id)?)
+    }
+
+    pub fn get_session_by_access_token(&self, token_id: i64) -> Result<Option<Session>, Error> {
+        Ok(self.harness.read_by_access_token(token_id)?)
+    }
+
+    pub fn get_session_by_refresh_token(&self, token_id: i64) -> Result<Option<Session>, Error> {
+        Ok(self.harness.read_by_refresh_token(token_id)?)
+    }
+
+    /// Verifies an "<id>.<secret>" access token string (see `encode_token`), as sent in a bearer header,
+    /// and returns the session it belongs to. Client tokens have no session and are rejected.
+    pub fn authenticate(&self, access_token: &str) -> Result<Session, Error> {
+        let not_authorized = || Error::new(ErrorKind::NotAuthorized);
+
+        let (token_id, secret) = match decode_token(access_token) {
+            Some(decoded) => decoded,
+            None => return Err(not_authorized()),
+        };
+
+        // a forged token id finds no token.
+        let user_id = match self.token_manager.get_access_token(token_id)? {
+            Some(token) => match token.user_id() {
+                Some(user_id) => user_id,
+                None => return Err(not_authorized()),
+            },
+            None => return Err(not_authorized()),
+        };
+
+        self.verify_access_token(token_id, user_id, secret)?;
+
+        match self.get_session_by_access_token(token_id) {
+            Ok(Some(session)) => Ok(session),
+            Ok(None) => Err(not_authorized()),
+            Err(err) => Err(err),
+        }
     }
 
     pub fn create_new_access_token(
         &self,
         session: &mut Session,
         user_id: i64,
-    ) -> Result<String, Box<dyn error::Error>> {
-        // cleanup old token
-        match session.access_token {
-            Some(token_id) => {
-                // cleanup old access token
-                self.token_manager.delete_access_token(token_id)?;
-            }
-            // no access token to cleanup, we can continue...
-            None => {}
-        }
-
-        let (new_token, access_token_secret) =
-            self.token_manager.next_token(user_id, TokenTtl::Access)?;
+    ) -> Result<String, Error> {
+        // the access token carries the scopes of the session's refresh token.
+        let scopes = match session.refresh_token {
+            Some(token_id) => match self.token_manager.get_refresh_token(token_id)? {
+                Some(refresh_token) => refresh_token.scopes().clone(),
+                None => Scopes::new(),
+            },
+            None => Scopes::new(),
+        };
 
-        session.access_token = Some(new_token.id());
+        let (new_token, access_token_secret) = self.token_manager.build_token(
+            TokenSubject::User(user_id),
+            TokenTtl::Access,
+            scopes,
+        )?;
 
-        //update the session with the new token id.
-        self.harness.update(session)?;
+        // the old token is swapped for the new one in a single step.
+        let tx = self.harness.transaction()?;
+        if let Some(token_id) = session.access_token {
+            tx.delete_access_token(token_id)?;
+        }
+        tx.insert_token(&new_token)?;
+        let mut updated = *session;
+        updated.access_token = Some(new_token.id());
+        tx.update_session(&updated)?;
+        tx.commit()?;
+        *session = updated;
 
         return Ok(access_token_secret);
     }
@@ -155,11 +378,23 @@ where
     /// while session does have a user_id field, we do not want to verify the user id from this struct,
     /// instead the user id should be supplied from the user request.
     pub fn create_new_refresh_token(
+        &self,
+        session: Session,
+        user_id: i64,
+        user_token_atmpt: &str,
+    ) -> Result<(String, String), Error> {
+        self.create_new_refresh_token_with_scopes(session, user_id, user_token_atmpt, None)
+    }
+
+    /// Rotates the refresh token like `create_new_refresh_token`. When `requested` is provided the new tokens only
+    /// carry those scopes, which must be a subset of the scopes of the current refresh token.
+    pub fn create_new_refresh_token_with_scopes(
         &self,
         mut session: Session,
         user_id: i64,
         user_token_atmpt: &str,
-    ) -> Result<(String, String), TokenManagerError> {
+        requested: Option<&Scopes>,
+    ) -> Result<(String, String), Error> {
         let refresh_token: Option<AuthToken>;
 
         // retrieve the persisted token from db
@@ -172,12 +407,12 @@ where
                     Ok(token) => refresh_token = token,
 
                     // the harness failed to fetch the provided refresh token, return an error...
-                    Err(err) => return Err(TokenManagerError::Harness(err)),
+                    Err(err) => return Err(err),
                 }
             }
             None => {
                 // if the session has a None value in the session token, there is no 'old' refresh token to check.
-                return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized).into());
+                return Err(Error::new(ErrorKind::NotAuthorized));
             }
         }
 
@@ -195,18 +430,16 @@ where
 
                     Err(err) => {
                         match err.kind {
-                            AuthTokenErrorKind::Expired | AuthTokenErrorKind::Invalid => {
+                            ErrorKind::TokenExpired | ErrorKind::TokenInvalid => {
                                 // if we hit this area, someone has accessed an expired or invalidated refresh token.
                                 // When this happens, we want to invalidate the session and return an error.
                                 self.set_token_ids_none(session)?;
                                 // Change the error to reflect the new state of the session.
-                                return Err(
-                                    AuthTokenError::new(AuthTokenErrorKind::NotAuthorized).into()
-                                );
+                                return Err(Error::new(ErrorKind::NotAuthorized));
                             }
                             _ => {
                                 // propogate the wildcard error
-                                return Err(err.into());
+                                return Err(err);
                             }
                         }
                     }
@@ -215,72 +448,168 @@ where
 
             // No token found in the database, return an error...
             None => {
-                return Err(AuthTokenError::new(AuthTokenErrorKind::NotAuthorized).into());
+                return Err(Error::new(ErrorKind::NotAuthorized));
             }
         }
 
-        // create the new refresh token...
-        let (refresh_token, refresh_token_secret) = self
-            .token_manager
-            .next_token(user_id, TokenTtl::Refresh(self.ttl))?;
+        // a refresh may narrow the scope of the session, never widen it.
+        let granted = match refresh_token {
+            Some(token) => token.scopes().clone(),
+            None => Scopes::new(),
+        };
+        let scopes = match requested {
+            Some(requested) => {
+                if !requested.is_subset(&granted) {
+                    return Err(Error::new(ErrorKind::InvalidScope));
+                }
+                requested.clone()
+            }
+            None => granted,
+        };
+
+        if let Err(veto) = listener::check(&self.listeners, |listener| {
+            listener.before_refresh(&session)
+        }) {
+            return Err(veto.into());
+        }
 
-        // create a new access token...
-        // creating it this way instead of the already build harness allows
-        let (access_token, access_token_secret) =
-            self.token_manager.next_token(user_id, TokenTtl::Access)?;
+        let (refresh_token, refresh_token_secret) = self.token_manager.build_token(
+            TokenSubject::User(user_id),
+            TokenTtl::Refresh(self.ttl),
+            scopes.clone(),
+        )?;
+        let (access_token, access_token_secret) = self.token_manager.build_token(
+            TokenSubject::User(user_id),
+            TokenTtl::Access,
+            scopes,
+        )?;
 
         // save the tokens to the session
-        session.refresh_token = Some(refresh_token.id());
-        session.access_token = Some(access_token.id());
-
-        match self.harness.update(&session) {
-            Ok(()) => return Ok((refresh_token_secret, access_token_secret)),
-            Err(err) => return Err(TokenManagerError::Harness(err)),
+        let old_refresh_token = session.refresh_token.replace(refresh_token.id());
+        let old_access_token = session.access_token.replace(access_token.id());
+
+        // the new tokens replace the old ones together with the session pointing at them, on failure the session
+        // keeps its current tokens and the client can try again. The old refresh token is taken inside the
+        // transaction, of two refreshes with the same token only the first one commits.
+        let rotate = || -> Result<bool, HarnessError> {
+            let tx = self.harness.transaction()?;
+            if let Some(token_id) = old_refresh_token {
+                if !tx.delete_refresh_token(token_id)? {
+                    return Ok(false);
+                }
+            }
+            if let Some(token_id) = old_access_token {
+                tx.delete_access_token(token_id)?;
+            }
+            tx.insert_token(&refresh_token)?;
+            tx.insert_token(&access_token)?;
+            tx.update_session(&session)?;
+            tx.commit()?;
+            Ok(true)
+        };
+        match rotate() {
+            // replayed, the transaction was rolled back.
+            Ok(false) => return Err(Error::new(ErrorKind::NotAuthorized)),
+            Ok(true) => {
+                audit::record(
+                    &self.audit_sink,
+                    self.token_manager.clock(),
+                    AuditEventKind::TokenRefreshed,
+                    Some(session.user_id),
+                    None,
+                );
+                listener::notify(&self.listeners, |listener| listener.on_refresh(&session));
+                return Ok((refresh_token_secret, access_token_secret));
+            }
+            Err(err) => return Err(err.into()),
         }
     }
 
-    pub fn set_token_ids_none(&self, mut session: Session) -> Result<(), TokenManagerError> {
+    pub fn set_token_ids_none(&self, mut session: Session) -> Result<(), Error> {
         session.access_token = None;
         session.refresh_token = None;
 
         match self.harness.update(&session) {
-            Err(err) => return Err(TokenManagerError::Harness(err)),
+            Err(err) => return Err(err.into()),
             Ok(()) => Ok(()),
         }
     }
 
-    pub fn invalidate_session(&self, mut session: Session) -> Result<(), TokenManagerError> {
-        match session.refresh_token {
-            Some(token_id) => {
-                let _ = self.token_manager.delete_resfresh_token(token_id);
-                session.refresh_token = None;
+    /// Logs the user out of every session, e.g. after a password change.
+    pub fn invalidate_user_sessions(&self, user_id: i64) -> Result<(), Error> {
+        let sessions = self.get_user_sessions(user_id)?;
+
+        for session in sessions {
+            if session.refresh_token.is_some() || session.access_token.is_some() {
+                self.invalidate_session(session)?;
             }
-            None => {}
         }
-        match session.access_token {
-            Some(token_id) => {
-                let _ = self.token_manager.delete_access_token(token_id);
-                session.access_token = None;
+        Ok(())
+    }
+
+    pub fn invalidate_session(&self, mut session: Session) -> Result<(), Error> {
+        // either both tokens are gone and the session is empty, or nothing changed and the logout can be retried.
+        let mut logout = || -> Result<(), HarnessError> {
+            let tx = self.harness.transaction()?;
+            self.clear_session_tokens(&tx, &mut session)?;
+            tx.commit()
+        };
+        match logout() {
+            Err(err) => return Err(err.into()),
+            Ok(()) => {
+                self.session_revoked(&session);
+                Ok(())
             }
-            None => {}
-        }
-        match self.harness.update(&session) {
-            Err(err) => return Err(TokenManagerError::Harness(err)),
-            Ok(()) => Ok(()),
         }
     }
 
-    pub fn invalidate_access_token(&self, mut session: Session) -> Result<(), TokenManagerError> {
-        match session.access_token {
-            Some(token_id) => match self.token_manager.delete_access_token(token_id) {
-                Ok(()) => {
-                    session.access_token = None;
-                    return Ok(());
-                }
-                Err(err) => return Err(TokenManagerError::Harness(err)),
-            },
-            None => todo!(),
+    // flows that log the user out as part of a larger change, e.g. a password reset, run their writes in one
+    // transaction with `clear_session_tokens` and call `session_revoked` once it is committed.
+    pub(crate) fn transaction(&self) -> Result<V::Transaction, HarnessError> {
+        self.harness.transaction()
+    }
+
+    pub(crate) fn clear_session_tokens(
+        &self,
+        tx: &V::Transaction,
+        session: &mut Session,
+    ) -> Result<(), HarnessError> {
+        if let Some(token_id) = session.refresh_token.take() {
+            tx.delete_refresh_token(token_id)?;
+        }
+        if let Some(token_id) = session.access_token.take() {
+            tx.delete_access_token(token_id)?;
         }
+        tx.update_session(session)
+    }
+
+    pub(crate) fn session_revoked(&self, session: &Session) {
+        audit::record(
+            &self.audit_sink,
+            self.token_manager.clock(),
+            AuditEventKind::Logout,
+            Some(session.user_id),
+            Some(format!("session {}", session.id)),
+        );
+        listener::notify(&self.listeners, |listener| {
+            listener.on_session_revoked(session)
+        });
+    }
+
+    /// Drops the session's access token, the refresh token stays usable. Succeeds when there is none.
+    pub fn invalidate_access_token(&self, mut session: Session) -> Result<(), Error> {
+        let token_id = match session.access_token.take() {
+            Some(token_id) => token_id,
+            None => return Ok(()),
+        };
+
+        let invalidate = || -> Result<(), HarnessError> {
+            let tx = self.harness.transaction()?;
+            tx.delete_access_token(token_id)?;
+            tx.update_session(&session)?;
+            tx.commit()
+        };
+        Ok(invalidate()?)
     }
 }
 
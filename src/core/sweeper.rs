@@ -0,0 +1,181 @@
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::harness::{DbHarnessSession, DbHarnessToken};
+
+use chrono::TimeDelta;
+
+use super::{
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
+    Error,
+};
+
+pub struct SweeperConfig {
+    interval: TimeDelta,
+    batch_size: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for SweeperConfig {
+    fn default() -> Self {
+        return Self {
+            interval: TimeDelta::minutes(15),
+            batch_size: 500,
+            clock: Arc::new(SystemClock),
+        };
+    }
+}
+
+impl SweeperConfig {
+    /// Fails with `ErrorKind::InvalidConfig` when the interval is not positive or too long.
+    pub fn init<V: DbHarnessSession, Y: DbHarnessToken>(
+        &self,
+        session_harness: V,
+        token_harness: Y,
+    ) -> Result<Sweeper<V, Y>, Error> {
+        clock::check_ttl(self.clock.as_ref(), self.interval, "sweep interval")?;
+        return Ok(Sweeper {
+            session_harness,
+            token_harness,
+            interval: self.interval,
+            batch_size: self.batch_size,
+            clock: self.clock.clone(),
+        });
+    }
+
+    /// Time between two runs of a spawned sweeper, 15 minutes by default.
+    pub fn with_interval(mut self, interval: impl IntoTimeDelta) -> Self {
+        self.interval = interval.into_time_delta();
+        return self;
+    }
+
+    /// How many rows a single delete may remove. Smaller batches hold the database lock for shorter.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        return self;
+    }
+
+    /// Decides which tokens have expired by `clock` instead of the system time, see `MockClock`.
+    /// Hand it the same clock as the `AuthTokenManagerConfig`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        return self;
+    }
+}
+
+/// What a single sweep deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SweepReport {
+    pub tokens: usize,
+    pub sessions: usize,
+}
+
+/// Deletes expired and invalidated access and refresh tokens, then the sessions left without any token.
+///
+/// Call `sweep` from a cron job, or `spawn` a thread that sweeps every interval. The sweeper takes its own harnesses,
+/// built on the same database as the ones given to the `SessionManager`.
+pub struct Sweeper<V, Y>
+where
+    V: DbHarnessSession,
+    Y: DbHarnessToken,
+{
+    session_harness: V,
+    token_harness: Y,
+    interval: TimeDelta,
+    batch_size: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl<V, Y> Sweeper<V, Y>
+where
+    V: DbHarnessSession,
+    Y: DbHarnessToken,
+{
+    /// Sweeps once, batch after batch until nothing is left.
+    pub fn sweep(&self) -> Result<SweepReport, Error> {
+        let mut report = SweepReport::default();
+        // a batch size of 0 would never make progress.
+        let batch_size = self.batch_size.max(1);
+
+        let now = self.clock.now();
+        loop {
+            let deleted = self.token_harness.delete_expired(now, batch_size)?;
+            report.tokens += deleted;
+            if deleted < batch_size {
+                break;
+            }
+        }
+        // tokens go first, the sessions they leave empty are removed in the same sweep.
+        loop {
+            let deleted = self.session_harness.delete_dead(batch_size)?;
+            report.sessions += deleted;
+            if deleted < batch_size {
+                break;
+            }
+        }
+        return Ok(report);
+    }
+
+    pub fn interval(&self) -> TimeDelta {
+        return self.interval;
+    }
+}
+
+impl<V, Y> Sweeper<V, Y>
+where
+    V: DbHarnessSession + Send + 'static,
+    Y: DbHarnessToken + Send + 'static,
+{
+    /// Sweeps right away and then every interval on a background thread, until the handle is stopped or dropped.
+    /// A failed sweep is retried at the next interval.
+    pub fn spawn(self) -> SweeperHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        // init rejects an interval that is not positive.
+        let interval = self.interval.to_std().unwrap_or(Duration::MAX);
+
+        let thread = thread::spawn(move || loop {
+            let _ = self.sweep();
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                // told to stop, or the handle is gone.
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+
+        return SweeperHandle {
+            stop,
+            thread: Some(thread),
+        };
+    }
+}
+
+pub struct SweeperHandle {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SweeperHandle {
+    /// Stops the sweeper and waits for a sweep in progress to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SweeperHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
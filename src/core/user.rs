@@ -1,12 +1,24 @@
-use std::{error, fmt::Display};
+use std::sync::Arc;
 
-use crate::harness::{DbHarnessSession, DbHarnessToken, DbHarnessUser};
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::harness::{
+    DbHarnessSession, DbHarnessToken, DbHarnessUser, DbTransaction, HarnessError,
+};
 
 use super::{
-    auth_token::{AuthTokenError, TokenManagerError},
+    audit::{self, AuditEventKind, AuditSink},
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
     default_hash_fn, default_rng_salt_fn, default_verify_token_fn,
     id::{DefaultIdGenerator, IdGenerator},
+    listener::{self, AuthEventListener, Listeners},
+    password_policy::{
+        PasswordPolicy, PasswordPolicyError, PasswordPolicyErrorKind, PasswordViolation,
+    },
+    pepper::{self, Pepper},
+    scope::Scopes,
     session::{Session, SessionManager},
+    Error, ErrorKind, GenerateFn, HashFn, VerifyFn,
 };
 
 pub struct UserManagerConfig<T>
@@ -14,18 +26,37 @@ where
     T: IdGenerator,
 {
     id_generator: T,
-    salt_fn: fn() -> String,
-    hash_fn: fn(&str, &str) -> Result<String, AuthTokenError>,
-    verify_pass_fn: fn(&str, &str) -> Result<(), AuthTokenError>,
+    salt_fn: GenerateFn,
+    hash_fn: HashFn,
+    verify_pass_fn: VerifyFn,
+    password_policy: PasswordPolicy,
+    // how many recent passwords, the current one included, can not be reused. 0 turns the history off.
+    password_history: usize,
+    // older history entries are dropped even if there are fewer than `password_history`.
+    password_history_retention: Option<TimeDelta>,
+    // how long until a password has to be changed, None if passwords do not expire.
+    password_max_age: Option<TimeDelta>,
+    pepper: Option<Arc<Pepper>>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    listeners: Listeners,
+    clock: Arc<dyn Clock>,
 }
 
 impl UserManagerConfig<DefaultIdGenerator> {
     pub fn default() -> Self {
         Self {
             id_generator: DefaultIdGenerator {},
-            salt_fn: default_rng_salt_fn,
-            hash_fn: default_hash_fn,
-            verify_pass_fn: default_verify_token_fn,
+            salt_fn: Arc::new(default_rng_salt_fn),
+            hash_fn: Arc::new(default_hash_fn),
+            verify_pass_fn: Arc::new(default_verify_token_fn),
+            password_policy: PasswordPolicy::default(),
+            password_history: 0,
+            password_history_retention: None,
+            password_max_age: None,
+            pepper: None,
+            audit_sink: None,
+            listeners: Vec::new(),
+            clock: Arc::new(SystemClock),
         }
     }
 }
@@ -34,22 +65,115 @@ impl<T> UserManagerConfig<T>
 where
     T: IdGenerator + Copy,
 {
-    pub fn init<V: DbHarnessUser>(&self, harness: V) -> UserManager<T, V> {
-        UserManager {
+    /// Fails with `ErrorKind::InvalidConfig` when no password could satisfy the policy, or when the password max age
+    /// or history retention is not positive or too long.
+    pub fn init<V: DbHarnessUser>(&self, harness: V) -> Result<UserManager<T, V>, Error> {
+        self.password_policy.validate()?;
+        if let Some(max_age) = self.password_max_age {
+            clock::check_ttl(self.clock.as_ref(), max_age, "password max age")?;
+        }
+        if let Some(retention) = self.password_history_retention {
+            clock::check_ttl(self.clock.as_ref(), retention, "password history retention")?;
+        }
+
+        Ok(UserManager {
             id_generator: self.id_generator,
-            hash_fn: self.hash_fn,
-            verify_pass_fn: self.verify_pass_fn,
-            salt_fn: self.salt_fn,
+            hash_fn: self.hash_fn.clone(),
+            verify_pass_fn: self.verify_pass_fn.clone(),
+            salt_fn: self.salt_fn.clone(),
+            password_policy: self.password_policy.clone(),
+            password_history: self.password_history,
+            password_history_retention: self.password_history_retention,
+            password_max_age: self.password_max_age,
+            pepper: self.pepper.clone(),
+            audit_sink: self.audit_sink.clone(),
+            listeners: self.listeners.clone(),
+            clock: self.clock.clone(),
             harness,
-        }
+        })
+    }
+
+    /// Generates the salt for every password hash. The closure may capture state, e.g. a seeded generator.
+    pub fn with_salt_fn(mut self, salt_fn: GenerateFn) -> Self {
+        self.salt_fn = salt_fn;
+        self
+    }
+
+    /// Hashes passwords, called with the password and a salt from the salt function. Existing hashes have to stay
+    /// verifiable by the verify function.
+    pub fn with_hash_fn(mut self, hash_fn: HashFn) -> Self {
+        self.hash_fn = hash_fn;
+        self
+    }
+
+    /// Checks a password against a hash made by the hash function.
+    pub fn with_verify_fn(mut self, verify_fn: VerifyFn) -> Self {
+        self.verify_pass_fn = verify_fn;
+        self
+    }
+
+    pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+        self.password_policy = password_policy;
+        self
+    }
+
+    /// Rejects a new password that matches one of the user's last `depth` passwords, the current one included.
+    pub fn with_password_history(mut self, depth: usize) -> Self {
+        self.password_history = depth;
+        self
+    }
+
+    /// Forget previous passwords after `retention`, regardless of the history depth.
+    pub fn with_password_history_retention(mut self, retention: impl IntoTimeDelta) -> Self {
+        self.password_history_retention = Some(retention.into_time_delta());
+        self
+    }
+
+    /// Passwords older than `max_age` have to be changed at the next login, see `UserManager::login`.
+    pub fn with_password_max_age(mut self, max_age: impl IntoTimeDelta) -> Self {
+        self.password_max_age = Some(max_age.into_time_delta());
+        self
+    }
+
+    /// Keys password hashes with `pepper`. Passwords hashed without its current key are rehashed at the next
+    /// login, see `Pepper`.
+    pub fn with_pepper(mut self, pepper: Pepper) -> Self {
+        self.pepper = Some(Arc::new(pepper));
+        self
+    }
+
+    /// Records account creation, logins, password changes, bans and role changes, see `AuditSink`.
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Can be called more than once, see `AuthEventListener`.
+    pub fn with_listener(mut self, listener: Arc<dyn AuthEventListener>) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Dates password changes and checks their age against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 
     pub fn with_id_gen<X: IdGenerator + Copy>(&self, id_generator: X) -> UserManagerConfig<X> {
         return UserManagerConfig {
             id_generator,
-            salt_fn: self.salt_fn,
-            verify_pass_fn: self.verify_pass_fn,
-            hash_fn: self.hash_fn,
+            salt_fn: self.salt_fn.clone(),
+            verify_pass_fn: self.verify_pass_fn.clone(),
+            hash_fn: self.hash_fn.clone(),
+            password_policy: self.password_policy.clone(),
+            password_history: self.password_history,
+            password_history_retention: self.password_history_retention,
+            password_max_age: self.password_max_age,
+            pepper: self.pepper.clone(),
+            audit_sink: self.audit_sink.clone(),
+            listeners: self.listeners.clone(),
+            clock: self.clock.clone(),
         };
     }
 }
@@ -61,9 +185,17 @@ where
 {
     id_generator: T,
     harness: V,
-    salt_fn: fn() -> String,
-    hash_fn: fn(&str, &str) -> Result<String, AuthTokenError>,
-    verify_pass_fn: fn(&str, &str) -> Result<(), AuthTokenError>,
+    salt_fn: GenerateFn,
+    hash_fn: HashFn,
+    verify_pass_fn: VerifyFn,
+    password_policy: PasswordPolicy,
+    password_history: usize,
+    password_history_retention: Option<TimeDelta>,
+    password_max_age: Option<TimeDelta>,
+    pepper: Option<Arc<Pepper>>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    listeners: Listeners,
+    clock: Arc<dyn Clock>,
 }
 
 impl<T, V> UserManager<T, V>
@@ -78,26 +210,80 @@ where
         role: Role,
         public: Option<Pu>,
         private: Option<Pr>,
-    ) -> Result<User<Pu, Pr>, UserManagerError>
+    ) -> Result<User<Pu, Pr>, Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.insert_new_user(username, None, pwd, role, public, private)
+    }
+
+    /// Same as `create_user`, the account starts out as `PendingVerification` until the email is verified
+    /// (see `VerificationManager`).
+    pub fn create_user_with_email<Pu, Pr>(
+        &self,
+        username: String,
+        email: String,
+        pwd: String,
+        role: Role,
+        public: Option<Pu>,
+        private: Option<Pr>,
+    ) -> Result<User<Pu, Pr>, Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.insert_new_user(username, Some(email), pwd, role, public, private)
+    }
+
+    // a user created with an email has to verify it before logging in.
+    fn insert_new_user<Pu, Pr>(
+        &self,
+        username: String,
+        email: Option<String>,
+        pwd: String,
+        role: Role,
+        public: Option<Pu>,
+        private: Option<Pr>,
+    ) -> Result<User<Pu, Pr>, Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
+        self.password_policy.check(&username, &pwd)?;
+        listener::check(&self.listeners, |listener| {
+            listener.before_user_created(&username)
+        })?;
+
         let id = self.id_generator.new_u64();
 
-        let salt = (self.salt_fn)();
-        let secret = (self.hash_fn)(&pwd, &salt)?;
+        let secret = self.hash_pwd(&pwd)?;
 
-        let user = User::new(
+        let mut user = User::new(
             i64::from_be_bytes(id.to_be_bytes()),
             username,
             secret,
             role,
             public,
             private,
+            self.clock.now(),
         )?;
+        if email.is_some() {
+            user.email = email;
+            user.status = AccountStatus::PendingVerification;
+        }
 
         self.harness.insert(&user)?;
+        audit::record(
+            &self.audit_sink,
+            self.clock.as_ref(),
+            AuditEventKind::UserCreated,
+            Some(user.id),
+            None,
+        );
+        listener::notify(&self.listeners, |listener| {
+            listener.on_user_created(user.id)
+        });
         return Ok(user);
     }
 
@@ -106,7 +292,25 @@ where
         session_manager: &SessionManager<Id, Sh, Th>,
         user: &User<Pu, Pr>,
         pwd: &str,
-    ) -> Result<(Session, String, String), UserManagerError>
+    ) -> Result<(Session, String, String), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+        Id: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        self.login_with_scopes(session_manager, user, pwd, Scopes::new())
+    }
+
+    /// Same as `login`, the tokens of the new session carry `scopes`.
+    pub fn login_with_scopes<Pu, Pr, Id, Sh, Th>(
+        &self,
+        session_manager: &SessionManager<Id, Sh, Th>,
+        user: &User<Pu, Pr>,
+        pwd: &str,
+        scopes: Scopes,
+    ) -> Result<(Session, String, String), Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
@@ -119,10 +323,10 @@ where
 
         match user_res {
             // harness error occured, propogate the err.
-            Err(err) => return Err(err.into()),
+            Err(err) => return Err(err),
             Ok(user_opt) => match user_opt {
                 // user not found
-                None => return Err(UserManagerError::new(UserManagerErrorKind::UserNotFound)),
+                None => return Err(Error::new(ErrorKind::UserNotFound)),
                 Some(q_user) => {
                     // assign user, continue to verify password
                     user = q_user;
@@ -132,23 +336,97 @@ where
 
         match self.verify_pwd(&user, pwd) {
             // Error validating the user, propogate the Error.
-            Err(err) => return Err(err.into()),
+            Err(err) => {
+                self.login_failed(user.id, err.kind.to_string());
+                return Err(err);
+            }
             Ok(_) => {
-                let sess_res = session_manager.new_session(user.id);
+                self.rehash_pwd(&user, pwd)?;
+                // only checked once the password is verified, the status is not revealed to anyone else.
+                self.check_login(&user)?;
+
+                let sess_res = session_manager.new_scoped_session(user.id, scopes);
                 match sess_res {
-                    Ok(res) => return Ok(res),
-                    Err(err) => return Err(err.into()),
+                    Ok(res) => {
+                        self.logged_in(&res.0);
+                        return Ok(res);
+                    }
+                    Err(err) => return Err(err),
                 }
             }
         };
     }
 
+    /// Answers the `PasswordChangeRequired` challenge of `login`: verifies the current password, sets the new
+    /// one and opens the session the login was refused.
+    pub fn login_with_password_change<Pu, Pr, Id, Sh, Th>(
+        &self,
+        session_manager: &SessionManager<Id, Sh, Th>,
+        user: &User<Pu, Pr>,
+        pwd: &str,
+        new_pwd: String,
+    ) -> Result<(Session, String, String), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+        Id: IdGenerator,
+        Sh: DbHarnessSession,
+        Th: DbHarnessToken,
+    {
+        let user: User<Pu, Pr> = match self.get_user(&user.id)? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::UserNotFound)),
+        };
+
+        if let Err(err) = self.verify_pwd(&user, pwd) {
+            self.login_failed(user.id, err.kind.to_string());
+            return Err(err);
+        }
+        self.check_account(&user)?;
+        self.before_login(user.id)?;
+
+        let user_id = user.id;
+        self.update_password(user, new_pwd)?;
+
+        match session_manager.new_session(user_id) {
+            Ok(res) => {
+                self.logged_in(&res.0);
+                Ok(res)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Why the user has to change their password before logging in, None if they do not.
+    pub fn password_change_required<Pu, Pr>(
+        &self,
+        user: &User<Pu, Pr>,
+    ) -> Option<PasswordChangeReason>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        if user.must_change_password {
+            return Some(PasswordChangeReason::Required);
+        }
+
+        // passwords set before the change time was recorded have no age to expire by.
+        let (max_age, changed_at) = match (self.password_max_age, user.password_changed_at) {
+            (Some(max_age), Some(changed_at)) => (max_age, changed_at),
+            _ => return None,
+        };
+        match changed_at.checked_add_signed(max_age) {
+            Some(expires) if expires > self.clock.now() => None,
+            _ => Some(PasswordChangeReason::Expired),
+        }
+    }
+
     pub fn logout<Pu, Pr, Id, Sh, Th>(
         &self,
         session_manager: &SessionManager<Id, Sh, Th>,
         user: &User<Pu, Pr>,
         user_token_atmpt: &str,
-    ) -> Result<(), UserManagerError>
+    ) -> Result<(), Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
@@ -167,10 +445,10 @@ where
                                 user.id,
                                 user_token_atmpt,
                             ) {
-                                // token was verified.
-                                Ok(()) => return Ok(()),
+                                // token was verified, the session is invalidated below.
+                                Ok(()) => {}
                                 // token was invalid.
-                                Err(err) => return Err(err.into()),
+                                Err(err) => return Err(err),
                             }
                         }
                         None => {
@@ -189,72 +467,388 @@ where
                              */
                         }
                     }
-                    match session_manager.invalidate_session(session) {
-                        Ok(()) => return Ok(()),
-                        Err(err) => {
-                            return Err(UserManagerError::new(
-                                UserManagerErrorKind::SessionInvalidation(err),
-                            ))
-                        }
-                    }
+                    return session_manager.invalidate_session(session);
                 }
-                Err(err) => return Err(UserManagerError::new(UserManagerErrorKind::Harness(err))),
+                Err(err) => return Err(err),
             },
-            None => {
-                return Err(UserManagerError::new(
-                    UserManagerErrorKind::AlreadyLoggedOut,
-                ))
+            None => return Err(Error::new(ErrorKind::AlreadyLoggedOut)),
+        }
+    }
+
+    pub fn verify_pwd<Pu, Pr>(&self, user: &User<Pu, Pr>, pwd: &str) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        pepper::verify_secret(
+            self.pepper.as_deref(),
+            &self.verify_pass_fn,
+            pwd,
+            &user.secret,
+        )
+    }
+
+    fn hash_pwd(&self, pwd: &str) -> Result<String, Error> {
+        let salt = (self.salt_fn)();
+        pepper::hash_secret(self.pepper.as_deref(), &self.hash_fn, pwd, &salt)
+    }
+
+    // moves a password over to the current pepper key, only possible while the plain password is at hand.
+    fn rehash_pwd<Pu, Pr>(&self, user: &User<Pu, Pr>, pwd: &str) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        if !pepper::needs_rehash(self.pepper.as_deref(), &user.secret) {
+            return Ok(());
+        }
+        let mut user = user.clone();
+        user.set_secret(self.hash_pwd(pwd)?);
+        self.harness.update(&user)?;
+        Ok(())
+    }
+
+    pub fn update_user<Pu, Pr>(&self, user: User<Pu, Pr>) -> Result<usize, Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        // bans and role changes are only looked for when someone is interested, that saves the extra read.
+        let previous: Option<User<Pu, Pr>> =
+            match self.audit_sink.is_some() || !self.listeners.is_empty() {
+                true => self.harness.read(user.id)?,
+                false => None,
+            };
+        let ban_change = previous
+            .as_ref()
+            .filter(|previous| previous.ban != user.ban)
+            .map(|_| user.ban);
+        let role_change = previous
+            .as_ref()
+            .filter(|previous| previous.role.as_str() != user.role.as_str())
+            .map(|previous| previous.role.clone());
+
+        if ban_change == Some(true) {
+            listener::check(&self.listeners, |listener| listener.before_ban(user.id))?;
+        }
+        if let Some(from) = &role_change {
+            listener::check(&self.listeners, |listener| {
+                listener.before_role_change(user.id, from, &user.role)
+            })?;
+        }
+
+        let res = self.harness.update(&user)?;
+
+        match ban_change {
+            Some(true) => {
+                audit::record(
+                    &self.audit_sink,
+                    self.clock.as_ref(),
+                    AuditEventKind::Banned,
+                    Some(user.id),
+                    None,
+                );
+                listener::notify(&self.listeners, |listener| listener.on_banned(user.id));
             }
+            Some(false) => {
+                audit::record(
+                    &self.audit_sink,
+                    self.clock.as_ref(),
+                    AuditEventKind::Unbanned,
+                    Some(user.id),
+                    None,
+                );
+                listener::notify(&self.listeners, |listener| listener.on_unbanned(user.id));
+            }
+            None => {}
+        }
+        if let Some(from) = &role_change {
+            audit::record(
+                &self.audit_sink,
+                self.clock.as_ref(),
+                AuditEventKind::RoleChanged,
+                Some(user.id),
+                Some(format!("{} -> {}", from.as_str(), user.role.as_str())),
+            );
+            listener::notify(&self.listeners, |listener| {
+                listener.on_role_changed(user.id, from, &user.role)
+            });
         }
+        return Ok(res);
+    }
+
+    pub(crate) fn email_taken(&self, email: &str, user_id: i64) -> Result<bool, Error> {
+        Ok(self.harness.email_taken(email, user_id)?)
     }
 
-    pub fn verify_pwd<Pu, Pr>(&self, user: &User<Pu, Pr>, pwd: &str) -> Result<(), AuthTokenError>
+    // see `VerificationManager::verify_email`, false when the user's address is no longer `email`.
+    pub(crate) fn verify_email(&self, user_id: i64, email: &str) -> Result<bool, Error> {
+        Ok(self.harness.verify_email(user_id, email)?)
+    }
+
+    // see `VerificationManager::confirm_email_change`, false when the user is gone or the address was taken.
+    pub(crate) fn change_email(&self, user_id: i64, email: &str) -> Result<bool, Error> {
+        Ok(self.harness.change_email(user_id, email)?)
+    }
+
+    // asks the listeners once every other login check passed, a veto counts as a failed login.
+    fn before_login(&self, user_id: i64) -> Result<(), Error> {
+        if let Err(veto) =
+            listener::check(&self.listeners, |listener| listener.before_login(user_id))
+        {
+            self.login_failed(user_id, veto.to_string());
+            return Err(veto.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn logged_in(&self, session: &Session) {
+        audit::record(
+            &self.audit_sink,
+            self.clock.as_ref(),
+            AuditEventKind::LoginSucceeded,
+            Some(session.user_id()),
+            None,
+        );
+        listener::notify(&self.listeners, |listener| {
+            listener.on_login(session.user_id(), session)
+        });
+    }
+
+    // everything a login checks besides the credential, shared by every way to log in: the account has to be
+    // active and not banned, the password must not be due for a change and no listener may veto the login.
+    pub(crate) fn check_login<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.check_account(user)?;
+        // no session until the password is changed, see `login_with_password_change`.
+        if let Some(reason) = self.password_change_required(user) {
+            self.login_failed(user.id, reason.as_str().to_string());
+            return Err(Error::new(ErrorKind::PasswordChangeRequired(reason)));
+        }
+        self.before_login(user.id)
+    }
+
+    // refuses banned and inactive accounts.
+    fn check_account<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
-        (self.verify_pass_fn)(pwd, &user.secret)
+        if let Err(err) = user.check_status() {
+            let reason = match user.ban {
+                true => "banned",
+                false => user.status.as_str(),
+            };
+            self.login_failed(user.id, reason.to_string());
+            return Err(err);
+        }
+        Ok(())
     }
 
-    pub fn update_user<Pu, Pr>(&self, user: User<Pu, Pr>) -> Result<usize, Box<dyn error::Error>>
+    fn login_failed(&self, user_id: i64, reason: String) {
+        audit::record(
+            &self.audit_sink,
+            self.clock.as_ref(),
+            AuditEventKind::LoginFailed,
+            Some(user_id),
+            Some(reason),
+        );
+        listener::notify(&self.listeners, |listener| {
+            listener.on_login_failed(user_id)
+        });
+    }
+
+    /// Checks a password against the configured policy without setting it, e.g. to validate a form early.
+    pub fn check_password(&self, username: &str, pwd: &str) -> Result<(), Error> {
+        Ok(self.password_policy.check(username, pwd)?)
+    }
+
+    /// Same as `check_password`, and also rejects passwords from the user's password history.
+    pub fn check_password_change<Pu, Pr>(&self, user: &User<Pu, Pr>, pwd: &str) -> Result<(), Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
-        return self.harness.update(&user);
+        self.password_policy.check(&user.username, pwd)?;
+
+        if self.password_history == 0 {
+            return Ok(());
+        }
+
+        let mut previous = vec![user.secret.clone()];
+        previous.extend(
+            self.harness
+                .read_password_history(user.id, self.password_history - 1)?,
+        );
+        // entries that fail to parse can not match, they do not block the change.
+        if previous.iter().any(|secret| {
+            pepper::verify_secret(self.pepper.as_deref(), &self.verify_pass_fn, pwd, secret).is_ok()
+        }) {
+            return Err(
+                PasswordPolicyError::new(PasswordPolicyErrorKind::Violations(vec![
+                    PasswordViolation::Reused,
+                ]))
+                .into(),
+            );
+        }
+        Ok(())
     }
 
     pub fn update_password<Pu, Pr>(
         &self,
         mut user: User<Pu, Pr>,
         pwd: String,
-    ) -> Result<usize, Box<dyn error::Error>>
+    ) -> Result<usize, Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
-        let salt = (self.salt_fn)();
-        let secret = (self.hash_fn)(&pwd, &salt)?;
+        let (previous, now) = self.prepare_password_change(&mut user, &pwd)?;
+        let res = self.harness.update(&user)?;
+
+        if self.password_history > 0 {
+            self.harness
+                .insert_password_history(user.id, &previous, now)?;
+            self.harness.prune_password_history(
+                user.id,
+                self.password_history - 1,
+                self.password_history_cutoff(now),
+            )?;
+        }
+
+        self.password_changed(user.id);
+        return Ok(res);
+    }
+
+    // checks and hashes `pwd` and sets it on `user` without storing anything. returns the hash it replaced and the
+    // time of the change, `update_password` and the password reset store them each their own way.
+    pub(crate) fn prepare_password_change<Pu, Pr>(
+        &self,
+        user: &mut User<Pu, Pr>,
+        pwd: &str,
+    ) -> Result<(String, DateTime<Utc>), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.check_password_change(user, pwd)?;
+        listener::check(&self.listeners, |listener| {
+            listener.before_password_change(user.id)
+        })?;
+
+        let secret = self.hash_pwd(pwd)?;
 
+        let previous = user.secret.clone();
         user.set_secret(secret);
+        let now = self.clock.now();
+        user.password_changed_at = Some(now);
+        user.must_change_password = false;
+        Ok((previous, now))
+    }
+
+    // the writes of `update_password` inside a transaction, see `prepare_password_change`.
+    pub(crate) fn store_password_change<Tx, Pu, Pr>(
+        &self,
+        tx: &Tx,
+        user: &User<Pu, Pr>,
+        previous: &str,
+        now: DateTime<Utc>,
+    ) -> Result<usize, HarnessError>
+    where
+        Tx: DbTransaction,
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let res = tx.update_user(user)?;
+
+        if self.password_history > 0 {
+            tx.insert_password_history(user.id, previous, now)?;
+            tx.prune_password_history(
+                user.id,
+                self.password_history - 1,
+                self.password_history_cutoff(now),
+            )?;
+        }
+        Ok(res)
+    }
+
+    pub(crate) fn password_changed(&self, user_id: i64) {
+        audit::record(
+            &self.audit_sink,
+            self.clock.as_ref(),
+            AuditEventKind::PasswordChanged,
+            Some(user_id),
+            None,
+        );
+        listener::notify(&self.listeners, |listener| {
+            listener.on_password_changed(user_id)
+        });
+    }
 
-        return self.harness.update(&user);
+    fn password_history_cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.password_history_retention
+            .and_then(|retention| now.checked_sub_signed(retention))
     }
 
-    pub fn get_user<Pu, Pr>(&self, id: &i64) -> Result<Option<User<Pu, Pr>>, Box<dyn error::Error>>
+    pub fn get_user<Pu, Pr>(&self, id: &i64) -> Result<Option<User<Pu, Pr>>, Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
-        return self.harness.read(*id);
+        Ok(self.harness.read(*id)?)
     }
 
-    pub fn delete_user<Pu, Pr>(&self, id: i64) -> Result<(), Box<dyn error::Error>>
+    pub fn get_user_by_username<Pu, Pr>(
+        &self,
+        username: &str,
+    ) -> Result<Option<User<Pu, Pr>>, Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        Ok(self.harness.read_by_username(username)?)
+    }
+
+    /// A page of users ordered by id.
+    pub fn list_users<Pu, Pr>(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User<Pu, Pr>>, Error>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
-        return self.harness.delete(id);
+        Ok(self.harness.list(offset, limit)?)
+    }
+
+    /// Users whose username or email contains `term`.
+    pub fn search_users<Pu, Pr>(&self, term: &str, limit: usize) -> Result<Vec<User<Pu, Pr>>, Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        Ok(self.harness.search(term, limit)?)
+    }
+
+    pub fn delete_user<Pu, Pr>(&self, id: i64) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.harness.delete(id)?;
+        audit::record(
+            &self.audit_sink,
+            self.clock.as_ref(),
+            AuditEventKind::UserDeleted,
+            Some(id),
+            None,
+        );
+        listener::notify(&self.listeners, |listener| listener.on_user_deleted(id));
+        return Ok(());
     }
 }
 
@@ -271,6 +865,12 @@ where
     ban: bool,
     groups: Groups,
     role: Role,
+    email: Option<String>,
+    status: AccountStatus,
+    // None for passwords set before the change time was recorded.
+    password_changed_at: Option<DateTime<Utc>>,
+    // set by an admin, the user has to pick a new password at the next login.
+    must_change_password: bool,
     public: Option<Pu>,
     private: Option<Pr>,
 }
@@ -280,6 +880,7 @@ where
     Pu: PublicUserMeta,
     Pr: PrivateUserMeta,
 {
+    /// A new active user whose password was set at `now`.
     pub fn new(
         id: i64,
         username: String,
@@ -287,7 +888,8 @@ where
         role: Role,
         public: Option<Pu>,
         private: Option<Pr>,
-    ) -> Result<Self, Box<dyn error::Error>> {
+        now: DateTime<Utc>,
+    ) -> Result<Self, Error> {
         return Ok(Self {
             id,
             username,
@@ -296,6 +898,10 @@ where
             session_id: None,
             groups: Groups::new(),
             role,
+            email: None,
+            status: AccountStatus::Active,
+            password_changed_at: Some(now),
+            must_change_password: false,
             public,
             private,
         });
@@ -309,6 +915,10 @@ where
         ban: bool,
         groups: Groups,
         role: Role,
+        email: Option<String>,
+        status: AccountStatus,
+        password_changed_at: Option<DateTime<Utc>>,
+        must_change_password: bool,
         public: Option<Pu>,
         private: Option<Pr>,
     ) -> Self {
@@ -320,6 +930,10 @@ where
             ban,
             groups,
             role,
+            email,
+            status,
+            password_changed_at,
+            must_change_password,
             public,
             private,
         };
@@ -373,6 +987,50 @@ where
         return self.session_id;
     }
 
+    pub fn email(&self) -> Option<&str> {
+        return self.email.as_deref();
+    }
+
+    /// Applies immediately. Changes requested by the user go through `VerificationManager::request_email_change`.
+    pub fn set_email(&mut self, email: Option<String>) {
+        self.email = email;
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        return self.status;
+    }
+
+    pub fn set_status(&mut self, status: AccountStatus) {
+        self.status = status;
+    }
+
+    pub fn password_changed_at(&self) -> Option<DateTime<Utc>> {
+        return self.password_changed_at;
+    }
+
+    pub fn must_change_password(&self) -> bool {
+        return self.must_change_password;
+    }
+
+    /// Makes `login` refuse the user until they picked a new password, persisted with `update_user`.
+    pub fn set_must_change_password(&mut self, must_change_password: bool) {
+        self.must_change_password = must_change_password;
+    }
+
+    /// Ok for active accounts that are not banned, otherwise the error `login` refuses the account with.
+    pub fn check_status(&self) -> Result<(), Error> {
+        if self.ban {
+            return Err(Error::new(ErrorKind::Banned));
+        }
+        let kind = match self.status {
+            AccountStatus::Active => return Ok(()),
+            AccountStatus::PendingVerification => ErrorKind::PendingVerification,
+            AccountStatus::Suspended => ErrorKind::Suspended,
+            AccountStatus::Deactivated => ErrorKind::Deactivated,
+        };
+        return Err(Error::new(kind));
+    }
+
     pub fn public(&self) -> Option<Pu> {
         return self.public.clone();
     }
@@ -400,6 +1058,55 @@ impl<Pu: PublicUserMeta, Pr: PrivateUserMeta> User<Pu, Pr> {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordChangeReason {
+    // flagged with `User::set_must_change_password`.
+    Required,
+    // older than `UserManagerConfig::with_password_max_age`.
+    Expired,
+}
+
+impl PasswordChangeReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Required => "password_change_required",
+            Self::Expired => "password_expired",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountStatus {
+    // created with an email that was not verified yet.
+    PendingVerification,
+    Active,
+    // temporarily locked, e.g. by an admin.
+    Suspended,
+    // closed by the user.
+    Deactivated,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &str {
+        return match self {
+            Self::PendingVerification => "pending_verification",
+            Self::Active => "active",
+            Self::Suspended => "suspended",
+            Self::Deactivated => "deactivated",
+        };
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        return match status {
+            "pending_verification" => Some(Self::PendingVerification),
+            "active" => Some(Self::Active),
+            "suspended" => Some(Self::Suspended),
+            "deactivated" => Some(Self::Deactivated),
+            _ => None,
+        };
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Role {
     pub name: String,
@@ -480,25 +1187,17 @@ impl Groups {
     }
 
     pub fn remove_group(&mut self, group: Group) {
-        let mut idx = 0;
-        for c_group in &self.groups {
-            if *c_group == group {
-                return;
-            }
-            idx += 1;
-        }
+        self.groups.retain(|c_group| *c_group != group);
+    }
 
-        self.groups.remove(idx);
+    pub fn iter(&self) -> std::slice::Iter<'_, Group> {
+        return self.groups.iter();
     }
 
+    // comma separated, the harnesses split on "," when reading the groups back.
     pub fn to_string(&self) -> String {
-        let mut string = String::new();
-
-        for group in &self.groups {
-            string += group.as_str()
-        }
-
-        return string;
+        let names: Vec<&str> = self.groups.iter().map(|group| group.as_str()).collect();
+        return names.join(",");
     }
 }
 
@@ -506,53 +1205,7 @@ pub trait PublicUserMeta: Clone {}
 
 pub trait PrivateUserMeta: Clone {}
 
-#[derive(Debug)]
-pub enum UserManagerErrorKind {
-    // Error can be in the harness, or in the token validation. This will occure only after the user and token are verified and the logout fails.
-    SessionInvalidation(TokenManagerError),
-    AlreadyLoggedOut,
-    // Error resides strictly in the Token, not in the harness.
-    Token(AuthTokenError),
-    Harness(Box<dyn error::Error>),
-    UserNotFound,
-}
-
-impl From<TokenManagerError> for UserManagerError {
-    fn from(value: TokenManagerError) -> Self {
-        match value {
-            TokenManagerError::AuthToken(err) => Self::new(UserManagerErrorKind::Token(err)),
-            TokenManagerError::Harness(err) => Self::new(UserManagerErrorKind::Harness(err)),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct UserManagerError {
-    pub kind: UserManagerErrorKind,
-}
-
-impl Display for UserManagerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return write!(f, "{}", self);
-    }
-}
-
-impl From<Box<dyn error::Error>> for UserManagerError {
-    fn from(value: Box<dyn error::Error>) -> Self {
-        return UserManagerError::new(UserManagerErrorKind::Harness(value));
-    }
-}
-
-impl From<AuthTokenError> for UserManagerError {
-    fn from(value: AuthTokenError) -> Self {
-        return UserManagerError::new(UserManagerErrorKind::Token(value));
-    }
-}
-
-impl UserManagerError {
-    pub fn new(kind: UserManagerErrorKind) -> Self {
-        return Self { kind };
-    }
-}
+// for callers that only need the core user fields, e.g. reading a role.
+impl PublicUserMeta for () {}
 
-impl error::Error for UserManagerError {}
+impl PrivateUserMeta for () {}
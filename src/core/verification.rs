@@ -0,0 +1,234 @@
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+
+use crate::harness::{DbHarnessOneTimeToken, DbHarnessUser};
+
+use super::{
+    clock::{self, Clock, IntoTimeDelta, SystemClock},
+    default_hash_fn, default_rng_salt_fn, default_rng_token_fn, default_verify_token_fn,
+    id::{DefaultIdGenerator, IdGenerator},
+    one_time::{Delivery, OneTimePurpose, OneTimeTokens, TokenDelivery},
+    user::{PrivateUserMeta, PublicUserMeta, User, UserManager},
+    Error, ErrorKind,
+};
+
+// Email verification. A new account created through `UserManager::create_user_with_email` stays
+// `PendingVerification` until the token sent to its address is confirmed. A change of address is only applied
+// once the token sent to the new address is confirmed, the old address stays in place until then.
+pub struct VerificationConfig<T>
+where
+    T: IdGenerator,
+{
+    id_generator: T,
+    ttl: TimeDelta,
+    tokens: OneTimeTokens,
+}
+
+impl Default for VerificationConfig<DefaultIdGenerator> {
+    fn default() -> Self {
+        Self {
+            id_generator: DefaultIdGenerator {},
+            ttl: TimeDelta::days(1),
+            tokens: OneTimeTokens {
+                salt_fn: Arc::new(default_rng_salt_fn),
+                token_fn: Arc::new(default_rng_token_fn),
+                hash_fn: Arc::new(default_hash_fn),
+                verify_fn: Arc::new(default_verify_token_fn),
+                clock: Arc::new(SystemClock),
+            },
+        }
+    }
+}
+
+impl<T> VerificationConfig<T>
+where
+    T: IdGenerator + Copy,
+{
+    /// How long a verification or email change token can be redeemed, a day by default.
+    pub fn with_ttl(mut self, ttl: impl IntoTimeDelta) -> Self {
+        self.ttl = ttl.into_time_delta();
+        self
+    }
+
+    /// Dates verification tokens and checks their expiry against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.tokens.clock = clock;
+        self
+    }
+
+    /// Fails with `ErrorKind::InvalidConfig` when the ttl is not positive or too long.
+    pub fn init<V: DbHarnessOneTimeToken, D: TokenDelivery>(
+        &self,
+        harness: V,
+        delivery: D,
+    ) -> Result<VerificationManager<T, V, D>, Error> {
+        clock::check_ttl(self.tokens.clock.as_ref(), self.ttl, "verification ttl")?;
+        Ok(VerificationManager {
+            id_generator: self.id_generator,
+            ttl: self.ttl,
+            tokens: self.tokens.clone(),
+            harness,
+            delivery,
+        })
+    }
+}
+
+pub struct VerificationManager<T, V, D>
+where
+    T: IdGenerator,
+    V: DbHarnessOneTimeToken,
+    D: TokenDelivery,
+{
+    id_generator: T,
+    ttl: TimeDelta,
+    tokens: OneTimeTokens,
+    harness: V,
+    delivery: D,
+}
+
+impl<T, V, D> VerificationManager<T, V, D>
+where
+    T: IdGenerator,
+    V: DbHarnessOneTimeToken,
+    D: TokenDelivery,
+{
+    /// Sends a verification token to the user's current address. Also used to resend a lost token,
+    /// the previous one stops working.
+    pub fn request_verification<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let email = match user.email() {
+            Some(email) => email,
+            None => return Err(Error::new(ErrorKind::MissingEmail)),
+        };
+
+        self.send(user, OneTimePurpose::EmailVerification, email)
+    }
+
+    /// Sends a token to `new_email`. The user's address is left untouched until `confirm_email_change`.
+    pub fn request_email_change<Pu, Pr>(
+        &self,
+        user: &User<Pu, Pr>,
+        new_email: &str,
+    ) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.send(user, OneTimePurpose::EmailChange, new_email)
+    }
+
+    /// Confirms the address of the user, a `PendingVerification` account becomes `Active`.
+    /// Suspended and deactivated accounts keep their status.
+    pub fn verify_email<Ui, Uh>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        token: &str,
+    ) -> Result<(), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+    {
+        let token = self
+            .tokens
+            .redeem(&self.harness, token, OneTimePurpose::EmailVerification)?;
+        let user = self.user(user_manager, token.user_id())?;
+
+        // the token was sent to an address the user has since moved away from.
+        let email = match token.payload() {
+            Some(email) if user.email() == Some(email) => email,
+            _ => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+        // only the status is written, a concurrent change to the rest of the user is kept.
+        match user_manager.verify_email(user.id(), email)? {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::TokenInvalid)),
+        }
+    }
+
+    /// Applies the new address the token was sent to. Receiving the token proves the address,
+    /// so a `PendingVerification` account becomes `Active` as well. Fails with `ErrorKind::EmailTaken`
+    /// when another account has the address by now.
+    pub fn confirm_email_change<Ui, Uh>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        token: &str,
+    ) -> Result<(), Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+    {
+        let token = self
+            .tokens
+            .redeem(&self.harness, token, OneTimePurpose::EmailChange)?;
+        let user = self.user(user_manager, token.user_id())?;
+        let email = match token.payload() {
+            Some(email) => email,
+            None => return Err(Error::new(ErrorKind::TokenInvalid)),
+        };
+
+        if user_manager.email_taken(email, user.id())? {
+            return Err(Error::new(ErrorKind::EmailTaken));
+        }
+        // only the email and status are written, a concurrent change to the rest of the user is kept.
+        match user_manager.change_email(user.id(), email)? {
+            true => Ok(()),
+            // the user was deleted, or another account took the address in between.
+            false => {
+                self.user(user_manager, user.id())?;
+                Err(Error::new(ErrorKind::EmailTaken))
+            }
+        }
+    }
+
+    fn send<Pu, Pr>(
+        &self,
+        user: &User<Pu, Pr>,
+        purpose: OneTimePurpose,
+        email: &str,
+    ) -> Result<(), Error>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let (token, token_str) = self.tokens.issue(
+            &self.id_generator,
+            &self.harness,
+            user.id(),
+            purpose,
+            self.ttl,
+            Some(email.to_string()),
+        )?;
+
+        let delivery = Delivery {
+            user_id: user.id(),
+            username: user.username(),
+            email: Some(email),
+            purpose,
+            token: &token_str,
+            expires: token.expires(),
+        };
+        match self.delivery.deliver(&delivery) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Delivery(err))),
+        }
+    }
+
+    fn user<Ui, Uh>(
+        &self,
+        user_manager: &UserManager<Ui, Uh>,
+        user_id: i64,
+    ) -> Result<User<(), ()>, Error>
+    where
+        Ui: IdGenerator,
+        Uh: DbHarnessUser,
+    {
+        match user_manager.get_user(&user_id)? {
+            Some(user) => Ok(user),
+            None => Err(Error::new(ErrorKind::UserNotFound)),
+        }
+    }
+}
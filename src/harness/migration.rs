@@ -0,0 +1,57 @@
+use std::fmt::Display;
+
+/// One step of a backend's schema. The steps are applied in version order and recorded in the `schema_version`
+/// table, a step that was recorded never runs again. That is why a released migration must not be edited, changes
+/// go into a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+    skip_if: Option<&'static str>,
+}
+
+impl Migration {
+    pub const fn new(version: i64, name: &'static str, sql: &'static str) -> Self {
+        Self {
+            version,
+            name,
+            sql,
+            skip_if: None,
+        }
+    }
+
+    /// A query returning true when the database already has what the step adds, e.g. a column that releases before
+    /// the versioned schema created up front. The step is then recorded without running its SQL.
+    pub const fn with_skip_if(mut self, query: &'static str) -> Self {
+        self.skip_if = Some(query);
+        self
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    pub fn sql(&self) -> &str {
+        self.sql
+    }
+
+    pub fn skip_if(&self) -> Option<&str> {
+        self.skip_if
+    }
+}
+
+// the dry run output, runnable as is.
+impl Display for Migration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "-- {}: {}", self.version, self.name)?;
+        if let Some(query) = self.skip_if {
+            writeln!(f, "-- skipped if: {}", query)?;
+        }
+        write!(f, "{}", self.sql.trim())
+    }
+}
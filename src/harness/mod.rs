@@ -1,11 +1,19 @@
+pub mod migration;
 pub mod mysql;
 pub mod postgresql;
 pub mod sqlite;
 
-use std::{error, fmt::Display};
+use std::{error, fmt::Display, sync::Arc};
+
+use chrono::{DateTime, Utc};
 
 use crate::{
     auth_token::AuthToken,
+    client::Client,
+    clock::{Clock, SystemClock},
+    device::DeviceAuthorization,
+    harness::migration::Migration,
+    one_time::{OneTimePurpose, OneTimeToken},
     session::Session,
     user::{PrivateUserMeta, PublicUserMeta, User},
 };
@@ -16,73 +24,255 @@ pub enum Db {
     Sqlite,
 }
 
+/// What went wrong in the database, independent of the backend. Backends map their own errors onto it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HarnessErrorKind {
+    // a row the operation needs does not exist. reads return `Ok(None)` for a missing row instead.
+    NotFound,
+    // a unique, foreign key or check constraint rejected the write.
+    Conflict,
+    // the database could not be reached or is busy, the operation can be retried.
+    Unavailable,
+    // stored data could not be read back.
+    Corrupt,
+    Other,
+}
+
+impl Display for HarnessErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::NotFound => "not found",
+            Self::Conflict => "conflict",
+            Self::Unavailable => "unavailable",
+            Self::Corrupt => "corrupt",
+            Self::Other => "other",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
 #[derive(Debug)]
-pub struct HarnessError(Box<dyn error::Error>);
+pub struct HarnessError {
+    pub kind: HarnessErrorKind,
+    source: Box<dyn error::Error + Send + Sync>,
+}
 
 impl HarnessError {
-    pub fn new(err: Box<dyn error::Error>) -> Self {
-        return Self(err);
+    pub fn new(
+        kind: HarnessErrorKind,
+        err: impl Into<Box<dyn error::Error + Send + Sync>>,
+    ) -> Self {
+        return Self {
+            kind,
+            source: err.into(),
+        };
     }
 }
 
-impl error::Error for HarnessError {}
-
-impl Display for HarnessError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return write!(f, "Harness Error: {}", self.0);
+impl error::Error for HarnessError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.source.as_ref())
     }
 }
 
-impl From<Box<dyn error::Error>> for HarnessError {
-    fn from(value: Box<dyn error::Error>) -> Self {
-        return Self(value);
+impl Display for HarnessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "Harness Error ({}): {}", self.kind, self.source);
     }
 }
 
 pub trait DbHarnessUser {
-    fn create_table(&self, sql_string: Option<String>) -> Result<(), Box<dyn error::Error>>;
-    fn read<'a, Pu, Pr>(&self, id: i64) -> Result<Option<User<Pu, Pr>>, Box<dyn error::Error>>
+    fn read<'a, Pu, Pr>(&self, id: i64) -> Result<Option<User<Pu, Pr>>, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta;
+
+    fn read_by_username<Pu, Pr>(
+        &self,
+        username: &str,
+    ) -> Result<Option<User<Pu, Pr>>, HarnessError>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta;
 
-    fn update<Pu, Pr>(&self, item: &User<Pu, Pr>) -> Result<usize, Box<dyn error::Error>>
+    // ordered by id.
+    fn list<Pu, Pr>(&self, offset: usize, limit: usize) -> Result<Vec<User<Pu, Pr>>, HarnessError>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta;
 
-    fn insert<Pu, Pr>(&self, item: &User<Pu, Pr>) -> Result<(), Box<dyn error::Error>>
+    // users whose username or email contains `term`, ordered by id.
+    fn search<Pu, Pr>(&self, term: &str, limit: usize) -> Result<Vec<User<Pu, Pr>>, HarnessError>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta;
 
-    fn delete(&self, id: i64) -> Result<(), Box<dyn error::Error>>;
+    fn update<Pu, Pr>(&self, item: &User<Pu, Pr>) -> Result<usize, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta;
+
+    fn insert<Pu, Pr>(&self, item: &User<Pu, Pr>) -> Result<(), HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta;
+
+    fn delete(&self, id: i64) -> Result<(), HarnessError>;
+
+    // true when a user other than `user_id` has the address `email`.
+    fn email_taken(&self, email: &str, user_id: i64) -> Result<bool, HarnessError>;
+
+    // activates the user if the account is pending verification and its address is still `email`, other statuses are
+    // kept. writes the status column only, false when no user has `id` and `email`.
+    fn verify_email(&self, id: i64, email: &str) -> Result<bool, HarnessError>;
 
-    // fn write_role(&self) -> Result<(), Box<dyn error::Error>>;
-    // fn insert_group(&self) -> Result<(), Box<dyn error::Error>>;
-    // fn remove_group(&self) -> Result<(), Box<dyn error::Error>>;
+    // stores `email` as the user's address and activates a pending account, in one statement that checks no other
+    // user has the address. writes the email and status columns only, false when nothing was written.
+    fn change_email(&self, id: i64, email: &str) -> Result<bool, HarnessError>;
+
+    // hashes of passwords the user had before, see `UserManagerConfig::with_password_history`.
+    fn insert_password_history(
+        &self,
+        user_id: i64,
+        secret: &str,
+        created: DateTime<Utc>,
+    ) -> Result<(), HarnessError>;
+
+    // newest first.
+    fn read_password_history(
+        &self,
+        user_id: i64,
+        limit: usize,
+    ) -> Result<Vec<String>, HarnessError>;
+
+    // keeps the newest `keep` entries of the user, minus any created before `before`.
+    fn prune_password_history(
+        &self,
+        user_id: i64,
+        keep: usize,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<(), HarnessError>;
+
+    // fn write_role(&self) -> Result<(), HarnessError>;
+    // fn insert_group(&self) -> Result<(), HarnessError>;
+    // fn remove_group(&self) -> Result<(), HarnessError>;
     // // change signature to fn(pu: PublicUserMeta) -> SqlString ?
-    // fn update_public(&self) -> Result<(), Box<dyn error::Error>>;
-    // fn update_private(&self) -> Result<(), Box<dyn error::Error>>;
-    // fn ban(&self) -> Result<(), Box<dyn error::Error>>;
+    // fn update_public(&self) -> Result<(), HarnessError>;
+    // fn update_private(&self) -> Result<(), HarnessError>;
+    // fn ban(&self) -> Result<(), HarnessError>;
+}
+
+/// The writes of one multi-step session operation (login, refresh rotation, logout, password reset). They become
+/// visible together at `commit`, a transaction dropped without committing rolls every write back.
+pub trait DbTransaction {
+    fn insert_token(&self, token: &AuthToken) -> Result<(), HarnessError>;
+    fn delete_access_token(&self, id: i64) -> Result<(), HarnessError>;
+    // false when there was no such token.
+    fn delete_refresh_token(&self, id: i64) -> Result<bool, HarnessError>;
+    fn insert_session(&self, session: &Session) -> Result<(), HarnessError>;
+    fn update_session(&self, session: &Session) -> Result<(), HarnessError>;
+    fn update_user<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<usize, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta;
+    fn insert_password_history(
+        &self,
+        user_id: i64,
+        secret: &str,
+        created: DateTime<Utc>,
+    ) -> Result<(), HarnessError>;
+    fn prune_password_history(
+        &self,
+        user_id: i64,
+        keep: usize,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<(), HarnessError>;
+    // false when the token was already gone, see `DbHarnessOneTimeToken::delete`.
+    fn delete_one_time_token(&self, id: i64) -> Result<bool, HarnessError>;
+    fn commit(self) -> Result<(), HarnessError>;
 }
 
 pub trait DbHarnessSession {
-    fn create_table(&self) -> Result<(), Box<dyn error::Error>>;
-    fn read(&self, id: i64) -> Result<Session, Box<dyn error::Error>>;
-    fn update(&self, session: &Session) -> Result<(), Box<dyn error::Error>>;
-    fn insert(&self, session: &Session) -> Result<(), Box<dyn error::Error>>;
-    fn delete(&self, id: i64) -> Result<(), Box<dyn error::Error>>;
+    type Transaction: DbTransaction;
+
+    // a unit of work over the session and token tables, see `DbTransaction`.
+    fn transaction(&self) -> Result<Self::Transaction, HarnessError>;
+    fn read(&self, id: i64) -> Result<Option<Session>, HarnessError>;
+    fn read_by_user(&self, user_id: i64) -> Result<Vec<Session>, HarnessError>;
+    fn read_by_access_token(&self, token_id: i64) -> Result<Option<Session>, HarnessError>;
+    fn read_by_refresh_token(&self, token_id: i64) -> Result<Option<Session>, HarnessError>;
+    fn update(&self, session: &Session) -> Result<(), HarnessError>;
+    fn insert(&self, session: &Session) -> Result<(), HarnessError>;
+    fn delete(&self, id: i64) -> Result<(), HarnessError>;
+    // deletes up to `limit` sessions left without a refresh and an access token, a token id pointing at a token that
+    // no longer exists counts as none. returns how many were deleted.
+    fn delete_dead(&self, limit: usize) -> Result<usize, HarnessError>;
 }
 
 pub trait DbHarnessToken {
-    fn create_table(&self) -> Result<(), Box<dyn error::Error>>;
-    fn update(&self, token: &AuthToken) -> Result<(), Box<dyn error::Error>>;
-    fn insert(&self, token: &AuthToken) -> Result<(), Box<dyn error::Error>>;
-    fn delete_access_token(&self, id: i64) -> Result<(), Box<dyn error::Error>>;
-    fn delete_resfresh_token(&self, id: i64) -> Result<(), Box<dyn error::Error>>;
-    fn read_refresh_token(&self, id: i64) -> Result<Option<AuthToken>, Box<dyn error::Error>>;
-    fn read_access_token(&self, id: i64) -> Result<Option<AuthToken>, Box<dyn error::Error>>;
+    fn update(&self, token: &AuthToken) -> Result<(), HarnessError>;
+    fn insert(&self, token: &AuthToken) -> Result<(), HarnessError>;
+    fn delete_access_token(&self, id: i64) -> Result<(), HarnessError>;
+    fn delete_resfresh_token(&self, id: i64) -> Result<(), HarnessError>;
+    fn read_refresh_token(&self, id: i64) -> Result<Option<AuthToken>, HarnessError>;
+    fn read_access_token(&self, id: i64) -> Result<Option<AuthToken>, HarnessError>;
+    // deletes up to `limit` access and refresh tokens that expired before `before` or were invalidated, access tokens
+    // first. returns how many were deleted.
+    fn delete_expired(&self, before: DateTime<Utc>, limit: usize) -> Result<usize, HarnessError>;
+}
+
+pub trait DbHarnessClient {
+    fn read(&self, id: i64) -> Result<Option<Client>, HarnessError>;
+    fn update(&self, client: &Client) -> Result<(), HarnessError>;
+    fn insert(&self, client: &Client) -> Result<(), HarnessError>;
+    // deletes the client together with its access tokens, in one transaction.
+    fn delete(&self, id: i64) -> Result<(), HarnessError>;
+}
+
+pub trait DbHarnessDevice {
+    fn insert(&self, authorization: &DeviceAuthorization) -> Result<(), HarnessError>;
+    // stores the polling state only, the status is changed by `decide` alone.
+    fn record_poll(&self, authorization: &DeviceAuthorization) -> Result<(), HarnessError>;
+    // stores the status only if the authorization is still pending, false when it was already decided.
+    fn decide(&self, authorization: &DeviceAuthorization) -> Result<bool, HarnessError>;
+    fn read_by_device_code(
+        &self,
+        device_code: &str,
+    ) -> Result<Option<DeviceAuthorization>, HarnessError>;
+    fn read_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorization>, HarnessError>;
+    // false when there was nothing to delete, e.g. a concurrent poll redeemed the code first.
+    fn delete(&self, id: i64) -> Result<bool, HarnessError>;
+}
+
+pub trait DbHarnessOneTimeToken {
+    fn insert(&self, token: &OneTimeToken) -> Result<(), HarnessError>;
+    fn read(&self, id: i64) -> Result<Option<OneTimeToken>, HarnessError>;
+    // the newest token of the user for `purpose`, used for codes which carry no id.
+    fn read_by_user(
+        &self,
+        user_id: i64,
+        purpose: OneTimePurpose,
+    ) -> Result<Option<OneTimeToken>, HarnessError>;
+    // returns the new count, None if the token is gone.
+    fn increment_attempts(&self, id: i64) -> Result<Option<i64>, HarnessError>;
+    // false when there was nothing to delete, e.g. a concurrent redeem used the token first.
+    fn delete(&self, id: i64) -> Result<bool, HarnessError>;
+    // deletes the tokens of the user for the same purpose and inserts `token`, in one transaction so concurrent
+    // requests leave a single live token behind.
+    fn replace(&self, token: &OneTimeToken) -> Result<(), HarnessError>;
+}
+
+/// The versioned schema of a backend, see `Migration`. It is the only way tables are created, a fresh database is
+/// built by applying every migration.
+pub trait DbHarnessSchema {
+    // applies the missing migrations, `applied` is stamped with `clock`.
+    fn migrate(&self, clock: &dyn Clock) -> Result<Vec<&'static Migration>, HarnessError>;
+    fn pending_migrations(&self) -> Result<Vec<&'static Migration>, HarnessError>;
+    // 0 for a database that was never migrated.
+    fn schema_version(&self) -> Result<i64, HarnessError>;
 }
 
 pub fn repeat_vars(count: usize) -> String {
@@ -112,6 +302,7 @@ where
     pub user: T,
     pub session: U,
     pub token: V,
+    clock: Arc<dyn Clock>,
 }
 
 impl<T, U, V> DbHarness<T, U, V>
@@ -125,14 +316,39 @@ where
             user,
             session,
             token,
+            clock: Arc::new(SystemClock),
         };
     }
 
-    pub fn init_tables(&self) -> Result<(), HarnessError> {
-        self.token.create_table()?;
-        self.session.create_table()?;
-        self.user.create_table(None)?;
+    /// Stamps applied migrations against `clock` instead of the system time, see `MockClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl<T, U, V> DbHarness<T, U, V>
+where
+    T: DbHarnessUser,
+    U: DbHarnessSession,
+    V: DbHarnessToken + DbHarnessSchema,
+{
+    /// Applies the migrations the database is missing and returns them. Each migration runs in its own transaction
+    /// together with its `schema_version` record, a failed one leaves the database at the version before it.
+    ///
+    /// A fresh database gets every table this way. Databases created before the schema was versioned are upgraded
+    /// like any other.
+    pub fn migrate(&self) -> Result<Vec<&'static Migration>, HarnessError> {
+        return self.token.migrate(self.clock.as_ref());
+    }
+
+    /// The dry run of `migrate`: the migrations it would apply, nothing is written.
+    pub fn pending_migrations(&self) -> Result<Vec<&'static Migration>, HarnessError> {
+        return self.token.pending_migrations();
+    }
 
-        return Ok(());
+    /// The newest applied migration, 0 for a database that was never migrated.
+    pub fn schema_version(&self) -> Result<i64, HarnessError> {
+        return self.token.schema_version();
     }
 }
@@ -0,0 +1,199 @@
+use std::error;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    named_params, params_from_iter, OptionalExtension, Row, ToSql, TransactionBehavior,
+};
+
+use crate::audit::{
+    chain_hash, verify_chain_hash, AuditEvent, AuditEventKind, AuditQuery, AuditSink, GENESIS_HASH,
+};
+use crate::harness::HarnessError;
+use crate::pepper::Pepper;
+
+const SELECT_AUDIT: &str =
+    "SELECT seq, time, kind, user_id, detail, prev_hash, hash FROM audit_log";
+
+struct AuditRow {
+    seq: i64,
+    // None when the kind is unknown to this version.
+    event: Option<AuditEvent>,
+    prev_hash: String,
+    hash: String,
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<AuditRow> {
+    let kind: String = row.get(2)?;
+    let event = AuditEventKind::parse(&kind)
+        .map(|kind| -> rusqlite::Result<AuditEvent> {
+            Ok(AuditEvent::from_values(
+                kind,
+                row.get(3)?,
+                row.get(1)?,
+                row.get(4)?,
+            ))
+        })
+        .transpose()?;
+    Ok(AuditRow {
+        seq: row.get(0)?,
+        event,
+        prev_hash: row.get(5)?,
+        hash: row.get(6)?,
+    })
+}
+
+/// Keeps audit events in the `audit_log` table. Every record carries the hash of the record before it (see
+/// `chain_hash`), `verify` walks the chain to find records that were edited, removed or reordered.
+///
+/// Dropping records from the end of the log leaves a valid, shorter chain. Store `head` somewhere else from time to
+/// time to detect that as well. Without `with_pepper` the hashes are unkeyed and the stored `head` is also the
+/// only thing that catches a rewritten log.
+pub struct SqliteAuditSink {
+    connection: Pool<SqliteConnectionManager>,
+    pepper: Option<Pepper>,
+}
+
+impl SqliteAuditSink {
+    pub fn new(connection: Pool<SqliteConnectionManager>) -> Self {
+        Self {
+            connection,
+            pepper: None,
+        }
+    }
+
+    /// Keys the hash chain, see `chain_hash`. Keep retired keys around for as long as records hashed under them
+    /// should verify.
+    pub fn with_pepper(mut self, pepper: Pepper) -> Self {
+        self.pepper = Some(pepper);
+        self
+    }
+
+    /// The sequence number and hash of the newest record, None for an empty log.
+    pub fn head(&self) -> Result<Option<(i64, String)>, HarnessError> {
+        let head = self
+            .connection
+            .get()?
+            .query_row(
+                "SELECT seq, hash FROM audit_log ORDER BY seq DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(head)
+    }
+
+    /// The sequence number of the first record that does not match the chain, None if the log is intact.
+    pub fn verify(&self) -> Result<Option<i64>, HarnessError> {
+        let connection = self.connection.get()?;
+        let mut stmt = connection.prepare(&format!("{} ORDER BY seq", SELECT_AUDIT))?;
+        let mut rows = stmt.query([])?;
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut expected_seq = 1;
+        while let Some(row) = rows.next()? {
+            let row = from_row(row)?;
+            let intact = match &row.event {
+                Some(event) => {
+                    row.seq == expected_seq
+                        && row.prev_hash == prev_hash
+                        && verify_chain_hash(
+                            self.pepper.as_ref(),
+                            &prev_hash,
+                            row.seq,
+                            event,
+                            &row.hash,
+                        )
+                }
+                None => false,
+            };
+            if !intact {
+                return Ok(Some(row.seq));
+            }
+            prev_hash = row.hash;
+            expected_seq += 1;
+        }
+        Ok(None)
+    }
+}
+
+impl AuditSink for SqliteAuditSink {
+    fn record(&self, event: &AuditEvent) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let mut connection = self.connection.get()?;
+        // the write lock is taken up front, two writers can not chain onto the same record.
+        let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let (seq, prev_hash) = tx
+            .query_row(
+                "SELECT seq, hash FROM audit_log ORDER BY seq DESC LIMIT 1",
+                [],
+                |row| Ok((row.get::<_, i64>(0)? + 1, row.get(1)?)),
+            )
+            .optional()?
+            .unwrap_or((1, GENESIS_HASH.to_string()));
+
+        tx.execute(
+            "INSERT INTO audit_log (seq, time, kind, user_id, detail, prev_hash, hash)
+                    VALUES (:seq, :time, :kind, :user_id, :detail, :prev_hash, :hash)",
+            named_params! {
+                ":seq": seq,
+                ":time": event.time(),
+                ":kind": event.kind().as_str(),
+                ":user_id": event.user_id(),
+                ":detail": event.detail(),
+                ":prev_hash": prev_hash,
+                ":hash": chain_hash(self.pepper.as_ref(), &prev_hash, seq, event)?,
+            },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn query(
+        &self,
+        query: &AuditQuery,
+    ) -> Result<Vec<AuditEvent>, Box<dyn error::Error + Send + Sync>> {
+        let mut filters: Vec<String> = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(user_id) = query.user_id() {
+            filters.push("user_id = ?".to_string());
+            values.push(Box::new(user_id));
+        }
+        if !query.kinds().is_empty() {
+            let placeholders = vec!["?"; query.kinds().len()].join(", ");
+            filters.push(format!("kind IN ({})", placeholders));
+            for kind in query.kinds() {
+                values.push(Box::new(kind.as_str().to_string()));
+            }
+        }
+        // times are stored as text in a fixed format, comparing the text orders them.
+        if let Some(since) = query.since() {
+            filters.push("time >= ?".to_string());
+            values.push(Box::new(since));
+        }
+        if let Some(until) = query.until() {
+            filters.push("time < ?".to_string());
+            values.push(Box::new(until));
+        }
+
+        let mut sql = SELECT_AUDIT.to_string();
+        if !filters.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&filters.join(" AND "));
+        }
+        sql.push_str(" ORDER BY seq");
+        if let Some(limit) = query.limit() {
+            sql.push_str(" LIMIT ?");
+            values.push(Box::new(limit as i64));
+        }
+
+        let connection = self.connection.get()?;
+        let mut stmt = connection.prepare(&sql)?;
+        let events = stmt
+            .query_map(params_from_iter(values), from_row)?
+            .filter_map(|row| row.map(|row| row.event).transpose())
+            .collect::<rusqlite::Result<Vec<AuditEvent>>>()?;
+        Ok(events)
+    }
+}
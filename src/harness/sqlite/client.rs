@@ -0,0 +1,78 @@
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{named_params, OptionalExtension, TransactionBehavior};
+
+use crate::{
+    client::Client,
+    harness::{DbHarnessClient, HarnessError},
+};
+
+pub struct SqliteHarnessClient {
+    connection: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteHarnessClient {
+    pub fn new(connection: Pool<SqliteConnectionManager>) -> Self {
+        Self { connection }
+    }
+}
+
+impl DbHarnessClient for SqliteHarnessClient {
+    fn read(&self, id: i64) -> Result<Option<Client>, HarnessError> {
+        let client = self
+            .connection
+            .get()?
+            .query_row(
+                "SELECT id, name, secret, scopes FROM clients WHERE id = :id",
+                named_params! {":id": id},
+                |row| {
+                    Ok(Client::from_values(
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        Ok(client)
+    }
+
+    fn insert(&self, client: &Client) -> Result<(), HarnessError> {
+        self.connection.get()?.execute(
+            "INSERT INTO clients (id, name, secret, scopes)
+                    VALUES (:id, :name, :secret, :scopes)",
+            named_params! {
+                ":id": client.id(),
+                ":name": client.name(),
+                ":secret": client.secret(),
+                ":scopes": client.scopes(),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn update(&self, client: &Client) -> Result<(), HarnessError> {
+        self.connection.get()?.execute(
+            "UPDATE clients
+                    SET name = :name, secret = :secret, scopes = :scopes
+                    WHERE id = :id",
+            named_params! {
+                ":id": client.id(),
+                ":name": client.name(),
+                ":secret": client.secret(),
+                ":scopes": client.scopes(),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn delete(&self, id: i64) -> Result<(), HarnessError> {
+        let mut connection = self.connection.get()?;
+        let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute("DELETE FROM access_tokens WHERE client_id = ?", [id])?;
+        tx.execute("DELETE FROM clients WHERE id = ?", [id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{named_params, OptionalExtension, Row};
+
+use crate::{
+    device::{DeviceAuthorization, DeviceAuthorizationStatus},
+    harness::{DbHarnessDevice, HarnessError},
+};
+
+pub struct SqliteHarnessDevice {
+    connection: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteHarnessDevice {
+    pub fn new(connection: Pool<SqliteConnectionManager>) -> Self {
+        Self { connection }
+    }
+}
+
+const SELECT_DEVICE: &str =
+    "SELECT id, device_code, user_code, expires, interval, last_polled, status, user_id
+    FROM device_authorizations";
+
+fn from_row(row: &Row) -> rusqlite::Result<DeviceAuthorization> {
+    let status: String = row.get(6)?;
+    let user_id: Option<i64> = row.get(7)?;
+    let status = match (status.as_str(), user_id) {
+        ("approved", Some(user_id)) => DeviceAuthorizationStatus::Approved(user_id),
+        ("denied", _) => DeviceAuthorizationStatus::Denied,
+        _ => DeviceAuthorizationStatus::Pending,
+    };
+
+    Ok(DeviceAuthorization::from_values(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        status,
+    ))
+}
+
+fn status_values(status: DeviceAuthorizationStatus) -> (&'static str, Option<i64>) {
+    match status {
+        DeviceAuthorizationStatus::Pending => ("pending", None),
+        DeviceAuthorizationStatus::Approved(user_id) => ("approved", Some(user_id)),
+        DeviceAuthorizationStatus::Denied => ("denied", None),
+    }
+}
+
+impl DbHarnessDevice for SqliteHarnessDevice {
+    fn insert(&self, authorization: &DeviceAuthorization) -> Result<(), HarnessError> {
+        let (status, user_id) = status_values(authorization.status());
+        self.connection.get()?.execute(
+            "INSERT INTO device_authorizations (id, device_code, user_code, expires, interval, last_polled, status, user_id)
+                    VALUES (:id, :device_code, :user_code, :expires, :interval, :last_polled, :status, :user_id)",
+            named_params! {
+                ":id": authorization.id(),
+                ":device_code": authorization.device_code(),
+                ":user_code": authorization.user_code(),
+                ":expires": authorization.expires(),
+                ":interval": authorization.interval(),
+                ":last_polled": authorization.last_polled(),
+                ":status": status,
+                ":user_id": user_id,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn record_poll(&self, authorization: &DeviceAuthorization) -> Result<(), HarnessError> {
+        self.connection.get()?.execute(
+            "UPDATE device_authorizations SET interval = :interval, last_polled = :last_polled WHERE id = :id",
+            named_params! {
+                ":id": authorization.id(),
+                ":interval": authorization.interval(),
+                ":last_polled": authorization.last_polled(),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn decide(&self, authorization: &DeviceAuthorization) -> Result<bool, HarnessError> {
+        let (status, user_id) = status_values(authorization.status());
+        let updated = self.connection.get()?.execute(
+            "UPDATE device_authorizations SET status = :status, user_id = :user_id
+                    WHERE id = :id AND status = 'pending'",
+            named_params! {
+                ":id": authorization.id(),
+                ":status": status,
+                ":user_id": user_id,
+            },
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn read_by_device_code(
+        &self,
+        device_code: &str,
+    ) -> Result<Option<DeviceAuthorization>, HarnessError> {
+        let authorization = self
+            .connection
+            .get()?
+            .query_row(
+                format!("{} WHERE device_code = :device_code", SELECT_DEVICE).as_str(),
+                named_params! {":device_code": device_code},
+                from_row,
+            )
+            .optional()?;
+        Ok(authorization)
+    }
+
+    fn read_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorization>, HarnessError> {
+        let authorization = self
+            .connection
+            .get()?
+            .query_row(
+                format!("{} WHERE user_code = :user_code", SELECT_DEVICE).as_str(),
+                named_params! {":user_code": user_code},
+                from_row,
+            )
+            .optional()?;
+        Ok(authorization)
+    }
+
+    fn delete(&self, id: i64) -> Result<bool, HarnessError> {
+        let deleted = self
+            .connection
+            .get()?
+            .execute("DELETE FROM device_authorizations WHERE id = ?", [id])?;
+        Ok(deleted > 0)
+    }
+}
@@ -3,7 +3,10 @@ use rusqlite::{
     ToSql,
 };
 
-use crate::user::{Group, Groups, Role};
+use crate::{
+    scope::Scopes,
+    user::{AccountStatus, Group, Groups, Role},
+};
 
 impl ToSql for Role {
     fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
@@ -29,7 +32,7 @@ impl FromSql for Groups {
 
         let mut out = Groups::new();
 
-        for group in groups {
+        for group in groups.into_iter().filter(|x| !x.is_empty()) {
             out.add_group(Group::from_str(group));
         }
 
@@ -43,3 +46,30 @@ impl ToSql for Groups {
         return Ok(ToSqlOutput::Owned(Value::Text(string)));
     }
 }
+
+impl ToSql for Scopes {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(Value::Text(self.to_string())))
+    }
+}
+
+impl FromSql for Scopes {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(Scopes::parse(value.as_str()?))
+    }
+}
+
+impl ToSql for AccountStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(Value::Text(self.as_str().to_owned())))
+    }
+}
+
+impl FromSql for AccountStatus {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> FromSqlResult<Self> {
+        match AccountStatus::parse(value.as_str()?) {
+            Some(status) => Ok(status),
+            None => Err(rusqlite::types::FromSqlError::InvalidType),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use rusqlite::{named_params, Connection, TransactionBehavior};
+
+use crate::{
+    clock::Clock,
+    harness::{migration::Migration, DbHarnessSchema, HarnessError},
+};
+
+use super::SqliteHarnessToken;
+
+/// The SQLite schema, oldest first.
+pub const MIGRATIONS: &[Migration] = &[
+    // the tables as the first release created them, before the schema was versioned. every statement tolerates
+    // tables that already exist, the steps after it bring a database created by any earlier release up to date.
+    Migration::new(
+        1,
+        "initial schema",
+        "
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    secret STRING NOT NULL,
+    expires DATETIME NOT NULL,
+    valid BOOL NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+CREATE INDEX IF NOT EXISTS idx_user_id ON refresh_tokens(user_id);
+CREATE TABLE IF NOT EXISTS access_tokens (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL,
+    token STRING NOT NULL,
+    expires DATETIME NOT NULL,
+    valid BOOL NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+CREATE INDEX IF NOT EXISTS idx_user_id ON access_tokens(user_id);
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    refresh_token INTEGER NOT NULL UNIQUE,
+    access_token INTEGER NOT NULL UNIQUE,
+    FOREIGN KEY(user_id) REFERENCES user(id),
+    FOREIGN KEY(refresh_token) REFERENCES refresh_tokens(id),
+    FOREIGN KEY(access_token) REFERENCES access_tokens(id)
+);
+CREATE INDEX IF NOT EXISTS idx_user_id ON sessions(user_id);
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER,
+    username STRING NOT NULL UNIQUE,
+    secret STRING NOT NULL,
+    ban TINYINT NOT NULL,
+    groups STRING NOT NULL,
+    role STRING NOT NULL,
+    FOREIGN KEY(session_id) REFERENCES sessions(id)
+);
+",
+    ),
+    Migration::new(
+        2,
+        "clients",
+        "
+CREATE TABLE IF NOT EXISTS clients (
+    id INTEGER PRIMARY KEY,
+    name STRING NOT NULL,
+    secret STRING NOT NULL,
+    scopes STRING NOT NULL
+);
+",
+    ),
+    // an access token belongs to a user or to a client. sqlite can not alter a column, the table is rebuilt.
+    Migration::new(
+        3,
+        "client access tokens",
+        "
+CREATE TABLE access_tokens_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER,
+    token STRING NOT NULL,
+    expires DATETIME NOT NULL,
+    valid BOOL NOT NULL,
+    client_id INTEGER,
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(client_id) REFERENCES clients(id),
+    CHECK ((user_id IS NULL) != (client_id IS NULL))
+);
+INSERT INTO access_tokens_new (id, user_id, token, expires, valid)
+    SELECT id, user_id, token, expires, valid FROM access_tokens;
+DROP TABLE access_tokens;
+ALTER TABLE access_tokens_new RENAME TO access_tokens;
+",
+    )
+    .with_skip_if(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('access_tokens') WHERE name = 'client_id'",
+    ),
+    Migration::new(
+        4,
+        "device authorizations",
+        "
+CREATE TABLE IF NOT EXISTS device_authorizations (
+    id INTEGER PRIMARY KEY,
+    device_code STRING NOT NULL UNIQUE,
+    user_code STRING NOT NULL UNIQUE,
+    expires DATETIME NOT NULL,
+    interval INTEGER NOT NULL,
+    last_polled DATETIME,
+    status STRING NOT NULL,
+    user_id INTEGER,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+",
+    ),
+    Migration::new(
+        5,
+        "token scopes",
+        "
+ALTER TABLE refresh_tokens ADD COLUMN scope STRING NOT NULL DEFAULT '';
+ALTER TABLE access_tokens ADD COLUMN scope STRING NOT NULL DEFAULT '';
+",
+    )
+    .with_skip_if("SELECT COUNT(*) > 0 FROM pragma_table_info('refresh_tokens') WHERE name = 'scope'"),
+    // a logged out session keeps its row without tokens. the access token moves to text affinity, with the old
+    // numeric affinity a token made of digits only was stored as a number.
+    Migration::new(
+        6,
+        "optional session tokens",
+        "
+CREATE TABLE sessions_new (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    refresh_token INTEGER UNIQUE,
+    access_token INTEGER UNIQUE,
+    FOREIGN KEY(user_id) REFERENCES user(id),
+    FOREIGN KEY(refresh_token) REFERENCES refresh_tokens(id),
+    FOREIGN KEY(access_token) REFERENCES access_tokens(id)
+);
+INSERT INTO sessions_new (id, user_id, refresh_token, access_token)
+    SELECT id, user_id, refresh_token, access_token FROM sessions;
+DROP TABLE sessions;
+ALTER TABLE sessions_new RENAME TO sessions;
+CREATE TABLE access_tokens_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER,
+    token TEXT NOT NULL,
+    expires DATETIME NOT NULL,
+    valid BOOL NOT NULL,
+    client_id INTEGER,
+    scope STRING NOT NULL DEFAULT '',
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(client_id) REFERENCES clients(id),
+    CHECK ((user_id IS NULL) != (client_id IS NULL))
+);
+INSERT INTO access_tokens_new (id, user_id, token, expires, valid, client_id, scope)
+    SELECT id, user_id, token, expires, valid, client_id, scope FROM access_tokens;
+DROP TABLE access_tokens;
+ALTER TABLE access_tokens_new RENAME TO access_tokens;
+",
+    )
+    .with_skip_if(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('sessions') WHERE name = 'refresh_token' AND \"notnull\" = 0",
+    ),
+    Migration::new(
+        7,
+        "one time tokens",
+        "
+CREATE TABLE IF NOT EXISTS one_time_tokens (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    purpose STRING NOT NULL,
+    secret STRING NOT NULL,
+    expires DATETIME NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+CREATE INDEX IF NOT EXISTS idx_one_time_user_id ON one_time_tokens(user_id);
+",
+    ),
+    Migration::new(
+        8,
+        "account status",
+        "
+ALTER TABLE users ADD COLUMN email STRING;
+ALTER TABLE users ADD COLUMN status STRING NOT NULL DEFAULT 'active';
+",
+    )
+    .with_skip_if("SELECT COUNT(*) > 0 FROM pragma_table_info('users') WHERE name = 'email'"),
+    Migration::new(
+        9,
+        "one time token payload",
+        "
+ALTER TABLE one_time_tokens ADD COLUMN payload STRING;
+",
+    )
+    .with_skip_if(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('one_time_tokens') WHERE name = 'payload'",
+    ),
+    Migration::new(
+        10,
+        "one time token attempts",
+        "
+ALTER TABLE one_time_tokens ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+",
+    )
+    .with_skip_if(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('one_time_tokens') WHERE name = 'attempts'",
+    ),
+    Migration::new(
+        11,
+        "password history",
+        "
+CREATE TABLE IF NOT EXISTS password_history (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    secret STRING NOT NULL,
+    created DATETIME NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+CREATE INDEX IF NOT EXISTS idx_password_history_user_id ON password_history(user_id);
+",
+    ),
+    Migration::new(
+        12,
+        "password expiry",
+        "
+ALTER TABLE users ADD COLUMN password_changed_at DATETIME;
+ALTER TABLE users ADD COLUMN must_change_password TINYINT NOT NULL DEFAULT 0;
+",
+    )
+    .with_skip_if(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('users') WHERE name = 'password_changed_at'",
+    ),
+    Migration::new(
+        13,
+        "audit log",
+        "
+CREATE TABLE IF NOT EXISTS audit_log (
+    seq INTEGER PRIMARY KEY,
+    time DATETIME NOT NULL,
+    kind STRING NOT NULL,
+    user_id INTEGER,
+    detail STRING,
+    prev_hash STRING NOT NULL,
+    hash STRING NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_audit_user_id ON audit_log(user_id, time);
+",
+    ),
+    // index names are global in sqlite: the tables shared idx_user_id, only refresh_tokens ever got the index.
+    Migration::new(
+        14,
+        "user_id indexes",
+        "
+DROP INDEX IF EXISTS idx_user_id;
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id ON refresh_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_access_tokens_user_id ON access_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
+",
+    ),
+];
+
+fn create_version_table(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                name STRING NOT NULL,
+                applied DATETIME NOT NULL
+        );",
+        [],
+    )?;
+    Ok(())
+}
+
+// 0 when nothing was applied yet, reading does not create the version table.
+fn current_version(connection: &Connection) -> rusqlite::Result<i64> {
+    let exists: bool = connection.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+        [],
+        |row| row.get(0),
+    )?;
+    match exists {
+        true => connection.query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+            [],
+            |row| row.get(0),
+        ),
+        false => Ok(0),
+    }
+}
+
+fn pending(version: i64) -> Vec<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version() > version)
+        .collect()
+}
+
+impl DbHarnessSchema for SqliteHarnessToken {
+    fn migrate(&self, clock: &dyn Clock) -> Result<Vec<&'static Migration>, HarnessError> {
+        let mut connection = self.connection().get()?;
+        create_version_table(&connection)?;
+
+        let mut applied = Vec::new();
+        for migration in MIGRATIONS {
+            // the write lock is taken before the version is checked, two processes can not apply the same step.
+            let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            if current_version(&tx)? >= migration.version() {
+                continue;
+            }
+            let skip = match migration.skip_if() {
+                Some(query) => tx.query_row(query, [], |row| row.get(0))?,
+                None => false,
+            };
+            if !skip {
+                tx.execute_batch(migration.sql())?;
+            }
+            tx.execute(
+                "INSERT INTO schema_version (version, name, applied) VALUES (:version, :name, :applied)",
+                named_params! {
+                    ":version": migration.version(),
+                    ":name": migration.name(),
+                    ":applied": clock.now(),
+                },
+            )?;
+            tx.commit()?;
+            applied.push(migration);
+        }
+        Ok(applied)
+    }
+
+    fn pending_migrations(&self) -> Result<Vec<&'static Migration>, HarnessError> {
+        Ok(pending(self.schema_version()?))
+    }
+
+    fn schema_version(&self) -> Result<i64, HarnessError> {
+        let connection = self.connection().get()?;
+        Ok(current_version(&connection)?)
+    }
+}
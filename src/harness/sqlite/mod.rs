@@ -1,30 +1,75 @@
 mod entity;
 
+mod audit;
+mod client;
+mod device;
+mod migration;
+mod one_time;
 mod session;
 mod token;
+mod transaction;
 mod user;
 
-use session::*;
-use token::*;
-use user::*;
+pub use audit::*;
+pub use client::*;
+pub use device::*;
+pub use migration::*;
+pub use one_time::*;
+pub use session::*;
+pub use token::*;
+pub use transaction::*;
+pub use user::*;
 
-use rusqlite::ToSql;
+use rusqlite::{ErrorCode, ToSql};
 
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 
-use super::DbHarness;
+use super::{DbHarness, HarnessError, HarnessErrorKind};
 
 impl<'a> DbHarness<SqliteHarnessUser<'a>, SqliteHarnessSession, SqliteHarnessToken> {
     pub fn new_sqlite(pool: Pool<SqliteConnectionManager>) -> Self {
-        return DbHarness {
-            user: SqliteHarnessUser::new(pool.clone()),
-            session: SqliteHarnessSession::new(pool.clone()),
-            token: SqliteHarnessToken::new(pool),
-        };
+        return DbHarness::new_custom(
+            SqliteHarnessUser::new(pool.clone()),
+            SqliteHarnessSession::new(pool.clone()),
+            SqliteHarnessToken::new(pool),
+        );
     }
 }
 
 pub trait IntoValues {
     fn into_values(&self) -> &[(&str, &dyn ToSql)];
 }
+
+impl From<rusqlite::Error> for HarnessError {
+    fn from(value: rusqlite::Error) -> Self {
+        let kind = match &value {
+            rusqlite::Error::QueryReturnedNoRows => HarnessErrorKind::NotFound,
+            rusqlite::Error::SqliteFailure(err, _) => match err.code {
+                ErrorCode::ConstraintViolation => HarnessErrorKind::Conflict,
+                ErrorCode::DatabaseBusy
+                | ErrorCode::DatabaseLocked
+                | ErrorCode::CannotOpen
+                | ErrorCode::SystemIoFailure
+                | ErrorCode::OutOfMemory
+                | ErrorCode::DiskFull => HarnessErrorKind::Unavailable,
+                ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => HarnessErrorKind::Corrupt,
+                _ => HarnessErrorKind::Other,
+            },
+            // a column holds something its type can not be read from.
+            rusqlite::Error::FromSqlConversionFailure(..)
+            | rusqlite::Error::InvalidColumnType(..)
+            | rusqlite::Error::IntegralValueOutOfRange(..)
+            | rusqlite::Error::Utf8Error(..) => HarnessErrorKind::Corrupt,
+            _ => HarnessErrorKind::Other,
+        };
+        HarnessError::new(kind, value)
+    }
+}
+
+// the pool timed out waiting for a connection.
+impl From<r2d2::Error> for HarnessError {
+    fn from(value: r2d2::Error) -> Self {
+        HarnessError::new(HarnessErrorKind::Unavailable, value)
+    }
+}
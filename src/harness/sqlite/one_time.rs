@@ -0,0 +1,132 @@
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{named_params, Connection, OptionalExtension, Row, TransactionBehavior};
+
+use crate::{
+    harness::{DbHarnessOneTimeToken, HarnessError},
+    one_time::{OneTimePurpose, OneTimeToken},
+};
+
+const SELECT_ONE_TIME_TOKEN: &str =
+    "SELECT id, user_id, purpose, secret, expires, payload, attempts FROM one_time_tokens";
+
+// rows with a purpose this version does not know about are treated as missing.
+fn from_row(row: &Row) -> rusqlite::Result<Option<OneTimeToken>> {
+    let purpose: String = row.get(2)?;
+    match OneTimePurpose::parse(&purpose) {
+        Some(purpose) => Ok(Some(OneTimeToken::from_values(
+            row.get(0)?,
+            row.get(1)?,
+            purpose,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+pub struct SqliteHarnessOneTimeToken {
+    connection: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteHarnessOneTimeToken {
+    pub fn new(connection: Pool<SqliteConnectionManager>) -> Self {
+        Self { connection }
+    }
+}
+
+impl DbHarnessOneTimeToken for SqliteHarnessOneTimeToken {
+    fn insert(&self, token: &OneTimeToken) -> Result<(), HarnessError> {
+        let connection = self.connection.get()?;
+        insert_one_time_token(&connection, token)?;
+        Ok(())
+    }
+
+    fn read(&self, id: i64) -> Result<Option<OneTimeToken>, HarnessError> {
+        let token = self
+            .connection
+            .get()?
+            .query_row(
+                &format!("{} WHERE id = :id", SELECT_ONE_TIME_TOKEN),
+                named_params! {":id": id},
+                from_row,
+            )
+            .optional()?;
+        Ok(token.flatten())
+    }
+
+    fn read_by_user(
+        &self,
+        user_id: i64,
+        purpose: OneTimePurpose,
+    ) -> Result<Option<OneTimeToken>, HarnessError> {
+        let token = self
+            .connection
+            .get()?
+            .query_row(
+                &format!(
+                    "{} WHERE user_id = :user_id AND purpose = :purpose ORDER BY expires DESC LIMIT 1",
+                    SELECT_ONE_TIME_TOKEN
+                ),
+                named_params! {":user_id": user_id, ":purpose": purpose.as_str()},
+                from_row,
+            )
+            .optional()?;
+        Ok(token.flatten())
+    }
+
+    fn increment_attempts(&self, id: i64) -> Result<Option<i64>, HarnessError> {
+        let attempts = self
+            .connection
+            .get()?
+            .query_row(
+                "UPDATE one_time_tokens SET attempts = attempts + 1 WHERE id = ? RETURNING attempts",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(attempts)
+    }
+
+    fn delete(&self, id: i64) -> Result<bool, HarnessError> {
+        let connection = self.connection.get()?;
+        Ok(delete_one_time_token(&connection, id)?)
+    }
+
+    fn replace(&self, token: &OneTimeToken) -> Result<(), HarnessError> {
+        let mut connection = self.connection.get()?;
+        let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM one_time_tokens WHERE user_id = :user_id AND purpose = :purpose",
+            named_params! {":user_id": token.user_id(), ":purpose": token.purpose().as_str()},
+        )?;
+        insert_one_time_token(&tx, token)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// shared with `SqliteTransaction`, which runs the same statements inside a transaction.
+pub(super) fn delete_one_time_token(connection: &Connection, id: i64) -> rusqlite::Result<bool> {
+    let rows = connection.execute("DELETE FROM one_time_tokens WHERE id = ?", [id])?;
+    Ok(rows > 0)
+}
+
+fn insert_one_time_token(connection: &Connection, token: &OneTimeToken) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO one_time_tokens (id, user_id, purpose, secret, expires, payload, attempts)
+                VALUES (:id, :user_id, :purpose, :secret, :expires, :payload, :attempts)",
+        named_params! {
+            ":id": token.id(),
+            ":user_id": token.user_id(),
+            ":purpose": token.purpose().as_str(),
+            ":secret": token.secret(),
+            ":expires": token.expires(),
+            ":payload": token.payload(),
+            ":attempts": token.attempts(),
+        },
+    )?;
+    Ok(())
+}
@@ -1,10 +1,13 @@
-use std::error;
-
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::named_params;
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+
+use crate::{
+    harness::{DbHarnessSession, HarnessError},
+    session::Session,
+};
 
-use crate::{harness::DbHarnessSession, session::Session};
+use super::SqliteTransaction;
 
 pub struct SqliteHarnessSession {
     connection: Pool<SqliteConnectionManager>,
@@ -16,83 +19,122 @@ impl SqliteHarnessSession {
     }
 }
 
+fn from_row(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session::from_values(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+    ))
+}
+
 impl DbHarnessSession for SqliteHarnessSession {
-    fn delete(&self, id: i64) -> Result<(), Box<dyn error::Error>> {
+    type Transaction = SqliteTransaction;
+
+    fn transaction(&self) -> Result<SqliteTransaction, HarnessError> {
+        SqliteTransaction::begin(self.connection.get()?)
+    }
+    fn delete(&self, id: i64) -> Result<(), HarnessError> {
         self.connection
             .get()?
             .execute("DELETE FROM sessions WHERE id = ?", [id])?;
         return Ok(());
     }
-    fn insert(&self, session: &Session) -> Result<(), Box<dyn error::Error>> {
-        self.connection.get()?.execute(
-            "INSERT INTO sessions (id, user_id, refresh_token, access_token)
-                        VALUES (:id, :user_id, :refresh_token, :access_token)",
-            named_params![
-                ":id": session.id(),
-                ":user_id": session.user_id(),
-                ":refresh_token": session.refresh_token(),
-                ":access_token": session.access_token()
-            ],
+    fn delete_dead(&self, limit: usize) -> Result<usize, HarnessError> {
+        let deleted = self.connection.get()?.execute(
+            "DELETE FROM sessions WHERE id IN (
+                SELECT id FROM sessions
+                    WHERE (refresh_token IS NULL OR refresh_token NOT IN (SELECT id FROM refresh_tokens))
+                    AND (access_token IS NULL OR access_token NOT IN (SELECT id FROM access_tokens))
+                    LIMIT :limit
+            )",
+            named_params! {":limit": limit as i64},
         )?;
-        return Ok(());
+        Ok(deleted)
     }
-    fn read(&self, id: i64) -> Result<Session, Box<dyn error::Error>> {
+    fn insert(&self, session: &Session) -> Result<(), HarnessError> {
         let connection = self.connection.get()?;
-        match connection.query_row(
-            "SELECT * FROM sessions WHERE id = :id",
-            named_params! {":id": id},
-            |row| {
-                let user_id = row.get(1)?;
-                let refresh_token = row.get(2)?;
-                let access_token = row.get(3)?;
-                return Ok(Session::from_values(
-                    id,
-                    user_id,
-                    refresh_token,
-                    access_token,
-                ));
-            },
-        ) {
-            Ok(session) => return Ok(session),
-            Err(err) => return Err(err.into()),
-        };
+        insert_session(&connection, session)?;
+        return Ok(());
+    }
+    fn read(&self, id: i64) -> Result<Option<Session>, HarnessError> {
+        let session = self
+            .connection
+            .get()?
+            .query_row(
+                "SELECT id, user_id, refresh_token, access_token FROM sessions WHERE id = :id",
+                named_params! {":id": id},
+                from_row,
+            )
+            .optional()?;
+        Ok(session)
     }
-    fn update(&self, session: &Session) -> Result<(), Box<dyn error::Error>> {
+    fn read_by_user(&self, user_id: i64) -> Result<Vec<Session>, HarnessError> {
         let connection = self.connection.get()?;
-
-        connection.execute(
-            "UPDATE sessions 
-                SET refresh_token = :refresh_token, access_token = :access_token
-                WHERE id = :id",
-            named_params![
-                ":refresh_token": session.refresh_token(),
-                ":access_token": session.access_token(),
-                ":id": session.id()
-            ],
+        let mut stmt = connection.prepare(
+            "SELECT id, user_id, refresh_token, access_token FROM sessions WHERE user_id = :user_id",
         )?;
-
-        return Ok(());
+        let sessions = stmt
+            .query_map(named_params! {":user_id": user_id}, from_row)?
+            .collect::<Result<Vec<Session>, rusqlite::Error>>()?;
+        Ok(sessions)
     }
-
-    fn create_table(&self) -> Result<(), Box<dyn error::Error>> {
+    fn read_by_access_token(&self, token_id: i64) -> Result<Option<Session>, HarnessError> {
+        let session = self
+            .connection
+            .get()?
+            .query_row(
+                "SELECT id, user_id, refresh_token, access_token FROM sessions WHERE access_token = :token_id",
+                named_params! {":token_id": token_id},
+                from_row,
+            )
+            .optional()?;
+        Ok(session)
+    }
+    fn read_by_refresh_token(&self, token_id: i64) -> Result<Option<Session>, HarnessError> {
+        let session = self
+            .connection
+            .get()?
+            .query_row(
+                "SELECT id, user_id, refresh_token, access_token FROM sessions WHERE refresh_token = :token_id",
+                named_params! {":token_id": token_id},
+                from_row,
+            )
+            .optional()?;
+        Ok(session)
+    }
+    fn update(&self, session: &Session) -> Result<(), HarnessError> {
         let connection = self.connection.get()?;
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                    id INTEGER PRIMARY KEY,
-                    user_id INTEGER NOT NULL,
-                    refresh_token INTEGER NOT NULL UNIQUE,
-                    access_token INTEGER NOT NULL UNIQUE,
-                    FOREIGN KEY(user_id) REFERENCES user(id),
-                    FOREIGN KEY(refresh_token) REFERENCES refresh_tokens(id),
-                    FOREIGN KEY(access_token) REFERENCES access_tokens(id)
-            );",
-            [],
-        )?;
-
-        connection.execute(
-            "CREATE INDEX IF NOT EXISTS idx_user_id ON sessions(user_id);",
-            [],
-        )?;
+        update_session(&connection, session)?;
         return Ok(());
     }
 }
+
+// shared with `SqliteTransaction`, which runs the same statements inside a transaction.
+pub(super) fn insert_session(connection: &Connection, session: &Session) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO sessions (id, user_id, refresh_token, access_token)
+                    VALUES (:id, :user_id, :refresh_token, :access_token)",
+        named_params![
+            ":id": session.id(),
+            ":user_id": session.user_id(),
+            ":refresh_token": session.refresh_token(),
+            ":access_token": session.access_token()
+        ],
+    )?;
+    Ok(())
+}
+
+pub(super) fn update_session(connection: &Connection, session: &Session) -> rusqlite::Result<()> {
+    connection.execute(
+        "UPDATE sessions
+            SET refresh_token = :refresh_token, access_token = :access_token
+            WHERE id = :id",
+        named_params![
+            ":refresh_token": session.refresh_token(),
+            ":access_token": session.access_token(),
+            ":id": session.id()
+        ],
+    )?;
+    Ok(())
+}
@@ -1,12 +1,11 @@
-use std::error;
-
+use chrono::{DateTime, Utc};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::named_params;
+use rusqlite::{named_params, Connection, OptionalExtension};
 
 use crate::{
-    auth_token::{AuthToken, TokenType},
-    harness::DbHarnessToken,
+    auth_token::{AuthToken, TokenSubject, TokenType},
+    harness::{DbHarnessToken, HarnessError},
 };
 
 pub struct SqliteHarnessToken {
@@ -17,54 +16,55 @@ impl SqliteHarnessToken {
     pub fn new(connection: Pool<SqliteConnectionManager>) -> Self {
         Self { connection }
     }
+
+    pub(crate) fn connection(&self) -> &Pool<SqliteConnectionManager> {
+        &self.connection
+    }
 }
 
 impl DbHarnessToken for SqliteHarnessToken {
-    fn delete_access_token(&self, id: i64) -> Result<(), Box<dyn error::Error>> {
-        self.connection
-            .get()?
-            .execute("DELETE FROM access_tokens WHERE id = ?", [id])?;
+    fn delete_access_token(&self, id: i64) -> Result<(), HarnessError> {
+        let connection = self.connection.get()?;
+        delete_access_token(&connection, id)?;
         return Ok(());
     }
 
-    fn delete_resfresh_token(&self, id: i64) -> Result<(), Box<dyn error::Error>> {
-        self.connection
-            .get()?
-            .execute("DELETE FROM refresh_tokens WHERE id = ?", [id])?;
+    fn delete_resfresh_token(&self, id: i64) -> Result<(), HarnessError> {
+        let connection = self.connection.get()?;
+        delete_refresh_token(&connection, id)?;
         return Ok(());
     }
 
-    fn insert(&self, auth_token: &AuthToken) -> Result<(), Box<dyn error::Error>> {
+    fn delete_expired(&self, before: DateTime<Utc>, limit: usize) -> Result<usize, HarnessError> {
         let connection = self.connection.get()?;
-        match &auth_token.token_type() {
-            TokenType::Refresh { secret } => connection.execute(
-                "INSERT INTO refresh_tokens (id, user_id, secret, expires, valid)
-                    VALUES (:id, :user_id, :secret, :expires, :valid",
+        let mut deleted = 0;
+        for table in ["access_tokens", "refresh_tokens"] {
+            if deleted == limit {
+                break;
+            }
+            // sqlite is not always built with DELETE ... LIMIT, select the batch instead.
+            deleted += connection.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE id IN (
+                        SELECT id FROM {table} WHERE expires < :before OR valid = FALSE LIMIT :limit
+                    )"
+                ),
                 named_params! {
-                    ":id": auth_token.id(),
-                    ":user_id": auth_token.user_id(),
-                    ":secret": secret,
-                    ":expires": auth_token.expires(),
-                    ":valid": auth_token.valid(),
+                    ":before": before,
+                    ":limit": (limit - deleted) as i64,
                 },
-            )?,
-            TokenType::Access { token } => connection.execute(
-                "INSERT INTO access_tokens (id, user_id, token, expires, valid) 
-                    VALUES (:id, :user_id, :token, :expires, :valid",
-                named_params! {
-                    ":id": auth_token.id(),
-                    ":user_id": auth_token.user_id(),
-                    ":secret": token,
-                    ":expires": auth_token.expires(),
-                    ":valid": auth_token.valid(),
-                },
-            )?,
-        };
+            )?;
+        }
+        return Ok(deleted);
+    }
 
+    fn insert(&self, auth_token: &AuthToken) -> Result<(), HarnessError> {
+        let connection = self.connection.get()?;
+        insert_token(&connection, auth_token)?;
         Ok(())
     }
 
-    fn update(&self, auth_token: &AuthToken) -> Result<(), Box<dyn error::Error>> {
+    fn update(&self, auth_token: &AuthToken) -> Result<(), HarnessError> {
         let connection = self.connection.get()?;
 
         match auth_token.token_type() {
@@ -79,7 +79,7 @@ impl DbHarnessToken for SqliteHarnessToken {
             )?,
             TokenType::Access { .. } => connection.execute(
                 "UPDATE access_tokens
-                    SET valid = :valid,
+                    SET valid = :valid
                     WHERE id = :id",
                 named_params! {
                     ":valid": auth_token.valid(),
@@ -91,85 +91,113 @@ impl DbHarnessToken for SqliteHarnessToken {
         return Ok(());
     }
 
-    fn create_table(&self) -> Result<(), Box<dyn error::Error>> {
+    fn read_access_token(&self, id: i64) -> Result<Option<AuthToken>, HarnessError> {
         let connection = self.connection.get()?;
 
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS refresh_tokens (
-                    id INTEGER PRIMARY KEY,
-                    user_id INTEGER NOT NULL,
-                    secret STRING NOT NULL,
-                    expires DATETIME NOT NULL,
-                    valid BOOL NOT NULL,
-                    FOREIGN KEY(user_id) REFERENCES users(id)
-            );",
-            [],
-        )?;
-        connection.execute(
-            "CREATE INDEX IF NOT EXISTS idx_user_id ON refresh_tokens(user_id);",
-            [],
-        )?;
-
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS access_tokens (
-                    id INTEGER PRIMARY KEY NOT NULL,
-                    user_id INTEGER NOT NULL,
-                    token STRING NOT NULL,
-                    expires DATETIME NOT NULL,
-                    valid BOOL NOT NULL,
-                    FOREIGN KEY(user_id) REFERENCES users(id)
-            );",
-            [],
-        )?;
-
-        connection.execute(
-            "CREATE INDEX IF NOT EXISTS idx_user_id ON access_tokens(user_id);",
-            [],
-        )?;
-        return Ok(());
+        let token = connection
+            .query_row(
+                "SELECT id, user_id, token, expires, valid, client_id, scope FROM access_tokens WHERE id = :id;",
+                named_params! {":id": id},
+                |row| {
+                    let user_id: Option<i64> = row.get(1)?;
+                    let token_type = TokenType::Access { token: row.get(2)? };
+                    let expires = row.get(3)?;
+                    let valid = row.get(4)?;
+                    let client_id: Option<i64> = row.get(5)?;
+                    let scopes = row.get(6)?;
+                    let subject = match (user_id, client_id) {
+                        (Some(user_id), _) => TokenSubject::User(user_id),
+                        (None, Some(client_id)) => TokenSubject::Client(client_id),
+                        (None, None) => {
+                            return Err(rusqlite::Error::InvalidColumnType(
+                                1,
+                                String::from("user_id"),
+                                rusqlite::types::Type::Null,
+                            ))
+                        }
+                    };
+                    return Ok(AuthToken::from_values(
+                        id, subject, token_type, expires, valid, scopes,
+                    ));
+                },
+            )
+            .optional()?;
+        Ok(token)
     }
 
-    fn read_access_token(&self, id: i64) -> Result<Option<AuthToken>, Box<dyn error::Error>> {
+    fn read_refresh_token(&self, id: i64) -> Result<Option<AuthToken>, HarnessError> {
         let connection = self.connection.get()?;
 
-        match connection.query_row(
-            "SELECT * FROM access_tokens WHERE id = :id;",
-            named_params! {":id": id},
-            |row| {
-                let user_id = row.get(1)?;
-                let token_type = TokenType::Access { token: row.get(2)? };
-                let expires = row.get(3)?;
-                let valid = row.get(4)?;
-                return Ok(AuthToken::from_values(
-                    id, user_id, token_type, expires, valid,
-                ));
-            },
-        ) {
-            Ok(token) => return Ok(Some(token)),
-            Err(err) => return Err(err.into()),
-        }
+        let token = connection
+            .query_row(
+                "SELECT id, user_id, secret, expires, valid, scope FROM refresh_tokens WHERE id = :id;",
+                named_params! {":id": id},
+                |row| {
+                    let user_id = row.get(1)?;
+                    let token_type = TokenType::Refresh {
+                        secret: row.get(2)?,
+                    };
+                    let expires = row.get(3)?;
+                    let valid = row.get(4)?;
+                    let scopes = row.get(5)?;
+                    return Ok(AuthToken::from_values(
+                        id,
+                        TokenSubject::User(user_id),
+                        token_type,
+                        expires,
+                        valid,
+                        scopes,
+                    ));
+                },
+            )
+            .optional()?;
+        Ok(token)
     }
+}
 
-    fn read_refresh_token(&self, id: i64) -> Result<Option<AuthToken>, Box<dyn error::Error>> {
-        let connection = self.connection.get()?;
-
-        match connection.query_row(
-            "SELECT * FROM refresh_tokens WHERE id = :id;",
-            named_params! {":id": id},
-            |row| {
-                let user_id = row.get(1)?;
-                let token_type = TokenType::Refresh {
-                    secret: row.get(2)?,
-                };
-                let expires = row.get(3)?;
-                let valid = row.get(4)?;
-                return Ok(AuthToken::from_values(
-                    id, user_id, token_type, expires, valid,
-                ));
+// shared with `SqliteTransaction`, which runs the same statements inside a transaction.
+pub(super) fn insert_token(
+    connection: &Connection,
+    auth_token: &AuthToken,
+) -> rusqlite::Result<()> {
+    match &auth_token.token_type() {
+        TokenType::Refresh { secret } => connection.execute(
+            "INSERT INTO refresh_tokens (id, user_id, secret, expires, valid, scope)
+                VALUES (:id, :user_id, :secret, :expires, :valid, :scope)",
+            named_params! {
+                ":id": auth_token.id(),
+                ":user_id": auth_token.user_id(),
+                ":secret": secret,
+                ":expires": auth_token.expires(),
+                ":valid": auth_token.valid(),
+                ":scope": auth_token.scopes(),
             },
-        ) {
-            Ok(token) => return Ok(Some(token)),
-            Err(err) => return Err(err.into()),
-        }
-    }
+        )?,
+        TokenType::Access { token } => connection.execute(
+            "INSERT INTO access_tokens (id, user_id, token, expires, valid, client_id, scope)
+                VALUES (:id, :user_id, :token, :expires, :valid, :client_id, :scope)",
+            named_params! {
+                ":id": auth_token.id(),
+                ":user_id": auth_token.user_id(),
+                ":client_id": auth_token.client_id(),
+                ":token": token,
+                ":expires": auth_token.expires(),
+                ":valid": auth_token.valid(),
+                ":scope": auth_token.scopes(),
+            },
+        )?,
+    };
+
+    Ok(())
+}
+
+pub(super) fn delete_access_token(connection: &Connection, id: i64) -> rusqlite::Result<()> {
+    connection.execute("DELETE FROM access_tokens WHERE id = ?", [id])?;
+    Ok(())
+}
+
+// false when the token was already gone, e.g. taken by a concurrent refresh.
+pub(super) fn delete_refresh_token(connection: &Connection, id: i64) -> rusqlite::Result<bool> {
+    let rows = connection.execute("DELETE FROM refresh_tokens WHERE id = ?", [id])?;
+    Ok(rows > 0)
 }
@@ -0,0 +1,111 @@
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    auth_token::AuthToken,
+    harness::{DbTransaction, HarnessError},
+    session::Session,
+    user::{PrivateUserMeta, PublicUserMeta, User},
+};
+
+use super::{
+    one_time::delete_one_time_token,
+    session::{insert_session, update_session},
+    token::{delete_access_token, delete_refresh_token, insert_token},
+    user::{insert_password_history, prune_password_history, update_user},
+};
+
+/// Holds a pooled connection with an open transaction. `rusqlite::Transaction` borrows its connection, which can not
+/// live next to the pooled connection it borrows from, so the transaction is driven with plain statements.
+pub struct SqliteTransaction {
+    connection: PooledConnection<SqliteConnectionManager>,
+    committed: bool,
+}
+
+impl SqliteTransaction {
+    pub(super) fn begin(
+        connection: PooledConnection<SqliteConnectionManager>,
+    ) -> Result<Self, HarnessError> {
+        // the write lock is taken up front, a concurrent writer waits instead of failing halfway through.
+        connection.execute_batch("BEGIN IMMEDIATE")?;
+        Ok(Self {
+            connection,
+            committed: false,
+        })
+    }
+}
+
+impl DbTransaction for SqliteTransaction {
+    fn insert_token(&self, token: &AuthToken) -> Result<(), HarnessError> {
+        insert_token(&self.connection, token)?;
+        Ok(())
+    }
+
+    fn delete_access_token(&self, id: i64) -> Result<(), HarnessError> {
+        delete_access_token(&self.connection, id)?;
+        Ok(())
+    }
+
+    fn delete_refresh_token(&self, id: i64) -> Result<bool, HarnessError> {
+        Ok(delete_refresh_token(&self.connection, id)?)
+    }
+
+    fn insert_session(&self, session: &Session) -> Result<(), HarnessError> {
+        insert_session(&self.connection, session)?;
+        Ok(())
+    }
+
+    fn update_session(&self, session: &Session) -> Result<(), HarnessError> {
+        update_session(&self.connection, session)?;
+        Ok(())
+    }
+
+    fn update_user<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<usize, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        Ok(update_user(&self.connection, user)?)
+    }
+
+    fn insert_password_history(
+        &self,
+        user_id: i64,
+        secret: &str,
+        created: DateTime<Utc>,
+    ) -> Result<(), HarnessError> {
+        insert_password_history(&self.connection, user_id, secret, created)?;
+        Ok(())
+    }
+
+    fn prune_password_history(
+        &self,
+        user_id: i64,
+        keep: usize,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<(), HarnessError> {
+        prune_password_history(&self.connection, user_id, keep, before)?;
+        Ok(())
+    }
+
+    fn delete_one_time_token(&self, id: i64) -> Result<bool, HarnessError> {
+        Ok(delete_one_time_token(&self.connection, id)?)
+    }
+
+    fn commit(mut self) -> Result<(), HarnessError> {
+        self.connection.execute_batch("COMMIT")?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteTransaction {
+    fn drop(&mut self) {
+        // the connection goes back to the pool, it must not carry the open transaction with it.
+        if !self.committed {
+            let _ = self.connection.execute_batch("ROLLBACK");
+        }
+    }
+}
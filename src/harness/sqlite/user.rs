@@ -1,12 +1,11 @@
-use std::{error, result};
-
+use chrono::{DateTime, Utc};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::named_params;
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
 
 use crate::{
-    harness::DbHarnessUser,
-    user::{PrivateUserMeta, PublicUserMeta, User},
+    harness::{DbHarnessUser, HarnessError},
+    user::{AccountStatus, PrivateUserMeta, PublicUserMeta, User},
 };
 
 pub struct SqliteHarnessUser<'a> {
@@ -35,8 +34,37 @@ impl<'a> SqliteHarnessUser<'a> {
     }
 }
 
+const SELECT_USER: &str =
+    "SELECT id, session_id, username, secret, ban, groups, role, email, status,
+    password_changed_at, must_change_password FROM users";
+
+fn from_row<Pu, Pr>(row: &Row) -> rusqlite::Result<User<Pu, Pr>>
+where
+    Pu: PublicUserMeta,
+    Pr: PrivateUserMeta,
+{
+    Ok(User::from_values(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+        row.get(8)?,
+        row.get(9)?,
+        row.get(10)?,
+        None,
+        None,
+    ))
+}
+
 impl<'a> DbHarnessUser for SqliteHarnessUser<'a> {
-    fn delete(&self, id: i64) -> Result<(), Box<dyn error::Error>> {
+    fn delete(&self, id: i64) -> Result<(), HarnessError> {
+        self.connection
+            .get()?
+            .execute("DELETE FROM password_history WHERE user_id = ?", [id])?;
         self.connection
             .get()?
             .prepare("DELETE FROM users WHERE id = ?")?
@@ -44,7 +72,48 @@ impl<'a> DbHarnessUser for SqliteHarnessUser<'a> {
         return Ok(());
     }
 
-    fn insert<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), Box<dyn error::Error>>
+    fn insert_password_history(
+        &self,
+        user_id: i64,
+        secret: &str,
+        created: DateTime<Utc>,
+    ) -> Result<(), HarnessError> {
+        let connection = self.connection.get()?;
+        insert_password_history(&connection, user_id, secret, created)?;
+        Ok(())
+    }
+
+    fn read_password_history(
+        &self,
+        user_id: i64,
+        limit: usize,
+    ) -> Result<Vec<String>, HarnessError> {
+        let connection = self.connection.get()?;
+        let mut stmt = connection.prepare(
+            "SELECT secret FROM password_history WHERE user_id = :user_id
+                    ORDER BY created DESC, id DESC LIMIT :limit",
+        )?;
+        let secrets = stmt
+            .query_map(
+                named_params! {":user_id": user_id, ":limit": limit as i64},
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(secrets)
+    }
+
+    fn prune_password_history(
+        &self,
+        user_id: i64,
+        keep: usize,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<(), HarnessError> {
+        let connection = self.connection.get()?;
+        prune_password_history(&connection, user_id, keep, before)?;
+        Ok(())
+    }
+
+    fn insert<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<(), HarnessError>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
@@ -61,8 +130,10 @@ impl<'a> DbHarnessUser for SqliteHarnessUser<'a> {
         //TODO: dynamically utilize the fields in the .public_meta and .private_meta
         self.connection.get()?.execute(
             format!(
-                "INSERT INTO users (id, session_id, username, secret, ban, groups, role{})
-                    VALUES (:id, :session_id, :username, :secret, :ban, :groups, :role{})",
+                "INSERT INTO users (id, session_id, username, secret, ban, groups, role, email, status,
+                    password_changed_at, must_change_password{})
+                    VALUES (:id, :session_id, :username, :secret, :ban, :groups, :role, :email, :status,
+                    :password_changed_at, :must_change_password{})",
                 my_params, my_named_params
             )
             .as_str(),
@@ -73,123 +144,234 @@ impl<'a> DbHarnessUser for SqliteHarnessUser<'a> {
                 ":secret": user.secret(),
                 ":ban": user.is_banned(),
                 ":groups": user.groups(),
-                ":role": user.role()
+                ":role": user.role(),
+                ":email": user.email(),
+                ":status": user.status(),
+                ":password_changed_at": user.password_changed_at(),
+                ":must_change_password": user.must_change_password(),
             },
         )?;
 
         return Ok(());
     }
-    fn read<Pu, Pr>(&self, id: i64) -> Result<Option<User<Pu, Pr>>, Box<dyn error::Error>>
+    fn read<Pu, Pr>(&self, id: i64) -> Result<Option<User<Pu, Pr>>, HarnessError>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
         let conn = self.connection.get()?;
-        let res = conn.query_row("SELECT * FROM users WHERE id = ?", [id], |row| {
-            let id = row.get(0)?;
-            let session_id = row.get(1)?;
-            let username = row.get(2)?;
-            let secret = row.get(3)?;
-            let ban = row.get(4)?;
-            let groups = row.get(5)?;
-            let role = row.get(6)?;
-
-            return Ok(User::from_values(
-                id, session_id, username, secret, ban, groups, role, None, None,
-            ));
-        });
-
-        match res {
-            Ok(user) => Ok(Some(user)),
-            Err(err) => Err(err.into()),
-        }
+        let user = conn
+            .query_row(
+                format!("{} WHERE id = ?", SELECT_USER).as_str(),
+                [id],
+                from_row,
+            )
+            .optional()?;
+        Ok(user)
     }
-    fn update<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<usize, Box<dyn error::Error>>
+    fn read_by_username<Pu, Pr>(&self, username: &str) -> Result<Option<User<Pu, Pr>>, HarnessError>
     where
         Pu: PublicUserMeta,
         Pr: PrivateUserMeta,
     {
         let conn = self.connection.get()?;
-        let res = conn.execute(
-            "UPDATE users SET 
-        session_id = :session_id,
-        username = :username,
-        ban = :ban,
-        groups = :groups,
-        role = :role
-        WHERE id = :id",
-            named_params! {
-                ":id": user.id(),
-                ":session_id": user.session_id(),
-                ":username": user.username(),
-                ":ban": user.is_banned(),
-                ":groups": user.groups(),
-                ":role": user.role()
-            },
+        let user = conn
+            .query_row(
+                format!("{} WHERE username = ?", SELECT_USER).as_str(),
+                [username],
+                from_row,
+            )
+            .optional()?;
+        Ok(user)
+    }
+    fn list<Pu, Pr>(&self, offset: usize, limit: usize) -> Result<Vec<User<Pu, Pr>>, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let conn = self.connection.get()?;
+        let mut stmt = conn
+            .prepare(format!("{} ORDER BY id LIMIT :limit OFFSET :offset", SELECT_USER).as_str())?;
+        let users = stmt
+            .query_map(
+                named_params! {":limit": limit as i64, ":offset": offset as i64},
+                from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<User<Pu, Pr>>>>()?;
+        Ok(users)
+    }
+    fn search<Pu, Pr>(&self, term: &str, limit: usize) -> Result<Vec<User<Pu, Pr>>, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        // the term is matched literally, LIKE wildcards in it are escaped.
+        let pattern = format!(
+            "%{}%",
+            term.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
         );
-
-        match res {
-            Ok(res) => Ok(res),
-            Err(err) => Err(err.into()),
-        }
+        let conn = self.connection.get()?;
+        let mut stmt = conn.prepare(
+            format!(
+                "{} WHERE username LIKE :pattern ESCAPE '\\' OR email LIKE :pattern ESCAPE '\\'
+                    ORDER BY id LIMIT :limit",
+                SELECT_USER
+            )
+            .as_str(),
+        )?;
+        let users = stmt
+            .query_map(
+                named_params! {":pattern": pattern, ":limit": limit as i64},
+                from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<User<Pu, Pr>>>>()?;
+        Ok(users)
+    }
+    fn update<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<usize, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        let connection = self.connection.get()?;
+        Ok(update_user(&connection, user)?)
     }
 
-    fn create_table(
-        &self,
-        sql_string: Option<String>,
-    ) -> result::Result<(), Box<dyn error::Error>> {
-        let default_stmt = format!(
-            "CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY,
-                session_id INTEGER,
-                username STRING NOT NULL UNIQUE,
-                secret STRING NOT NULL,
-                ban TINYINT NOT NULL,
-                groups STRING NOT NULL,
-                role STRING NOT NULL,
-                FOREIGN KEY(session_id) REFERENCES sessions(id)
-            "
-        );
+    fn email_taken(&self, email: &str, user_id: i64) -> Result<bool, HarnessError> {
+        let connection = self.connection.get()?;
+        let taken = connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE email = :email AND id != :id)",
+            named_params! {":email": email, ":id": user_id},
+            |row| row.get(0),
+        )?;
+        Ok(taken)
+    }
 
-        match sql_string {
-            Some(stmt_extension) => {
-                self.connection
-                    .get()?
-                    .prepare(format!("{},{});", default_stmt, stmt_extension).as_str())?
-                    .execute([])?;
-            }
-            None => {
-                self.connection
-                    .get()?
-                    .prepare(format!("{});", default_stmt).as_str())?
-                    .execute([])?;
-            }
-        };
+    fn verify_email(&self, id: i64, email: &str) -> Result<bool, HarnessError> {
+        let connection = self.connection.get()?;
+        let rows = connection.execute(
+            "UPDATE users SET status = CASE status WHEN :pending THEN :active ELSE status END
+                WHERE id = :id AND email = :email",
+            named_params! {
+                ":pending": AccountStatus::PendingVerification,
+                ":active": AccountStatus::Active,
+                ":id": id,
+                ":email": email,
+            },
+        )?;
+        Ok(rows > 0)
+    }
 
-        return Ok(());
+    fn change_email(&self, id: i64, email: &str) -> Result<bool, HarnessError> {
+        let connection = self.connection.get()?;
+        let rows = connection.execute(
+            "UPDATE users SET email = :email, status = CASE status WHEN :pending THEN :active ELSE status END
+                WHERE id = :id AND NOT EXISTS (SELECT 1 FROM users WHERE email = :email AND id != :id)",
+            named_params! {
+                ":pending": AccountStatus::PendingVerification,
+                ":active": AccountStatus::Active,
+                ":id": id,
+                ":email": email,
+            },
+        )?;
+        Ok(rows > 0)
     }
 
-    // fn ban(&self) -> result::Result<(), Box<dyn error::Error>> {
+    // fn ban(&self) -> result::Result<(), HarnessError> {
     //     todo!()
     // }
 
-    // fn insert_group(&self) -> result::Result<(), Box<dyn error::Error>> {
+    // fn insert_group(&self) -> result::Result<(), HarnessError> {
     //     todo!()
     // }
 
-    // fn remove_group(&self) -> result::Result<(), Box<dyn error::Error>> {
+    // fn remove_group(&self) -> result::Result<(), HarnessError> {
     //     todo!()
     // }
 
-    // fn update_private(&self) -> result::Result<(), Box<dyn error::Error>> {
+    // fn update_private(&self) -> result::Result<(), HarnessError> {
     //     todo!()
     // }
 
-    // fn update_public(&self) -> result::Result<(), Box<dyn error::Error>> {
+    // fn update_public(&self) -> result::Result<(), HarnessError> {
     //     todo!()
     // }
 
-    // fn write_role(&self) -> result::Result<(), Box<dyn error::Error>> {
+    // fn write_role(&self) -> result::Result<(), HarnessError> {
     //     todo!()
     // }
 }
+
+// shared with `SqliteTransaction`, which runs the same statements inside a transaction.
+pub(super) fn update_user<Pu, Pr>(
+    connection: &Connection,
+    user: &User<Pu, Pr>,
+) -> rusqlite::Result<usize>
+where
+    Pu: PublicUserMeta,
+    Pr: PrivateUserMeta,
+{
+    connection.execute(
+        "UPDATE users SET 
+        session_id = :session_id,
+        username = :username,
+        secret = :secret,
+        ban = :ban,
+        groups = :groups,
+        role = :role,
+        email = :email,
+        status = :status,
+        password_changed_at = :password_changed_at,
+        must_change_password = :must_change_password
+        WHERE id = :id",
+        named_params! {
+            ":id": user.id(),
+            ":session_id": user.session_id(),
+            ":username": user.username(),
+            ":secret": user.secret(),
+            ":ban": user.is_banned(),
+            ":groups": user.groups(),
+            ":role": user.role(),
+            ":email": user.email(),
+            ":status": user.status(),
+            ":password_changed_at": user.password_changed_at(),
+            ":must_change_password": user.must_change_password(),
+        },
+    )
+}
+
+pub(super) fn insert_password_history(
+    connection: &Connection,
+    user_id: i64,
+    secret: &str,
+    created: DateTime<Utc>,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO password_history (user_id, secret, created)
+                VALUES (:user_id, :secret, :created)",
+        named_params! {":user_id": user_id, ":secret": secret, ":created": created},
+    )?;
+    Ok(())
+}
+
+pub(super) fn prune_password_history(
+    connection: &Connection,
+    user_id: i64,
+    keep: usize,
+    before: Option<DateTime<Utc>>,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "DELETE FROM password_history WHERE user_id = :user_id AND id NOT IN (
+                SELECT id FROM password_history WHERE user_id = :user_id
+                ORDER BY created DESC, id DESC LIMIT :keep)",
+        named_params! {":user_id": user_id, ":keep": keep as i64},
+    )?;
+    if let Some(before) = before {
+        connection.execute(
+            "DELETE FROM password_history WHERE user_id = :user_id AND created < :before",
+            named_params! {":user_id": user_id, ":before": before},
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,417 @@
+// actix-web integration. The `AuthState` is registered as app data, `AuthUser` is then available as an extractor
+// and `AuthGuard` can wrap scopes or resources that need a role or group.
+//
+// App::new()
+//     .app_data(web::Data::new(state))
+//     .configure(configure)
+//     .service(web::scope("/admin").wrap(AuthGuard::role("admin")).route("", web::get().to(admin)))
+//
+// The refresh token never reaches JavaScript, it lives in an HttpOnly cookie (see `RefreshCookie`) and only the
+// access token is returned in the body.
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    cookie::{Cookie, SameSite},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, StatusCode},
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{bearer_token, AuthBackend, AuthError, AuthState, AuthUser, TokenPair};
+
+// the managers hash with scrypt and talk to the database synchronously, keep that off the workers.
+async fn blocking<F, R>(state: &AuthState, f: F) -> Result<R, AuthError>
+where
+    F: FnOnce(&dyn AuthBackend) -> Result<R, AuthError> + Send + 'static,
+    R: Send + 'static,
+{
+    let backend = state.backend();
+    match web::block(move || f(backend.as_ref())).await {
+        Ok(res) => res,
+        Err(err) => Err(AuthError::internal(err.to_string())),
+    }
+}
+
+fn auth_state(req: &HttpRequest) -> Result<AuthState, AuthError> {
+    match req.app_data::<web::Data<AuthState>>() {
+        Some(state) => Ok(state.get_ref().clone()),
+        None => Err(AuthError::internal(String::from(
+            "AuthState is not registered as app data",
+        ))),
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    code: &'a str,
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            res.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        res.json(ErrorBody {
+            error: &self.message,
+            code: &self.code,
+        })
+    }
+}
+
+// verifies the bearer token once per request, the result is cached in the request extensions so that a guard and
+// an extractor on the same request do not both hit the database.
+async fn authenticate(req: &HttpRequest) -> Result<AuthUser, AuthError> {
+    let cached = req.extensions().get::<AuthUser>().cloned();
+    if let Some(user) = cached {
+        return Ok(user);
+    }
+
+    let state = auth_state(req)?;
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(bearer_token)
+        .map(str::to_string);
+
+    let token = match token {
+        Some(token) => token,
+        None => return Err(AuthError::unauthorized()),
+    };
+
+    let user = blocking(&state, move |backend| backend.authenticate(&token)).await?;
+    req.extensions_mut().insert(user.clone());
+    Ok(user)
+}
+
+/// Verifies the `Authorization: Bearer <token>` header, see `SessionManager::authenticate`.
+impl FromRequest for AuthUser {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Requirement {
+    Authenticated,
+    AnyRole(Vec<String>),
+    Group(String),
+}
+
+impl Requirement {
+    fn check(&self, user: &AuthUser) -> Result<(), AuthError> {
+        let allowed = match self {
+            Self::Authenticated => true,
+            Self::AnyRole(roles) => roles.iter().any(|role| user.has_role(role)),
+            Self::Group(group) => user.in_group(group),
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(AuthError::forbidden())
+        }
+    }
+}
+
+/// Middleware rejecting requests without a valid bearer token with 401, and requests whose user lacks the
+/// required role or group with 403.
+#[derive(Clone, Debug)]
+pub struct AuthGuard {
+    requirement: Requirement,
+}
+
+impl AuthGuard {
+    pub fn authenticated() -> Self {
+        Self {
+            requirement: Requirement::Authenticated,
+        }
+    }
+
+    pub fn role(role: &str) -> Self {
+        Self::any_role(&[role])
+    }
+
+    pub fn any_role(roles: &[&str]) -> Self {
+        Self {
+            requirement: Requirement::AnyRole(roles.iter().map(|role| role.to_string()).collect()),
+        }
+    }
+
+    /// Groups double as permissions, e.g. `AuthGuard::group("billing:write")`.
+    pub fn group(group: &str) -> Self {
+        Self {
+            requirement: Requirement::Group(group.to_string()),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AuthGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = AuthGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthGuardMiddleware {
+            service: Rc::new(service),
+            requirement: self.requirement.clone(),
+        }))
+    }
+}
+
+pub struct AuthGuardMiddleware<S> {
+    service: Rc<S>,
+    requirement: Requirement,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let requirement = self.requirement.clone();
+
+        Box::pin(async move {
+            let allowed = match authenticate(req.request()).await {
+                Ok(user) => requirement.check(&user),
+                Err(err) => Err(err),
+            };
+
+            match allowed {
+                Ok(()) => Ok(service.call(req).await?.map_into_left_body()),
+                Err(err) => Ok(req
+                    .into_response(err.error_response())
+                    .map_into_right_body()),
+            }
+        })
+    }
+}
+
+/// Where and how the refresh token cookie is set. Register it as app data to override the defaults,
+/// `__Host-sheesh_refresh`, path "/", Secure and SameSite=Strict.
+#[derive(Clone, Debug)]
+pub struct RefreshCookie {
+    name: String,
+    path: String,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Default for RefreshCookie {
+    fn default() -> Self {
+        Self {
+            // the __Host- prefix makes browsers refuse the cookie unless it is Secure, host only and on "/".
+            name: String::from("__Host-sheesh_refresh"),
+            path: String::from("/"),
+            secure: true,
+            same_site: SameSite::Strict,
+        }
+    }
+}
+
+impl RefreshCookie {
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Only turn this off for local development over plain http.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn cookie(&self, refresh_token: &str) -> Cookie<'static> {
+        Cookie::build(self.name.clone(), refresh_token.to_string())
+            .path(self.path.clone())
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .finish()
+    }
+
+    /// An expired cookie with the same name and path, instructs the browser to drop the refresh token.
+    pub fn removal(&self) -> Cookie<'static> {
+        let mut cookie = self.cookie("");
+        cookie.make_removal();
+        cookie
+    }
+
+    fn token_response(&self, tokens: TokenPair) -> HttpResponse {
+        HttpResponse::Ok()
+            .cookie(self.cookie(&tokens.refresh_token))
+            .json(AccessTokenResponse {
+                session_id: tokens.session_id,
+                access_token: tokens.access_token,
+                token_type: "Bearer",
+            })
+    }
+
+    pub async fn login(
+        &self,
+        req: &HttpRequest,
+        username: String,
+        password: String,
+    ) -> Result<HttpResponse, AuthError> {
+        let state = auth_state(req)?;
+        let tokens = blocking(&state, move |backend| backend.login(&username, &password)).await?;
+        Ok(self.token_response(tokens))
+    }
+
+    /// Same as `login`, answering a password change challenge with `new_password`.
+    pub async fn login_with_password_change(
+        &self,
+        req: &HttpRequest,
+        username: String,
+        password: String,
+        new_password: String,
+    ) -> Result<HttpResponse, AuthError> {
+        let state = auth_state(req)?;
+        let tokens = blocking(&state, move |backend| {
+            backend.login_with_password_change(&username, &password, &new_password)
+        })
+        .await?;
+        Ok(self.token_response(tokens))
+    }
+
+    /// Rotates the refresh token read from the cookie, the response carries the new cookie and access token.
+    pub async fn refresh(&self, req: &HttpRequest) -> Result<HttpResponse, AuthError> {
+        let state = auth_state(req)?;
+        let refresh_token = match req.cookie(&self.name) {
+            Some(cookie) => cookie.value().to_string(),
+            None => return Err(AuthError::unauthorized()),
+        };
+
+        let tokens = blocking(&state, move |backend| backend.refresh(&refresh_token)).await?;
+        Ok(self.token_response(tokens))
+    }
+
+    pub async fn logout(&self, req: &HttpRequest) -> Result<HttpResponse, AuthError> {
+        let state = auth_state(req)?;
+        let user = authenticate(req).await?;
+
+        blocking(&state, move |backend| backend.logout(user.session)).await?;
+        Ok(HttpResponse::NoContent().cookie(self.removal()).finish())
+    }
+}
+
+fn refresh_cookie(req: &HttpRequest) -> RefreshCookie {
+    match req.app_data::<web::Data<RefreshCookie>>() {
+        Some(cookie) => cookie.get_ref().clone(),
+        None => RefreshCookie::default(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+    // sent again with a new password after a "Password change required" or "Password expired" answer.
+    #[serde(default)]
+    pub new_password: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AccessTokenResponse {
+    pub session_id: i64,
+    pub access_token: String,
+    pub token_type: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: i64,
+    // the session of the token used for this request.
+    pub current: bool,
+}
+
+pub async fn login(
+    req: HttpRequest,
+    body: web::Json<LoginRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let body = body.into_inner();
+    let cookie = refresh_cookie(&req);
+    match body.new_password {
+        Some(new_password) => {
+            cookie
+                .login_with_password_change(&req, body.username, body.password, new_password)
+                .await
+        }
+        None => cookie.login(&req, body.username, body.password).await,
+    }
+}
+
+pub async fn refresh(req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    refresh_cookie(&req).refresh(&req).await
+}
+
+pub async fn logout(req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    refresh_cookie(&req).logout(&req).await
+}
+
+pub async fn sessions(req: HttpRequest, user: AuthUser) -> Result<HttpResponse, AuthError> {
+    let state = auth_state(&req)?;
+    let user_id = user.user_id;
+    let sessions = blocking(&state, move |backend| backend.sessions(user_id)).await?;
+
+    let sessions: Vec<SessionResponse> = sessions
+        .iter()
+        .map(|session| SessionResponse {
+            id: session.id(),
+            current: session.id() == user.session.id(),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// POST /login, POST /refresh, POST /logout and GET /sessions, for use with `App::configure` or `Scope::configure`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/login", web::post().to(login))
+        .route("/refresh", web::post().to(refresh))
+        .route("/logout", web::post().to(logout))
+        .route("/sessions", web::get().to(sessions));
+}
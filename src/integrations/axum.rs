@@ -0,0 +1,266 @@
+// axum integration. `router` wires the handlers, `AuthLayer` verifies the bearer token and makes `AuthUser`
+// available to every handler behind it.
+//
+// let state = AuthState::new(user_manager, session_manager);
+// let app = Router::new()
+//     .route("/me", get(me))
+//     .route_layer(AuthLayer::new(state.clone()))
+//     .nest("/auth", router(state));
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ::axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+
+use crate::Error;
+
+use super::{bearer_token, AuthBackend, AuthError, AuthState, AuthUser, TokenPair};
+
+// the managers hash with scrypt and talk to the database synchronously, keep that off the async workers.
+async fn blocking<F, R>(state: &AuthState, f: F) -> Result<R, AuthError>
+where
+    F: FnOnce(&dyn AuthBackend) -> Result<R, AuthError> + Send + 'static,
+    R: Send + 'static,
+{
+    let backend = state.backend();
+    match tokio::task::spawn_blocking(move || f(backend.as_ref())).await {
+        Ok(res) => res,
+        Err(err) => Err(AuthError::internal(err.to_string())),
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: String,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut res = (
+            status,
+            Json(ErrorBody {
+                error: self.message,
+                code: self.code,
+            }),
+        )
+            .into_response();
+
+        if status == StatusCode::UNAUTHORIZED {
+            res.headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        res
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        AuthError::from(self).into_response()
+    }
+}
+
+/// Requires the `AuthLayer` in front of the handler, responds with 401 otherwise.
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<AuthUser>() {
+            Some(user) => Ok(user.clone()),
+            None => Err(AuthError::unauthorized()),
+        }
+    }
+}
+
+/// Verifies the `Authorization: Bearer <token>` header of every request and rejects the request with 401 when
+/// it is missing or invalid.
+#[derive(Clone)]
+pub struct AuthLayer {
+    state: AuthState,
+}
+
+impl AuthLayer {
+    pub fn new(state: AuthState) -> Self {
+        Self { state }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    state: AuthState,
+}
+
+impl<S> Service<Request> for AuthService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        // the clone has not been polled ready, keep the one that was.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let state = self.state.clone();
+
+        Box::pin(async move {
+            let token = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(bearer_token)
+                .map(str::to_string);
+
+            let token = match token {
+                Some(token) => token,
+                None => return Ok(AuthError::unauthorized().into_response()),
+            };
+
+            match blocking(&state, move |backend| backend.authenticate(&token)).await {
+                Ok(user) => {
+                    req.extensions_mut().insert(user);
+                    inner.call(req).await
+                }
+                Err(err) => Ok(err.into_response()),
+            }
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+    // sent again with a new password after a "Password change required" or "Password expired" answer.
+    #[serde(default)]
+    pub new_password: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub session_id: i64,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+}
+
+impl From<TokenPair> for TokenResponse {
+    fn from(value: TokenPair) -> Self {
+        Self {
+            session_id: value.session_id,
+            access_token: value.access_token,
+            refresh_token: value.refresh_token,
+            token_type: "Bearer",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: i64,
+    // the session of the token used for this request.
+    pub current: bool,
+}
+
+pub async fn login(
+    State(state): State<AuthState>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<TokenResponse>, AuthError> {
+    let tokens = blocking(&state, move |backend| match &req.new_password {
+        Some(new_password) => {
+            backend.login_with_password_change(&req.username, &req.password, new_password)
+        }
+        None => backend.login(&req.username, &req.password),
+    })
+    .await?;
+    Ok(Json(tokens.into()))
+}
+
+/// Rotates the refresh token, the old refresh and access tokens stop working.
+pub async fn refresh(
+    State(state): State<AuthState>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<TokenResponse>, AuthError> {
+    let tokens = blocking(&state, move |backend| backend.refresh(&req.refresh_token)).await?;
+    Ok(Json(tokens.into()))
+}
+
+pub async fn logout(
+    State(state): State<AuthState>,
+    user: AuthUser,
+) -> Result<StatusCode, AuthError> {
+    blocking(&state, move |backend| backend.logout(user.session)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn sessions(
+    State(state): State<AuthState>,
+    user: AuthUser,
+) -> Result<Json<Vec<SessionResponse>>, AuthError> {
+    let user_id = user.user_id;
+    let sessions = blocking(&state, move |backend| backend.sessions(user_id)).await?;
+
+    Ok(Json(
+        sessions
+            .iter()
+            .map(|session| SessionResponse {
+                id: session.id(),
+                current: session.id() == user.session.id(),
+            })
+            .collect(),
+    ))
+}
+
+/// POST /login, POST /refresh, POST /logout and GET /sessions. The last two require a bearer token.
+pub fn router<S>(state: AuthState) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let protected = Router::new()
+        .route("/logout", post(logout))
+        .route("/sessions", get(sessions))
+        .route_layer(AuthLayer::new(state.clone()));
+
+    Router::new()
+        .route("/login", post(login))
+        .route("/refresh", post(refresh))
+        .merge(protected)
+        .with_state(state)
+}
@@ -0,0 +1,315 @@
+// Framework integrations. Everything that does not depend on a framework lives here, the framework modules
+// only translate requests and responses.
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+
+use std::{fmt::Display, sync::Arc};
+
+use crate::{
+    auth_token::{decode_token, encode_token},
+    harness::{DbHarnessSession, DbHarnessToken, DbHarnessUser},
+    id::IdGenerator,
+    session::{Session, SessionManager},
+    user::{Group, Groups, Role, User, UserManager},
+    Error, ErrorKind,
+};
+
+/// The authenticated caller of a request, produced by verifying a bearer access token.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub user_id: i64,
+    pub session: Session,
+    pub role: Role,
+    pub groups: Groups,
+}
+
+impl AuthUser {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.role.as_str() == role
+    }
+
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.contains(Group::from_str(group))
+    }
+}
+
+/// Tokens handed to the client after a login or refresh, both are "<id>.<secret>" strings (see `encode_token`).
+#[derive(Clone, Debug)]
+pub struct TokenPair {
+    pub session_id: i64,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// The blocking operations the framework integrations are built on. The managers are erased behind this trait so
+/// that handlers do not have to carry their generics around.
+pub trait AuthBackend: Send + Sync + 'static {
+    fn login(&self, username: &str, pwd: &str) -> Result<TokenPair, AuthError>;
+    // answers a password change challenge of `login`, see `UserManager::login_with_password_change`.
+    fn login_with_password_change(
+        &self,
+        username: &str,
+        pwd: &str,
+        new_pwd: &str,
+    ) -> Result<TokenPair, AuthError>;
+    fn refresh(&self, refresh_token: &str) -> Result<TokenPair, AuthError>;
+    fn authenticate(&self, access_token: &str) -> Result<AuthUser, AuthError>;
+    fn logout(&self, session: Session) -> Result<(), AuthError>;
+    fn sessions(&self, user_id: i64) -> Result<Vec<Session>, AuthError>;
+}
+
+pub struct Managers<Ui, Uh, Si, Sh, Th>
+where
+    Ui: IdGenerator,
+    Uh: DbHarnessUser,
+    Si: IdGenerator,
+    Sh: DbHarnessSession,
+    Th: DbHarnessToken,
+{
+    pub user_manager: UserManager<Ui, Uh>,
+    pub session_manager: SessionManager<Si, Sh, Th>,
+}
+
+impl<Ui, Uh, Si, Sh, Th> Managers<Ui, Uh, Si, Sh, Th>
+where
+    Ui: IdGenerator,
+    Uh: DbHarnessUser,
+    Si: IdGenerator,
+    Sh: DbHarnessSession,
+    Th: DbHarnessToken,
+{
+    fn try_login(&self, username: &str, pwd: &str) -> Result<TokenPair, Error> {
+        // an unknown username is indistinguishable from a wrong password.
+        let user: User<(), ()> = match self.user_manager.get_user_by_username(username)? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::NotAuthorized)),
+        };
+
+        let (session, refresh_secret, access_secret) =
+            self.user_manager.login(&self.session_manager, &user, pwd)?;
+        Ok(token_pair(&session, &refresh_secret, &access_secret))
+    }
+
+    fn try_login_with_password_change(
+        &self,
+        username: &str,
+        pwd: &str,
+        new_pwd: &str,
+    ) -> Result<TokenPair, Error> {
+        let user: User<(), ()> = match self.user_manager.get_user_by_username(username)? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::NotAuthorized)),
+        };
+
+        let (session, refresh_secret, access_secret) = self
+            .user_manager
+            .login_with_password_change(&self.session_manager, &user, pwd, new_pwd.to_string())?;
+        Ok(token_pair(&session, &refresh_secret, &access_secret))
+    }
+
+    fn try_refresh(&self, refresh_token: &str) -> Result<TokenPair, Error> {
+        let (token_id, secret) = match decode_token(refresh_token) {
+            Some(decoded) => decoded,
+            None => return Err(Error::new(ErrorKind::NotAuthorized)),
+        };
+
+        let session = match self
+            .session_manager
+            .get_session_by_refresh_token(token_id)?
+        {
+            Some(session) => session,
+            None => return Err(Error::new(ErrorKind::NotAuthorized)),
+        };
+
+        let (refresh_secret, access_secret) =
+            self.session_manager
+                .create_new_refresh_token(session, session.user_id(), secret)?;
+
+        // the rotation only hands back the secrets, the new token ids are on the stored session.
+        let session = self.session_manager.get_session(session.id())?;
+        Ok(token_pair(&session, &refresh_secret, &access_secret))
+    }
+
+    fn try_authenticate(&self, access_token: &str) -> Result<AuthUser, Error> {
+        let session = self.session_manager.authenticate(access_token)?;
+
+        let user: User<(), ()> = match self.user_manager.get_user(&session.user_id())? {
+            Some(user) => user,
+            None => return Err(Error::new(ErrorKind::NotAuthorized)),
+        };
+
+        // sessions issued before a ban or suspension stop working with it.
+        user.check_status()?;
+
+        Ok(AuthUser {
+            user_id: user.id(),
+            session,
+            role: user.role().clone(),
+            groups: user.groups().clone(),
+        })
+    }
+
+    fn try_sessions(&self, user_id: i64) -> Result<Vec<Session>, Error> {
+        let sessions = self.session_manager.get_user_sessions(user_id)?;
+        // logged out sessions keep their row, only the ones that can still be refreshed are listed.
+        Ok(sessions
+            .into_iter()
+            .filter(|session| session.refresh_token().is_some())
+            .collect())
+    }
+}
+
+impl<Ui, Uh, Si, Sh, Th> AuthBackend for Managers<Ui, Uh, Si, Sh, Th>
+where
+    Ui: IdGenerator + Send + Sync + 'static,
+    Uh: DbHarnessUser + Send + Sync + 'static,
+    Si: IdGenerator + Send + Sync + 'static,
+    Sh: DbHarnessSession + Send + Sync + 'static,
+    Th: DbHarnessToken + Send + Sync + 'static,
+{
+    fn login(&self, username: &str, pwd: &str) -> Result<TokenPair, AuthError> {
+        Ok(self.try_login(username, pwd)?)
+    }
+
+    fn login_with_password_change(
+        &self,
+        username: &str,
+        pwd: &str,
+        new_pwd: &str,
+    ) -> Result<TokenPair, AuthError> {
+        Ok(self.try_login_with_password_change(username, pwd, new_pwd)?)
+    }
+
+    fn refresh(&self, refresh_token: &str) -> Result<TokenPair, AuthError> {
+        Ok(self.try_refresh(refresh_token)?)
+    }
+
+    fn authenticate(&self, access_token: &str) -> Result<AuthUser, AuthError> {
+        Ok(self.try_authenticate(access_token)?)
+    }
+
+    fn logout(&self, session: Session) -> Result<(), AuthError> {
+        Ok(self.session_manager.invalidate_session(session)?)
+    }
+
+    fn sessions(&self, user_id: i64) -> Result<Vec<Session>, AuthError> {
+        Ok(self.try_sessions(user_id)?)
+    }
+}
+
+fn token_pair(session: &Session, refresh_secret: &str, access_secret: &str) -> TokenPair {
+    TokenPair {
+        session_id: session.id(),
+        // both ids are always set right after a login or a rotation.
+        access_token: encode_token(session.access_token().unwrap_or_default(), access_secret),
+        refresh_token: encode_token(session.refresh_token().unwrap_or_default(), refresh_secret),
+    }
+}
+
+/// Shared, cheaply cloneable handle to an `AuthBackend`. This is the state the framework integrations work with.
+#[derive(Clone)]
+pub struct AuthState(Arc<dyn AuthBackend>);
+
+impl AuthState {
+    pub fn new<Ui, Uh, Si, Sh, Th>(
+        user_manager: UserManager<Ui, Uh>,
+        session_manager: SessionManager<Si, Sh, Th>,
+    ) -> Self
+    where
+        Ui: IdGenerator + Send + Sync + 'static,
+        Uh: DbHarnessUser + Send + Sync + 'static,
+        Si: IdGenerator + Send + Sync + 'static,
+        Sh: DbHarnessSession + Send + Sync + 'static,
+        Th: DbHarnessToken + Send + Sync + 'static,
+    {
+        Self(Arc::new(Managers {
+            user_manager,
+            session_manager,
+        }))
+    }
+
+    pub fn from_backend<B: AuthBackend>(backend: B) -> Self {
+        Self(Arc::new(backend))
+    }
+
+    pub fn backend(&self) -> Arc<dyn AuthBackend> {
+        self.0.clone()
+    }
+}
+
+/// An `Error` ready to be sent to a client, or a failure of the integration itself such as a missing header.
+///
+/// `code` and `message` are safe to show to the client, `internal` describes the underlying failure and is meant
+/// for logs.
+#[derive(Clone, Debug)]
+pub struct AuthError {
+    pub status: u16,
+    pub code: String,
+    pub message: String,
+    pub internal: String,
+}
+
+impl AuthError {
+    pub fn new(status: u16, code: &str, message: &str) -> Self {
+        Self {
+            status,
+            code: code.to_string(),
+            message: message.to_string(),
+            internal: message.to_string(),
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        Error::new(ErrorKind::NotAuthorized).into()
+    }
+
+    pub fn forbidden() -> Self {
+        Self::new(403, "forbidden", "Forbidden")
+    }
+
+    pub fn internal(internal: String) -> Self {
+        Self {
+            status: 500,
+            code: String::from("internal_error"),
+            message: String::from("Internal Server Error"),
+            internal,
+        }
+    }
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AuthError: {} {}", self.status, self.internal)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+// veto reasons may come from a risk engine, `public_message` keeps them out of the response.
+impl From<Error> for AuthError {
+    fn from(value: Error) -> Self {
+        Self {
+            status: value.status(),
+            code: value.code().to_string(),
+            message: value.public_message(),
+            internal: value.to_string(),
+        }
+    }
+}
+
+/// Reads the token from an `Authorization: Bearer <token>` header value.
+pub fn bearer_token(header: &str) -> Option<&str> {
+    let (scheme, token) = header.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let token = token.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token)
+    }
+}
@@ -2,6 +2,7 @@ mod core;
 
 pub use core::*;
 pub mod harness;
+pub mod integrations;
 
 // re-export
 pub use scrypt;
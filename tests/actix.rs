@@ -0,0 +1,223 @@
+mod common;
+
+use actix_web::{
+    body::MessageBody,
+    cookie::Cookie,
+    dev::{Service, ServiceResponse},
+    http::{header, StatusCode},
+    test, web, App, HttpResponse,
+};
+use common::{pool, TestDb};
+use serde_json::{json, Value};
+use sheesh::{
+    harness::DbHarness,
+    integrations::{
+        actix::{configure, AuthGuard, RefreshCookie},
+        AuthState, AuthUser,
+    },
+    session::SessionManagerConfig,
+    user::{Group, Role, User, UserManagerConfig},
+};
+
+fn state(name: &str) -> (AuthState, TestDb) {
+    let (pool, db) = pool(name);
+
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let mut alice: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("admin"),
+            None,
+            None,
+        )
+        .unwrap();
+    alice.add_group(Group::from_str("billing"));
+    user_manager.update_user(alice).unwrap();
+
+    let _: User<(), ()> = user_manager
+        .create_user(
+            "bob".to_string(),
+            "battery staple correct horse".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    (AuthState::new(user_manager, session_manager), db)
+}
+
+async fn me(user: AuthUser) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "user_id": user.user_id, "role": user.role.as_str() }))
+}
+
+macro_rules! app {
+    ($state:expr) => {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new($state))
+                .app_data(web::Data::new(
+                    RefreshCookie::default().with_name("refresh"),
+                ))
+                .service(web::scope("/auth").configure(configure))
+                .route("/me", web::get().to(me))
+                .service(
+                    web::resource("/admin")
+                        .wrap(AuthGuard::role("admin"))
+                        .route(web::get().to(HttpResponse::Ok)),
+                )
+                .service(
+                    web::resource("/billing")
+                        .wrap(AuthGuard::group("billing"))
+                        .route(web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await
+    };
+}
+
+async fn login<S, B>(app: &S, username: &str, password: &str) -> (String, Cookie<'static>)
+where
+    S: Service<actix_http::Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .set_json(json!({ "username": username, "password": password }))
+        .to_request();
+    let res = test::call_service(app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let cookie = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "refresh")
+        .unwrap()
+        .into_owned();
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.secure(), Some(true));
+
+    let body: Value = test::read_body_json(res).await;
+    assert!(body.get("refresh_token").is_none());
+    (body["access_token"].as_str().unwrap().to_string(), cookie)
+}
+
+fn get(uri: &str, token: &str) -> actix_http::Request {
+    test::TestRequest::get()
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request()
+}
+
+#[actix_web::test]
+async fn cookie_refresh_flow() {
+    let (state, _db) = state("refresh");
+    let app = app!(state);
+
+    let (access, cookie) = login(&app, "alice", "correct horse battery staple").await;
+
+    let res = test::call_service(&app, get("/me", &access)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["role"], "admin");
+
+    let req = test::TestRequest::post()
+        .uri("/auth/refresh")
+        .cookie(cookie.clone())
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let rotated = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "refresh")
+        .unwrap()
+        .into_owned();
+    assert_ne!(rotated.value(), cookie.value());
+    let body: Value = test::read_body_json(res).await;
+    let new_access = body["access_token"].as_str().unwrap().to_string();
+
+    // the replaced refresh token is rejected.
+    let req = test::TestRequest::post()
+        .uri("/auth/refresh")
+        .cookie(cookie)
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = test::call_service(&app, get("/auth/sessions", &new_access)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body[0]["current"], true);
+
+    let req = test::TestRequest::post()
+        .uri("/auth/logout")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", new_access)))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let removal = res
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "refresh")
+        .unwrap();
+    assert_eq!(removal.value(), "");
+
+    let res = test::call_service(&app, get("/me", &new_access)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn extractor_rejects_missing_and_bad_tokens() {
+    let (state, _db) = state("extractor");
+    let app = app!(state);
+
+    let req = test::TestRequest::get().uri("/me").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        res.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+        "Bearer"
+    );
+
+    let res = test::call_service(&app, get("/me", "1.forged")).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .set_json(json!({ "username": "alice", "password": "wrong" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn guards_check_role_and_group() {
+    let (state, _db) = state("guards");
+    let app = app!(state);
+
+    let (alice, _) = login(&app, "alice", "correct horse battery staple").await;
+    let (bob, _) = login(&app, "bob", "battery staple correct horse").await;
+
+    let res = test::call_service(&app, get("/admin", &alice)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, get("/billing", &alice)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = test::call_service(&app, get("/admin", &bob)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = test::call_service(&app, get("/billing", &bob)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let req = test::TestRequest::get().uri("/admin").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
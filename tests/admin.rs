@@ -0,0 +1,134 @@
+mod common;
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use common::TestDb;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde_json::Value;
+use sheesh::{
+    harness::DbHarness,
+    session::SessionManagerConfig,
+    user::{User, UserManagerConfig},
+};
+
+// runs the binary with `stdin` piped in, returns the exit code and stdout.
+fn admin(db: &Path, args: &[&str], stdin: &str) -> (i32, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sheesh-admin"))
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn admin_json(db: &Path, args: &[&str], stdin: &str) -> Value {
+    let mut args = args.to_vec();
+    args.push("--json");
+    let (code, stdout) = admin(db, &args, stdin);
+    assert_eq!(code, 0, "sheesh-admin {:?} failed", args);
+    serde_json::from_str(&stdout).unwrap()
+}
+
+#[test]
+fn manages_users() {
+    let db = TestDb::new("users");
+    let path = db.path.as_path();
+    admin_json(path, &["migrate"], "");
+
+    let alice = admin_json(
+        path,
+        &["user", "create", "alice", "--email", "alice@example.com"],
+        "correct horse battery staple\n",
+    );
+    assert_eq!(alice["role"], "user");
+    admin_json(
+        path,
+        &["user", "create", "bob", "--role", "support"],
+        "another long passphrase\n",
+    );
+
+    let found = admin_json(path, &["user", "search", "example.com"], "");
+    assert_eq!(found.as_array().unwrap().len(), 1);
+    assert_eq!(found[0]["username"], "alice");
+
+    admin_json(path, &["user", "set-role", "alice", "admin"], "");
+    admin_json(path, &["user", "add-group", "alice", "ops"], "");
+    let id = alice["id"].to_string();
+    let alice = admin_json(path, &["user", "ban", &id], "");
+    assert_eq!(alice["role"], "admin");
+    assert_eq!(alice["groups"], serde_json::json!(["ops"]));
+    assert_eq!(alice["banned"], true);
+
+    let alice = admin_json(
+        path,
+        &["user", "reset-password", "alice", "--must-change"],
+        "a temporary passphrase\n",
+    );
+    assert_eq!(alice["must_change_password"], true);
+
+    let users = admin_json(path, &["user", "list", "--limit", "1", "--offset", "1"], "");
+    assert_eq!(users.as_array().unwrap().len(), 1);
+
+    // usage errors exit with 2, failed commands with 1.
+    assert_eq!(admin(path, &["user", "promote", "alice"], "").0, 2);
+    assert_eq!(admin(path, &["user", "show", "carol"], "").0, 1);
+    assert_eq!(admin(path, &["user", "reset-password", "bob"], "").0, 2);
+}
+
+#[test]
+fn manages_sessions() {
+    let db = TestDb::new("sessions");
+    let path = db.path.as_path();
+    admin_json(path, &["migrate"], "");
+
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file(path)).unwrap();
+    let harness = DbHarness::new_sqlite(pool);
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            sheesh::user::Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    for _ in 0..2 {
+        session_manager.new_session(user.id()).unwrap();
+    }
+
+    let sessions = admin_json(path, &["session", "list", "alice"], "");
+    assert_eq!(sessions.as_array().unwrap().len(), 2);
+    let revoked = sessions[0]["id"].to_string();
+    admin_json(path, &["session", "revoke", &revoked], "");
+
+    let sweep = admin_json(path, &["sweep"], "");
+    assert_eq!(sweep["tokens"], 0);
+    assert_eq!(sweep["sessions"], 1);
+
+    admin_json(path, &["session", "revoke-all", "alice"], "");
+    let sessions = admin_json(path, &["session", "list", "alice"], "");
+    assert_eq!(sessions[0]["refresh_token"], Value::Null);
+    assert_eq!(sessions[0]["access_token"], Value::Null);
+}
@@ -0,0 +1,218 @@
+mod common;
+
+use std::{sync::Arc, thread};
+
+use chrono::{TimeDelta, Utc};
+use common::pool;
+use sheesh::{
+    audit::{chain_hash, AuditEvent, AuditEventKind, AuditQuery, AuditSink, GENESIS_HASH},
+    clock::{Clock, MockClock},
+    harness::{sqlite::SqliteAuditSink, DbHarness},
+    pepper::Pepper,
+    session::SessionManagerConfig,
+    user::{Role, User, UserManagerConfig},
+};
+
+fn kinds(events: &[AuditEvent]) -> Vec<AuditEventKind> {
+    events.iter().map(|event| event.kind()).collect()
+}
+
+#[test]
+fn managers_record_account_events() {
+    let (pool, _db) = pool("events");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let sink = Arc::new(SqliteAuditSink::new(pool));
+
+    let user_manager = UserManagerConfig::default()
+        .with_audit_sink(sink.clone())
+        .init(harness.user)
+        .unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .with_audit_sink(sink.clone())
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let start = Utc::now();
+    let mut user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    assert!(user_manager
+        .login(&session_manager, &user, "wrong")
+        .is_err());
+    let (session, refresh, _) = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap();
+    session_manager
+        .create_new_refresh_token(session, user.id(), &refresh)
+        .unwrap();
+    let session = session_manager.get_session(session.id()).unwrap();
+    session_manager.invalidate_session(session).unwrap();
+
+    user.set_role(Role::from_str("admin"));
+    user.ban();
+    user_manager.update_user(user.clone()).unwrap();
+    user.unban();
+    user_manager.update_user(user.clone()).unwrap();
+    user_manager
+        .update_password(user.clone(), "a brand new passphrase".to_string())
+        .unwrap();
+
+    // the result, errors included, can be handed to another thread.
+    let query = AuditQuery::new().with_user(user.id());
+    let events = thread::spawn({
+        let sink = sink.clone();
+        move || sink.query(&query)
+    })
+    .join()
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        kinds(&events),
+        vec![
+            AuditEventKind::UserCreated,
+            AuditEventKind::LoginFailed,
+            AuditEventKind::LoginSucceeded,
+            AuditEventKind::TokenRefreshed,
+            AuditEventKind::Logout,
+            AuditEventKind::Banned,
+            AuditEventKind::RoleChanged,
+            AuditEventKind::Unbanned,
+            AuditEventKind::PasswordChanged,
+        ]
+    );
+
+    let changes = sink
+        .query(
+            &AuditQuery::new()
+                .with_kind(AuditEventKind::RoleChanged)
+                .with_kind(AuditEventKind::LoginFailed),
+        )
+        .unwrap();
+    assert_eq!(
+        kinds(&changes),
+        vec![AuditEventKind::LoginFailed, AuditEventKind::RoleChanged]
+    );
+    assert_eq!(changes[1].detail(), Some("user -> admin"));
+
+    let before = AuditQuery::new().with_until(start);
+    assert!(sink.query(&before).unwrap().is_empty());
+    let since = AuditQuery::new()
+        .with_since(start)
+        .with_until(Utc::now() + TimeDelta::minutes(1))
+        .with_limit(2);
+    assert_eq!(
+        kinds(&sink.query(&since).unwrap()),
+        vec![AuditEventKind::UserCreated, AuditEventKind::LoginFailed]
+    );
+
+    assert!(sink
+        .query(&AuditQuery::new().with_user(user.id() + 1))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn tampering_breaks_the_hash_chain() {
+    let (pool, _db) = pool("chain");
+    DbHarness::new_sqlite(pool.clone()).migrate().unwrap();
+    let sink = SqliteAuditSink::new(pool.clone());
+
+    let clock = MockClock::new(Utc::now() - TimeDelta::days(1));
+    for user_id in 1..=4 {
+        sink.record(&AuditEvent::new(
+            &clock,
+            AuditEventKind::LoginSucceeded,
+            Some(user_id),
+            None,
+        ))
+        .unwrap();
+    }
+    assert_eq!(sink.verify().unwrap(), None);
+    assert_eq!(sink.head().unwrap().unwrap().0, 4);
+    let events = sink.query(&AuditQuery::new()).unwrap();
+    assert!(events.iter().all(|event| event.time() == clock.now()));
+
+    let connection = pool.get().unwrap();
+    connection
+        .execute("UPDATE audit_log SET user_id = 9 WHERE seq = 3", [])
+        .unwrap();
+    assert_eq!(sink.verify().unwrap(), Some(3));
+
+    // putting the value back repairs the record, removing one breaks the chain at the next.
+    connection
+        .execute("UPDATE audit_log SET user_id = 3 WHERE seq = 3", [])
+        .unwrap();
+    assert_eq!(sink.verify().unwrap(), None);
+    connection
+        .execute("DELETE FROM audit_log WHERE seq = 2", [])
+        .unwrap();
+    assert_eq!(sink.verify().unwrap(), Some(3));
+
+    // a missing detail and an empty one are different records.
+    let event = AuditEvent::new(&clock, AuditEventKind::Logout, None, None);
+    let empty = AuditEvent::new(&clock, AuditEventKind::Logout, None, Some(String::new()));
+    assert_ne!(
+        chain_hash(None, GENESIS_HASH, 1, &event).unwrap(),
+        chain_hash(None, GENESIS_HASH, 1, &empty).unwrap()
+    );
+}
+
+#[test]
+fn a_keyed_chain_can_not_be_recomputed_without_the_key() {
+    let (pool, _db) = pool("keyed");
+    let pepper = Pepper::new(1, "a pepper key of thirty two bytes");
+    DbHarness::new_sqlite(pool.clone()).migrate().unwrap();
+    let sink = SqliteAuditSink::new(pool.clone()).with_pepper(pepper.clone());
+
+    let clock = MockClock::new(Utc::now());
+    for user_id in 1..=3 {
+        sink.record(&AuditEvent::new(
+            &clock,
+            AuditEventKind::LoginSucceeded,
+            Some(user_id),
+            None,
+        ))
+        .unwrap();
+    }
+    assert_eq!(sink.verify().unwrap(), None);
+    assert!(sink.head().unwrap().unwrap().1.starts_with("pepper:1:"));
+    assert_eq!(
+        SqliteAuditSink::new(pool.clone()).verify().unwrap(),
+        Some(1)
+    );
+
+    // the rotated key still verifies the records written under the retired one.
+    let rotated = Pepper::new(2, "another pepper key of 32 bytes!!")
+        .with_retired_key(1, "a pepper key of thirty two bytes");
+    let rotated = SqliteAuditSink::new(pool.clone()).with_pepper(rotated);
+    assert_eq!(rotated.verify().unwrap(), None);
+
+    // an edit with every later hash recomputed, as far as that is possible without the key.
+    let connection = pool.get().unwrap();
+    connection
+        .execute("UPDATE audit_log SET user_id = 9 WHERE seq = 3", [])
+        .unwrap();
+    let forged =
+        AuditEvent::from_values(AuditEventKind::LoginSucceeded, Some(9), clock.now(), None);
+    let prev_hash: String = connection
+        .query_row("SELECT hash FROM audit_log WHERE seq = 2", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    connection
+        .execute(
+            "UPDATE audit_log SET hash = ? WHERE seq = 3",
+            [chain_hash(None, &prev_hash, 3, &forged).unwrap()],
+        )
+        .unwrap();
+    assert_eq!(sink.verify().unwrap(), Some(3));
+    assert_eq!(rotated.verify().unwrap(), Some(3));
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use common::{pool, TestDb};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use sheesh::{
+    harness::DbHarness,
+    integrations::{
+        axum::{router, AuthLayer},
+        AuthState, AuthUser,
+    },
+    session::SessionManagerConfig,
+    user::{Role, User, UserManagerConfig},
+};
+use tower::ServiceExt;
+
+fn app(name: &str) -> (Router, TestDb) {
+    let (pool, db) = pool(name);
+
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let _: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("admin"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    let state = AuthState::new(user_manager, session_manager);
+    let app = Router::new()
+        .route("/me", get(me))
+        .route_layer(AuthLayer::new(state.clone()))
+        .nest("/auth", router(state));
+
+    (app, db)
+}
+
+async fn me(user: AuthUser) -> Json<Value> {
+    Json(json!({ "user_id": user.user_id, "role": user.role.as_str() }))
+}
+
+async fn send(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let bytes = res.into_body().collect().await.unwrap().to_bytes();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, body)
+}
+
+fn post_json(uri: &str, body: Value) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn with_bearer(method: &str, uri: &str, token: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn login(app: &Router) -> Value {
+    let (status, body) = send(
+        app,
+        post_json(
+            "/auth/login",
+            json!({ "username": "alice", "password": "correct horse battery staple" }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn login_refresh_logout() {
+    let (app, _db) = app("flow");
+
+    let tokens = login(&app).await;
+    let access = tokens["access_token"].as_str().unwrap().to_string();
+    let refresh = tokens["refresh_token"].as_str().unwrap().to_string();
+
+    let (status, body) = send(&app, with_bearer("GET", "/me", &access)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["role"], "admin");
+
+    let (status, body) = send(&app, with_bearer("GET", "/auth/sessions", &access)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["id"], tokens["session_id"]);
+    assert_eq!(body[0]["current"], true);
+
+    let (status, rotated) = send(
+        &app,
+        post_json("/auth/refresh", json!({ "refresh_token": refresh })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let new_access = rotated["access_token"].as_str().unwrap().to_string();
+
+    // the old access token went with the rotation, and the old refresh token can not be replayed.
+    let (status, _) = send(&app, with_bearer("GET", "/me", &access)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(
+        &app,
+        post_json("/auth/refresh", json!({ "refresh_token": refresh })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(&app, with_bearer("POST", "/auth/logout", &new_access)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = send(&app, with_bearer("GET", "/me", &new_access)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bad_credentials_are_unauthorized() {
+    let (app, _db) = app("credentials");
+
+    let (status, body) = send(
+        &app,
+        post_json(
+            "/auth/login",
+            json!({ "username": "alice", "password": "wrong" }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["error"].is_string());
+
+    // unknown users get the same answer as a wrong password.
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/auth/login",
+            json!({ "username": "mallory", "password": "correct horse battery staple" }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn protected_routes_require_a_bearer_token() {
+    let (app, _db) = app("bearer");
+
+    let req = Request::get("/me").body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+    let (status, _) = send(&app, with_bearer("GET", "/me", "garbage")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, with_bearer("GET", "/me", "1.forged")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let tokens = login(&app).await;
+    let refresh = tokens["refresh_token"].as_str().unwrap();
+    // a refresh token is not an access token.
+    let (status, _) = send(&app, with_bearer("GET", "/auth/sessions", refresh)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use common::pool;
+use sheesh::{
+    client::ClientManagerConfig,
+    clock::MockClock,
+    harness::{sqlite::SqliteHarnessClient, DbHarness},
+    scope::Scopes,
+};
+
+#[test]
+fn client_credentials_reject_bad_requests() {
+    let (pool, _db) = pool("rejected");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let client_manager = ClientManagerConfig::default()
+        .init(SqliteHarnessClient::new(pool), harness.token)
+        .unwrap();
+
+    let (client, secret) = client_manager
+        .create_client("reports".to_string(), Scopes::from_slice(&["reports:read"]))
+        .unwrap();
+
+    let err = client_manager
+        .client_credentials(client.id(), "wrong secret", None)
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_client");
+
+    // an unknown client looks the same as a wrong secret.
+    let err = client_manager
+        .client_credentials(client.id() ^ 1, &secret, None)
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_client");
+
+    let err = client_manager
+        .client_credentials(
+            client.id(),
+            &secret,
+            Some(&Scopes::from_slice(&["reports:read", "reports:write"])),
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_scope");
+}
+
+#[test]
+fn client_tokens_belong_to_the_client() {
+    let (pool, _db) = pool("tokens");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let clock = MockClock::default();
+    let client_manager = ClientManagerConfig::default()
+        .with_access_ttl(TimeDelta::minutes(5))
+        .with_clock(Arc::new(clock.clone()))
+        .init(SqliteHarnessClient::new(pool), harness.token)
+        .unwrap();
+
+    let (client, secret) = client_manager
+        .create_client(
+            "reports".to_string(),
+            Scopes::from_slice(&["reports:read", "reports:write"]),
+        )
+        .unwrap();
+
+    let (token, token_secret, granted) = client_manager
+        .client_credentials(
+            client.id(),
+            &secret,
+            Some(&Scopes::from_slice(&["reports:read"])),
+        )
+        .unwrap();
+    assert_eq!(token.client_id(), Some(client.id()));
+    assert_eq!(token.user_id(), None);
+    assert_eq!(granted, Scopes::from_slice(&["reports:read"]));
+
+    client_manager
+        .verify_access_token_with_scope(token.id(), client.id(), &token_secret, &["reports:read"])
+        .unwrap();
+    assert!(client_manager
+        .verify_access_token_with_scope(token.id(), client.id(), &token_secret, &["reports:write"])
+        .is_err());
+
+    clock.advance(TimeDelta::minutes(6));
+    let err = client_manager
+        .verify_access_token(token.id(), client.id(), &token_secret)
+        .unwrap_err();
+    assert_eq!(err.code(), "token_expired");
+
+    // the tokens of a deleted client stop working with it.
+    let (token, token_secret, _) = client_manager
+        .client_credentials(client.id(), &secret, None)
+        .unwrap();
+    client_manager.delete_client(client.id()).unwrap();
+    assert!(client_manager.get_client(client.id()).unwrap().is_none());
+    assert!(client_manager
+        .verify_access_token(token.id(), client.id(), &token_secret)
+        .is_err());
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use common::pool;
+use sheesh::{
+    auth_token::encode_token,
+    clock::{Clock, MockClock},
+    harness::DbHarness,
+    session::SessionManagerConfig,
+    user::{PasswordChangeReason, Role, User, UserManagerConfig},
+};
+
+#[test]
+fn tokens_expire_when_the_clock_moves() {
+    let (pool, _db) = pool("tokens");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let clock = MockClock::default();
+    let session_manager = SessionManagerConfig::default()
+        .with_clock(Arc::new(clock.clone()))
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let (session, refresh, access) = session_manager.new_session(1).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+    session_manager.authenticate(&access).unwrap();
+
+    clock.advance(session_manager.access_ttl() + TimeDelta::minutes(1));
+    let err = session_manager.authenticate(&access).unwrap_err();
+    assert_eq!(err.code(), "token_expired");
+
+    // the refresh token outlives the access token.
+    let (refresh, _) = session_manager
+        .create_new_refresh_token(session, 1, &refresh)
+        .unwrap();
+
+    clock.advance(session_manager.refresh_ttl() + TimeDelta::minutes(1));
+    let session = session_manager.get_session(session.id()).unwrap();
+    let err = session_manager
+        .create_new_refresh_token(session, 1, &refresh)
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_credentials");
+}
+
+#[test]
+fn passwords_age_with_the_clock() {
+    let (pool, _db) = pool("passwords");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let clock = MockClock::default();
+    let user_manager = UserManagerConfig::default()
+        .with_password_max_age(TimeDelta::days(90))
+        .with_clock(Arc::new(clock.clone()))
+        .init(harness.user)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(user.password_changed_at(), Some(clock.now()));
+
+    clock.advance(TimeDelta::days(89));
+    assert_eq!(user_manager.password_change_required(&user), None);
+    clock.advance(TimeDelta::days(2));
+    assert_eq!(
+        user_manager.password_change_required(&user),
+        Some(PasswordChangeReason::Expired)
+    );
+}
@@ -0,0 +1,37 @@
+// shared by every integration test, not all of them use every helper.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use r2d2_sqlite::SqliteConnectionManager;
+
+/// A temporary SQLite file, removed when dropped.
+pub struct TestDb {
+    pub path: PathBuf,
+}
+
+impl TestDb {
+    /// `name` keeps the tests of one file apart, the test binary and process id the test runs.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "sheesh_{}_{}_{}.db",
+            env!("CARGO_CRATE_NAME"),
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        TestDb { path }
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub fn pool(name: &str) -> (r2d2::Pool<SqliteConnectionManager>, TestDb) {
+    let db = TestDb::new(name);
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file(&db.path)).unwrap();
+    (pool, db)
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::TimeDelta;
+use common::pool;
+use sheesh::{
+    auth_token::encode_token,
+    device::DeviceManagerConfig,
+    harness::{
+        sqlite::{
+            SqliteHarnessDevice, SqliteHarnessOneTimeToken, SqliteHarnessSession,
+            SqliteHarnessToken,
+        },
+        DbHarness,
+    },
+    id::IdGenerator,
+    one_time::InMemoryDelivery,
+    password_policy::PasswordPolicy,
+    password_reset::PasswordResetConfig,
+    passwordless::PasswordlessConfig,
+    session::SessionManagerConfig,
+    sweeper::SweeperConfig,
+    user::{Role, User, UserManagerConfig},
+    verification::VerificationConfig,
+    Error, ErrorKind,
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1000);
+
+#[derive(Clone, Copy)]
+struct SequentialIds;
+
+impl IdGenerator for SequentialIds {
+    fn new_u64(&self) -> u64 {
+        NEXT_ID.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn new_u128(&self) -> u128 {
+        self.new_u64() as u128
+    }
+}
+
+// fast enough for a test, never for production.
+fn reversed(secret: &str, salt: &str) -> Result<String, Error> {
+    Ok(format!(
+        "{}${}",
+        salt,
+        secret.chars().rev().collect::<String>()
+    ))
+}
+
+fn is_reversed(secret: &str, hash: &str) -> Result<(), Error> {
+    match hash.split_once('$') {
+        Some((salt, _)) if reversed(secret, salt)? == hash => Ok(()),
+        _ => Err(Error::new(ErrorKind::NotAuthorized)),
+    }
+}
+
+#[test]
+fn builders_take_durations_and_custom_functions() {
+    let (pool, _db) = pool("builders");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    // a closure that captures its state.
+    let salt = "salt".to_string();
+    let user_manager = UserManagerConfig::default()
+        .with_salt_fn(Arc::new(move || salt.clone()))
+        .with_hash_fn(Arc::new(reversed))
+        .with_verify_fn(Arc::new(is_reversed))
+        .init(harness.user)
+        .unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .with_id_gen(SequentialIds)
+        .with_access_ttl(Duration::from_secs(5 * 60))
+        .with_refresh_ttl(TimeDelta::hours(1))
+        .with_hash_fn(Arc::new(reversed))
+        .with_verify_fn(Arc::new(is_reversed))
+        .init(harness.session, harness.token)
+        .unwrap();
+    assert_eq!(session_manager.access_ttl(), TimeDelta::minutes(5));
+    assert_eq!(session_manager.refresh_ttl(), TimeDelta::hours(1));
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(user.secret(), "salt$elpats yrettab esroh tcerroc");
+
+    let (session, refresh, access) = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap();
+    // the session and both of its tokens got their ids from the custom generator.
+    assert!(session.id() >= 1000);
+    assert!(session.refresh_token().unwrap() >= 1000);
+    session_manager
+        .authenticate(&encode_token(session.access_token().unwrap(), &access))
+        .unwrap();
+    session_manager
+        .create_new_refresh_token(session, user.id(), &refresh)
+        .unwrap();
+}
+
+#[test]
+fn init_rejects_invalid_settings() {
+    fn rejected<M>(res: Result<M, Error>) -> &'static str {
+        match res.err().map(|err| err.kind) {
+            Some(ErrorKind::InvalidConfig(setting)) => setting,
+            _ => panic!("expected the config to be rejected"),
+        }
+    }
+
+    let (pool, _db) = pool("rejected");
+    let sessions = |config: SessionManagerConfig<_>| {
+        let harness = DbHarness::new_sqlite(pool.clone());
+        rejected(config.init(harness.session, harness.token))
+    };
+    assert_eq!(
+        sessions(SessionManagerConfig::default().with_access_ttl(TimeDelta::zero())),
+        "access token ttl"
+    );
+    assert_eq!(
+        sessions(SessionManagerConfig::default().with_refresh_ttl(Duration::MAX)),
+        "refresh token ttl"
+    );
+    assert_eq!(
+        sessions(
+            SessionManagerConfig::default()
+                .with_access_ttl(TimeDelta::hours(2))
+                .with_refresh_ttl(TimeDelta::hours(1))
+        ),
+        "refresh token ttl shorter than the access token ttl"
+    );
+
+    let users = |config: UserManagerConfig<_>| {
+        rejected(config.init(DbHarness::new_sqlite(pool.clone()).user))
+    };
+    assert_eq!(
+        users(UserManagerConfig::default().with_password_max_age(TimeDelta::zero())),
+        "password max age"
+    );
+    assert_eq!(
+        users(UserManagerConfig::default().with_password_history_retention(Duration::MAX)),
+        "password history retention"
+    );
+    assert_eq!(
+        users(
+            UserManagerConfig::default()
+                .with_password_policy(PasswordPolicy::default().with_min_length(200))
+        ),
+        "password min length above the max length"
+    );
+
+    let one_time = || SqliteHarnessOneTimeToken::new(pool.clone());
+    let delivery = InMemoryDelivery::new();
+    assert_eq!(
+        rejected(
+            PasswordResetConfig::default()
+                .with_ttl(TimeDelta::minutes(-15))
+                .init(one_time(), &delivery)
+        ),
+        "password reset ttl"
+    );
+    assert_eq!(
+        rejected(
+            PasswordlessConfig::default()
+                .with_ttl(TimeDelta::zero())
+                .init(one_time(), &delivery)
+        ),
+        "passwordless ttl"
+    );
+    assert_eq!(
+        rejected(
+            VerificationConfig::default()
+                .with_ttl(Duration::MAX)
+                .init(one_time(), &delivery)
+        ),
+        "verification ttl"
+    );
+    assert_eq!(
+        rejected(
+            DeviceManagerConfig::new("https://example.com/device")
+                .with_ttl(Duration::ZERO)
+                .init(SqliteHarnessDevice::new(pool.clone()))
+        ),
+        "device ttl"
+    );
+    assert_eq!(
+        rejected(
+            DeviceManagerConfig::new("https://example.com/device")
+                .with_interval(0)
+                .init(SqliteHarnessDevice::new(pool.clone()))
+        ),
+        "device polling interval not positive"
+    );
+    assert_eq!(
+        rejected(
+            DeviceManagerConfig::new("https://example.com/device")
+                .with_user_code_len(0)
+                .init(SqliteHarnessDevice::new(pool.clone()))
+        ),
+        "device user code length not positive"
+    );
+    assert_eq!(
+        rejected(
+            SweeperConfig::default()
+                .with_interval(TimeDelta::minutes(-1))
+                .init(
+                    SqliteHarnessSession::new(pool.clone()),
+                    SqliteHarnessToken::new(pool.clone())
+                )
+        ),
+        "sweep interval"
+    );
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::pool;
+use sheesh::{
+    auth_token::encode_token,
+    cookie::{CookieConfig, SameSite},
+    harness::DbHarness,
+    session::SessionManagerConfig,
+};
+
+#[test]
+fn csrf_tokens_are_bound_to_the_session_and_access_token() {
+    let (pool, _db) = pool("csrf");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let config = CookieConfig::default();
+
+    let (session, refresh, access) = session_manager.new_session(7).unwrap();
+    let cookies = session_manager
+        .session_cookies(&config, &session, &refresh, &access)
+        .unwrap();
+    let access = cookies.access.value.clone();
+    let csrf = cookies.csrf_token().to_string();
+    session_manager
+        .authenticate_with_csrf(&access, &csrf)
+        .unwrap();
+    assert!(session_manager
+        .authenticate_with_csrf(&access, "forged")
+        .is_err());
+
+    // another session's token does not fit.
+    let (other, other_refresh, other_access) = session_manager.new_session(8).unwrap();
+    let other_cookies = session_manager
+        .session_cookies(&config, &other, &other_refresh, &other_access)
+        .unwrap();
+    assert!(session_manager
+        .authenticate_with_csrf(&access, other_cookies.csrf_token())
+        .is_err());
+    assert!(session_manager
+        .verify_csrf_token(&other, &other_cookies.access.value, &csrf)
+        .is_err());
+
+    // a rotation issues a new access token, the old CSRF token goes with the old one.
+    let (refresh, new_access) = session_manager
+        .create_new_refresh_token(session, 7, &refresh)
+        .unwrap();
+    let session = session_manager.get_session(session.id()).unwrap();
+    let rotated = session_manager
+        .session_cookies(&config, &session, &refresh, &new_access)
+        .unwrap();
+    assert_ne!(rotated.csrf_token(), csrf);
+    let new_access = encode_token(session.access_token().unwrap(), &new_access);
+    assert!(session_manager
+        .verify_csrf_token(&session, &new_access, &csrf)
+        .is_err());
+    session_manager
+        .authenticate_with_csrf(&new_access, rotated.csrf_token())
+        .unwrap();
+}
+
+#[test]
+fn session_cookies_carry_their_attributes() {
+    let (pool, _db) = pool("attributes");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let (session, refresh, access) = session_manager.new_session(7).unwrap();
+
+    let cookies = session_manager
+        .session_cookies(&CookieConfig::default(), &session, &refresh, &access)
+        .unwrap();
+    assert!(cookies.access.http_only && cookies.refresh.http_only);
+    // the client has to read the CSRF token.
+    assert!(!cookies.csrf.http_only);
+    for cookie in [&cookies.access, &cookies.refresh, &cookies.csrf] {
+        assert!(cookie.secure);
+        assert_eq!(cookie.same_site, SameSite::Strict);
+    }
+    assert_eq!(
+        cookies.refresh.max_age,
+        session_manager.refresh_ttl().num_seconds()
+    );
+    let [access_header, _, csrf_header] = cookies.to_header_values();
+    assert!(access_header.ends_with("; SameSite=Strict; HttpOnly; Secure"));
+    assert!(csrf_header.ends_with("; SameSite=Strict; Secure"));
+
+    let config = CookieConfig::default()
+        .with_secure(false)
+        .with_same_site(SameSite::Lax)
+        .with_refresh_path("/auth/refresh");
+    let cookies = session_manager
+        .session_cookies(&config, &session, &refresh, &access)
+        .unwrap();
+    assert!(!cookies.access.secure);
+    assert_eq!(cookies.access.same_site, SameSite::Lax);
+    assert_eq!(cookies.refresh.path, "/auth/refresh");
+    assert!(session_manager
+        .removal_cookies(&config)
+        .to_header_values()
+        .iter()
+        .all(|header| header.contains("Max-Age=0")));
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use common::pool;
+use sheesh::{
+    clock::MockClock,
+    device::DeviceManagerConfig,
+    harness::{
+        sqlite::{SqliteHarnessDevice, SqliteHarnessUser},
+        DbHarness,
+    },
+    id::DefaultIdGenerator,
+    session::SessionManagerConfig,
+    user::{Role, User, UserManager, UserManagerConfig},
+};
+
+fn create_user(user_manager: &UserManager<DefaultIdGenerator, SqliteHarnessUser>) -> User<(), ()> {
+    user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap()
+}
+
+#[test]
+fn device_polls_until_the_user_decides() {
+    let (pool, _db) = pool("decided");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let clock = MockClock::default();
+    let device_manager = DeviceManagerConfig::new("https://example.com/device")
+        .with_clock(Arc::new(clock.clone()))
+        .init(SqliteHarnessDevice::new(pool))
+        .unwrap();
+    let user = create_user(&user_manager);
+
+    let start = device_manager.start().unwrap();
+    let poll = || device_manager.poll(&user_manager, &session_manager, &start.device_code);
+    assert_eq!(poll().unwrap_err().code(), "authorization_pending");
+    // polled again right away.
+    assert_eq!(poll().unwrap_err().code(), "slow_down");
+
+    device_manager.approve(&user, &start.user_code).unwrap();
+    // a decision is final.
+    assert!(device_manager.deny(&start.user_code).is_err());
+
+    clock.advance(TimeDelta::minutes(1));
+    let (session, _, _) = poll().unwrap();
+    assert_eq!(session.user_id(), user.id());
+
+    // the device code and the user code are single use.
+    assert_eq!(poll().unwrap_err().code(), "invalid_device_code");
+    assert_eq!(
+        device_manager
+            .approve(&user, &start.user_code)
+            .unwrap_err()
+            .code(),
+        "invalid_user_code"
+    );
+
+    let denied = device_manager.start().unwrap();
+    device_manager.deny(&denied.user_code).unwrap();
+    let err = device_manager
+        .poll(&user_manager, &session_manager, &denied.device_code)
+        .unwrap_err();
+    assert_eq!(err.code(), "access_denied");
+}
+
+#[test]
+fn expired_codes_and_banned_users_get_no_session() {
+    let (pool, _db) = pool("refused");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let clock = MockClock::default();
+    let device_manager = DeviceManagerConfig::new("https://example.com/device")
+        .with_clock(Arc::new(clock.clone()))
+        .init(SqliteHarnessDevice::new(pool))
+        .unwrap();
+    let mut user = create_user(&user_manager);
+
+    // banned after approving, before the device picked up its session.
+    let start = device_manager.start().unwrap();
+    device_manager.approve(&user, &start.user_code).unwrap();
+    user.ban();
+    user_manager.update_user(user.clone()).unwrap();
+    let err = device_manager
+        .poll(&user_manager, &session_manager, &start.device_code)
+        .unwrap_err();
+    assert_eq!(err.code(), "access_denied");
+    assert!(session_manager
+        .get_user_sessions(user.id())
+        .unwrap()
+        .is_empty());
+
+    let expired = device_manager.start().unwrap();
+    clock.advance(TimeDelta::minutes(11));
+    assert_eq!(
+        device_manager
+            .get_by_user_code(&expired.user_code)
+            .unwrap_err()
+            .code(),
+        "token_expired"
+    );
+    let err = device_manager
+        .poll(&user_manager, &session_manager, &expired.device_code)
+        .unwrap_err();
+    assert_eq!(err.code(), "token_expired");
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use std::error::Error as _;
+
+use common::pool;
+use sheesh::{
+    harness::DbHarness,
+    integrations::AuthError,
+    listener::Veto,
+    session::SessionManagerConfig,
+    user::{AccountStatus, Role, User, UserManagerConfig},
+    Error, ErrorKind,
+};
+
+#[test]
+fn manager_errors_carry_codes_and_statuses() {
+    let (pool, _db) = pool("codes");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let mut user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    let err = user_manager
+        .login(&session_manager, &user, "wrong password")
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_credentials");
+    assert_eq!(err.status(), 401);
+
+    user.set_status(AccountStatus::Suspended);
+    user_manager.update_user(user.clone()).unwrap();
+    let err = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap_err();
+    assert_eq!(err.code(), "account_suspended");
+    assert_eq!(err.status(), 403);
+
+    let err = match user_manager.create_user::<(), ()>(
+        "bob".to_string(),
+        "short".to_string(),
+        Role::from_str("user"),
+        None,
+        None,
+    ) {
+        Err(err) => err,
+        Ok(_) => panic!("the password is too short"),
+    };
+    assert_eq!(err.code(), "password_policy");
+    assert_eq!(err.status(), 422);
+
+    assert_eq!(Error::new(ErrorKind::TokenExpired).code(), "token_expired");
+    assert_eq!(Error::new(ErrorKind::Banned).code(), "account_locked");
+}
+
+#[test]
+fn public_messages_leave_out_the_internal_detail() {
+    let err: Error = Veto::new("impossible travel").into();
+    assert_eq!(err.code(), "forbidden");
+    assert_eq!(err.public_message(), "Forbidden");
+    assert!(err.to_string().contains("impossible travel"));
+    assert_eq!(
+        err.source().unwrap().to_string(),
+        "Vetoed: impossible travel"
+    );
+
+    let res: AuthError = err.into();
+    assert_eq!(res.status, 403);
+    assert_eq!(res.code, "forbidden");
+    assert!(!res.message.contains("impossible travel"));
+    assert!(res.internal.contains("impossible travel"));
+
+    fn send_sync<T: Send + Sync + 'static>() {}
+    send_sync::<Error>();
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::pool;
+use sheesh::{
+    harness::{
+        DbHarness, DbHarnessSession, DbHarnessToken, DbHarnessUser, HarnessError, HarnessErrorKind,
+    },
+    id::DefaultIdGenerator,
+    session::SessionManagerConfig,
+    user::{Role, User, UserManager, UserManagerConfig},
+    Error, ErrorKind,
+};
+
+fn create_user<V: DbHarnessUser>(
+    user_manager: &UserManager<DefaultIdGenerator, V>,
+    username: &str,
+) -> Result<User<(), ()>, Error> {
+    user_manager.create_user(
+        username.to_string(),
+        "correct horse battery staple".to_string(),
+        Role::from_str("user"),
+        None,
+        None,
+    )
+}
+
+#[test]
+fn missing_rows_read_as_none() {
+    let (pool, _db) = pool("missing");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    assert!(harness.token.read_access_token(42).unwrap().is_none());
+    assert!(harness.token.read_refresh_token(42).unwrap().is_none());
+    assert!(harness.session.read(42).unwrap().is_none());
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    assert!(user_manager.get_user::<(), ()>(&42).unwrap().is_none());
+
+    // a session has to exist, asking for an unknown one is an error callers can recognize.
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    assert_eq!(
+        session_manager.get_session(42).unwrap_err().code(),
+        "not_found"
+    );
+    // an unknown token id is not authorized, not a database failure.
+    assert!(session_manager.authenticate("42.secret").is_err());
+}
+
+#[test]
+fn sqlite_errors_are_classified() {
+    fn send_sync<T: Send + Sync + 'static>() {}
+    send_sync::<HarnessError>();
+
+    let (pool, _db) = pool("classified");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+
+    let alice = create_user(&user_manager, "alice").unwrap();
+    match create_user(&user_manager, "alice") {
+        Err(err) => match err.kind {
+            ErrorKind::Harness(err) => assert_eq!(err.kind, HarnessErrorKind::Conflict),
+            kind => panic!("expected a harness error, got {:?}", kind),
+        },
+        Ok(_) => panic!("the username is taken"),
+    }
+
+    // a value no version of the library writes.
+    pool.get()
+        .unwrap()
+        .execute(
+            "UPDATE users SET status = 'unknown' WHERE id = ?",
+            [alice.id()],
+        )
+        .unwrap();
+    let err = match user_manager.get_user::<(), ()>(&alice.id()) {
+        Err(err) => err,
+        Ok(_) => panic!("the status can not be read"),
+    };
+    match &err.kind {
+        ErrorKind::Harness(err) => assert_eq!(err.kind, HarnessErrorKind::Corrupt),
+        kind => panic!("expected a harness error, got {:?}", kind),
+    }
+    // the unified error, the harness error and the conversion error underneath it.
+    let source = std::error::Error::source(&err).unwrap();
+    assert!(source.source().is_some());
+}
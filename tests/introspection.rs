@@ -0,0 +1,92 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use common::pool;
+use sheesh::{
+    auth_token::encode_token,
+    clock::MockClock,
+    harness::DbHarness,
+    introspection::{Introspection, TokenTypeHint},
+    session::SessionManagerConfig,
+};
+
+#[test]
+fn introspection_reports_only_verified_tokens_as_active() {
+    let (pool, _db) = pool("introspect");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let clock = MockClock::default();
+    let session_manager = SessionManagerConfig::default()
+        .with_clock(Arc::new(clock.clone()))
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let (session, refresh, access) = session_manager.new_session(7).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+    let refresh = encode_token(session.refresh_token().unwrap(), &refresh);
+
+    let introspection = session_manager.introspect(&access, None).unwrap();
+    assert!(introspection.active);
+    assert_eq!(introspection.sub.as_deref(), Some("7"));
+    assert_eq!(introspection.token_type, Some(TokenTypeHint::AccessToken));
+    assert_eq!(introspection.client_id, None);
+
+    // the hint only changes the search order.
+    let introspection = session_manager.introspect(&refresh, None).unwrap();
+    assert_eq!(introspection.token_type, Some(TokenTypeHint::RefreshToken));
+
+    let forged = encode_token(session.access_token().unwrap(), "not the secret");
+    for token in [forged.as_str(), "garbage"] {
+        assert_eq!(
+            session_manager
+                .introspect(token, Some(TokenTypeHint::AccessToken))
+                .unwrap(),
+            Introspection::inactive()
+        );
+    }
+
+    clock.advance(session_manager.access_ttl() + TimeDelta::minutes(1));
+    assert!(!session_manager.introspect(&access, None).unwrap().active);
+    assert!(session_manager.introspect(&refresh, None).unwrap().active);
+}
+
+#[test]
+fn revoking_a_refresh_token_revokes_its_access_token() {
+    let (pool, _db) = pool("revoke");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    // an access token alone can be revoked, the session refreshes into a new one.
+    let (session, refresh_secret, access) = session_manager.new_session(7).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+    session_manager.revoke(&access, None).unwrap();
+    assert!(session_manager.authenticate(&access).is_err());
+    let (refresh_secret, access) = session_manager
+        .create_new_refresh_token(session, 7, &refresh_secret)
+        .unwrap();
+
+    let session = session_manager.get_session(session.id()).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+    let refresh = encode_token(session.refresh_token().unwrap(), &refresh_secret);
+    session_manager.authenticate(&access).unwrap();
+
+    session_manager
+        .revoke(&refresh, Some(TokenTypeHint::RefreshToken))
+        .unwrap();
+    assert!(!session_manager.introspect(&refresh, None).unwrap().active);
+    assert!(session_manager.authenticate(&access).is_err());
+    assert!(!session_manager.introspect(&access, None).unwrap().active);
+    let session = session_manager.get_session(session.id()).unwrap();
+    assert!(session_manager
+        .create_new_refresh_token(session, 7, &refresh_secret)
+        .is_err());
+
+    // unknown and already revoked tokens are not an error.
+    session_manager.revoke(&refresh, None).unwrap();
+    session_manager.revoke("garbage", None).unwrap();
+}
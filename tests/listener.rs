@@ -0,0 +1,196 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::pool;
+use sheesh::{
+    harness::DbHarness,
+    listener::{AuthEventListener, Veto},
+    session::{Session, SessionManagerConfig},
+    user::{Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+// records every callback and refuses whatever the risk engine flagged.
+#[derive(Default)]
+struct RiskEngine {
+    calls: Mutex<Vec<String>>,
+    blocked_logins: Mutex<Vec<i64>>,
+    block_refresh: Mutex<bool>,
+    block_bans: Mutex<bool>,
+}
+
+impl RiskEngine {
+    fn calls(&self) -> Vec<String> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    fn call(&self, call: &str) {
+        self.calls.lock().unwrap().push(call.to_string());
+    }
+}
+
+impl AuthEventListener for RiskEngine {
+    fn on_user_created(&self, _user_id: i64) {
+        self.call("created");
+    }
+
+    fn before_login(&self, user_id: i64) -> Result<(), Veto> {
+        match self.blocked_logins.lock().unwrap().contains(&user_id) {
+            true => Err(Veto::new("impossible travel")),
+            false => Ok(()),
+        }
+    }
+
+    fn on_login(&self, _user_id: i64, _session: &Session) {
+        self.call("login");
+    }
+
+    fn on_login_failed(&self, _user_id: i64) {
+        self.call("login failed");
+    }
+
+    fn before_refresh(&self, _session: &Session) -> Result<(), Veto> {
+        match *self.block_refresh.lock().unwrap() {
+            true => Err(Veto::new("session flagged")),
+            false => Ok(()),
+        }
+    }
+
+    fn on_refresh(&self, _session: &Session) {
+        self.call("refresh");
+    }
+
+    fn on_session_revoked(&self, _session: &Session) {
+        self.call("revoked");
+    }
+
+    fn before_ban(&self, _user_id: i64) -> Result<(), Veto> {
+        match *self.block_bans.lock().unwrap() {
+            true => Err(Veto::new("protected account")),
+            false => Ok(()),
+        }
+    }
+
+    fn on_banned(&self, _user_id: i64) {
+        self.call("banned");
+    }
+}
+
+#[test]
+fn listeners_follow_the_session_lifecycle() {
+    let (pool, _db) = pool("lifecycle");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    let engine = Arc::new(RiskEngine::default());
+    let user_manager = UserManagerConfig::default()
+        .with_listener(engine.clone())
+        .init(harness.user)
+        .unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .with_listener(engine.clone())
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let mut user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    assert!(user_manager
+        .login(&session_manager, &user, "wrong")
+        .is_err());
+    let (session, refresh, _) = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap();
+    let (refresh, _) = session_manager
+        .create_new_refresh_token(session, user.id(), &refresh)
+        .unwrap();
+    assert_eq!(
+        engine.calls(),
+        ["created", "login failed", "login", "refresh"]
+    );
+
+    // a vetoed refresh leaves the current refresh token in place.
+    *engine.block_refresh.lock().unwrap() = true;
+    let session = session_manager.get_session(session.id()).unwrap();
+    let err = session_manager
+        .create_new_refresh_token(session, user.id(), &refresh)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::Vetoed(_)));
+    session_manager
+        .verify_session_token(session.refresh_token().unwrap(), user.id(), &refresh)
+        .unwrap();
+
+    session_manager.invalidate_session(session).unwrap();
+    user.ban();
+    user_manager.update_user(user.clone()).unwrap();
+    assert_eq!(engine.calls(), ["revoked", "banned"]);
+
+    // a banned user can not log back in, the ban is only revealed to someone who knows the password.
+    let err = user_manager
+        .login(&session_manager, &user, "wrong")
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::NotAuthorized));
+    let err = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::Banned));
+    assert_eq!(engine.calls(), ["login failed", "login failed"]);
+    assert!(session_manager
+        .get_user_sessions(user.id())
+        .unwrap()
+        .iter()
+        .all(|session| session.refresh_token().is_none()));
+}
+
+#[test]
+fn vetoed_actions_are_not_committed() {
+    let (pool, _db) = pool("veto");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    let engine = Arc::new(RiskEngine::default());
+    let user_manager = UserManagerConfig::default()
+        .with_listener(engine.clone())
+        .init(harness.user)
+        .unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let mut user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    engine.blocked_logins.lock().unwrap().push(user.id());
+    *engine.block_bans.lock().unwrap() = true;
+
+    let err = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap_err();
+    match err.kind {
+        ErrorKind::Vetoed(veto) => assert_eq!(veto.reason(), "impossible travel"),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(session_manager
+        .get_user_sessions(user.id())
+        .unwrap()
+        .is_empty());
+
+    user.ban();
+    assert!(user_manager.update_user(user.clone()).is_err());
+    let stored: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert!(!stored.is_banned());
+    assert_eq!(engine.calls(), ["created", "login failed"]);
+}
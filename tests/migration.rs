@@ -0,0 +1,372 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use common::pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use sheesh::{
+    auth_token::encode_token,
+    clock::{Clock, MockClock},
+    harness::{sqlite::MIGRATIONS, DbHarness},
+    session::SessionManagerConfig,
+    user::{AccountStatus, Role, User, UserManagerConfig},
+};
+
+fn indexes(pool: &r2d2::Pool<SqliteConnectionManager>, table: &str) -> Vec<String> {
+    let connection = pool.get().unwrap();
+    let mut stmt = connection
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ? AND sql IS NOT NULL ORDER BY name")
+        .unwrap();
+    stmt.query_map([table], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<String>>>()
+        .unwrap()
+}
+
+// name, type, not null and default of every column.
+fn columns(
+    pool: &r2d2::Pool<SqliteConnectionManager>,
+    table: &str,
+) -> Vec<(String, String, bool, Option<String>)> {
+    let connection = pool.get().unwrap();
+    let mut stmt = connection
+        .prepare(
+            "SELECT name, type, \"notnull\", dflt_value FROM pragma_table_info(?) ORDER BY cid",
+        )
+        .unwrap();
+    stmt.query_map([table], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+    })
+    .unwrap()
+    .collect::<rusqlite::Result<Vec<_>>>()
+    .unwrap()
+}
+
+fn versions(migrations: &[&sheesh::harness::migration::Migration]) -> Vec<i64> {
+    migrations
+        .iter()
+        .map(|migration| migration.version())
+        .collect()
+}
+
+#[test]
+fn upgrades_a_database_created_before_versioning() {
+    let (pool, _db) = pool("upgrade");
+    // every table as the last release before the versioned schema made it. index names are global, the sessions
+    // and access tokens indexes were never created.
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS refresh_tokens (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER NOT NULL,
+                    secret STRING NOT NULL,
+                    expires DATETIME NOT NULL,
+                    valid BOOL NOT NULL,
+                    scope STRING NOT NULL DEFAULT '',
+                    FOREIGN KEY(user_id) REFERENCES users(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_user_id ON refresh_tokens(user_id);
+            CREATE TABLE IF NOT EXISTS access_tokens (
+                    id INTEGER PRIMARY KEY NOT NULL,
+                    user_id INTEGER,
+                    token TEXT NOT NULL,
+                    expires DATETIME NOT NULL,
+                    valid BOOL NOT NULL,
+                    client_id INTEGER,
+                    scope STRING NOT NULL DEFAULT '',
+                    FOREIGN KEY(user_id) REFERENCES users(id),
+                    FOREIGN KEY(client_id) REFERENCES clients(id),
+                    CHECK ((user_id IS NULL) != (client_id IS NULL))
+            );
+            CREATE INDEX IF NOT EXISTS idx_user_id ON access_tokens(user_id);
+            CREATE TABLE IF NOT EXISTS sessions (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER NOT NULL,
+                    refresh_token INTEGER UNIQUE,
+                    access_token INTEGER UNIQUE,
+                    FOREIGN KEY(user_id) REFERENCES user(id),
+                    FOREIGN KEY(refresh_token) REFERENCES refresh_tokens(id),
+                    FOREIGN KEY(access_token) REFERENCES access_tokens(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_user_id ON sessions(user_id);
+            CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY,
+                session_id INTEGER,
+                username STRING NOT NULL UNIQUE,
+                secret STRING NOT NULL,
+                ban TINYINT NOT NULL,
+                groups STRING NOT NULL,
+                role STRING NOT NULL,
+                email STRING,
+                status STRING NOT NULL DEFAULT 'active',
+                password_changed_at DATETIME,
+                must_change_password TINYINT NOT NULL DEFAULT 0,
+                FOREIGN KEY(session_id) REFERENCES sessions(id)
+            );
+            CREATE TABLE IF NOT EXISTS password_history (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER NOT NULL,
+                    secret STRING NOT NULL,
+                    created DATETIME NOT NULL,
+                    FOREIGN KEY(user_id) REFERENCES users(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_password_history_user_id ON password_history(user_id);
+            CREATE TABLE IF NOT EXISTS clients (
+                    id INTEGER PRIMARY KEY,
+                    name STRING NOT NULL,
+                    secret STRING NOT NULL,
+                    scopes STRING NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS device_authorizations (
+                    id INTEGER PRIMARY KEY,
+                    device_code STRING NOT NULL UNIQUE,
+                    user_code STRING NOT NULL UNIQUE,
+                    expires DATETIME NOT NULL,
+                    interval INTEGER NOT NULL,
+                    last_polled DATETIME,
+                    status STRING NOT NULL,
+                    user_id INTEGER,
+                    FOREIGN KEY(user_id) REFERENCES users(id)
+            );
+            CREATE TABLE IF NOT EXISTS one_time_tokens (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER NOT NULL,
+                    purpose STRING NOT NULL,
+                    secret STRING NOT NULL,
+                    expires DATETIME NOT NULL,
+                    payload STRING,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    FOREIGN KEY(user_id) REFERENCES users(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_one_time_user_id ON one_time_tokens(user_id);
+            CREATE TABLE IF NOT EXISTS audit_log (
+                    seq INTEGER PRIMARY KEY,
+                    time DATETIME NOT NULL,
+                    kind STRING NOT NULL,
+                    user_id INTEGER,
+                    detail STRING,
+                    prev_hash STRING NOT NULL,
+                    hash STRING NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_audit_user_id ON audit_log(user_id, time);",
+        )
+        .unwrap();
+
+    let harness = DbHarness::new_sqlite(pool.clone());
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    let (session, _, _) = session_manager.new_session(user.id()).unwrap();
+    assert!(indexes(&pool, "sessions").is_empty());
+
+    let harness = DbHarness::new_sqlite(pool.clone());
+    assert_eq!(harness.schema_version().unwrap(), 0);
+    let all: Vec<i64> = MIGRATIONS
+        .iter()
+        .map(|migration| migration.version())
+        .collect();
+    assert_eq!(versions(&harness.pending_migrations().unwrap()), all);
+
+    assert_eq!(versions(&harness.migrate().unwrap()), all);
+    assert_eq!(harness.schema_version().unwrap(), *all.last().unwrap());
+    assert!(harness.migrate().unwrap().is_empty());
+    assert!(harness.pending_migrations().unwrap().is_empty());
+
+    assert_eq!(indexes(&pool, "sessions"), ["idx_sessions_user_id"]);
+    assert_eq!(
+        indexes(&pool, "access_tokens"),
+        ["idx_access_tokens_user_id"]
+    );
+    assert_eq!(
+        indexes(&pool, "refresh_tokens"),
+        ["idx_refresh_tokens_user_id"]
+    );
+
+    // the data survived the upgrade.
+    let stored: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert_eq!(stored.username(), "alice");
+    assert_eq!(
+        session_manager.get_user_sessions(user.id()).unwrap()[0].id(),
+        session.id()
+    );
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let (pool, _db) = pool("dry_run");
+    let clock = MockClock::new(Utc::now() - chrono::TimeDelta::days(3));
+    let harness = DbHarness::new_sqlite(pool.clone()).with_clock(Arc::new(clock.clone()));
+
+    let pending = harness.pending_migrations().unwrap();
+    assert_eq!(pending.len(), MIGRATIONS.len());
+    assert!(pending[0]
+        .to_string()
+        .starts_with("-- 1: initial schema\nCREATE TABLE"));
+    let tables: i64 = pool
+        .get()
+        .unwrap()
+        .query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(tables, 0);
+
+    // a new database goes through every migration and is usable right after.
+    harness.migrate().unwrap();
+    assert_eq!(
+        harness.schema_version().unwrap(),
+        MIGRATIONS.last().unwrap().version()
+    );
+    let applied: DateTime<Utc> = pool
+        .get()
+        .unwrap()
+        .query_row("SELECT MAX(applied) FROM schema_version", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(applied, clock.now());
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    user_manager
+        .create_user::<(), ()>(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+}
+
+#[test]
+fn upgrades_the_baseline_schema() {
+    let (pool, _db) = pool("baseline");
+    // the tables exactly as the first release created them.
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS refresh_tokens (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER NOT NULL,
+                    secret STRING NOT NULL,
+                    expires DATETIME NOT NULL,
+                    valid BOOL NOT NULL,
+                    FOREIGN KEY(user_id) REFERENCES users(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_user_id ON refresh_tokens(user_id);
+            CREATE TABLE IF NOT EXISTS access_tokens (
+                    id INTEGER PRIMARY KEY NOT NULL,
+                    user_id INTEGER NOT NULL,
+                    token STRING NOT NULL,
+                    expires DATETIME NOT NULL,
+                    valid BOOL NOT NULL,
+                    FOREIGN KEY(user_id) REFERENCES users(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_user_id ON access_tokens(user_id);
+            CREATE TABLE IF NOT EXISTS sessions (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER NOT NULL,
+                    refresh_token INTEGER NOT NULL UNIQUE,
+                    access_token INTEGER NOT NULL UNIQUE,
+                    FOREIGN KEY(user_id) REFERENCES user(id),
+                    FOREIGN KEY(refresh_token) REFERENCES refresh_tokens(id),
+                    FOREIGN KEY(access_token) REFERENCES access_tokens(id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_user_id ON sessions(user_id);
+            CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY,
+                session_id INTEGER,
+                username STRING NOT NULL UNIQUE,
+                secret STRING NOT NULL,
+                ban TINYINT NOT NULL,
+                groups STRING NOT NULL,
+                role STRING NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id)
+            );
+            INSERT INTO users (id, username, secret, ban, groups, role)
+                VALUES (1, 'alice', 'not a hash', 0, '', 'user');
+            INSERT INTO refresh_tokens (id, user_id, secret, expires, valid)
+                VALUES (2, 1, 'not a hash', '2000-01-01T00:00:00Z', 1);
+            INSERT INTO access_tokens (id, user_id, token, expires, valid)
+                VALUES (3, 1, 'not a hash', '2000-01-01T00:00:00Z', 1);
+            INSERT INTO sessions (id, user_id, refresh_token, access_token) VALUES (4, 1, 2, 3);",
+        )
+        .unwrap();
+
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+
+    let users = columns(&pool, "users");
+    for column in [
+        "email",
+        "status",
+        "password_changed_at",
+        "must_change_password",
+    ] {
+        assert!(users.iter().any(|(name, ..)| name == column), "{column}");
+    }
+    let access = columns(&pool, "access_tokens");
+    assert!(access
+        .iter()
+        .any(|(name, _, not_null, _)| name == "user_id" && !not_null));
+    assert!(access.iter().any(|(name, ..)| name == "client_id"));
+    assert!(access.iter().any(|(name, ..)| name == "scope"));
+    assert!(columns(&pool, "sessions")
+        .iter()
+        .all(|(name, _, not_null, _)| name == "id" || name == "user_id" || !not_null));
+
+    // the upgraded tables look like the ones a fresh database gets.
+    let (fresh, _fresh_db) = common::pool("fresh");
+    DbHarness::new_sqlite(fresh.clone()).migrate().unwrap();
+    for table in [
+        "users",
+        "password_history",
+        "refresh_tokens",
+        "access_tokens",
+        "sessions",
+        "clients",
+        "device_authorizations",
+        "one_time_tokens",
+        "audit_log",
+    ] {
+        assert_eq!(columns(&pool, table), columns(&fresh, table), "{table}");
+    }
+
+    // the old rows survived and the managers work on the upgraded tables.
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let alice: User<(), ()> = user_manager.get_user(&1).unwrap().unwrap();
+    assert_eq!(alice.status(), AccountStatus::Active);
+    assert_eq!(
+        session_manager.get_session(4).unwrap().refresh_token(),
+        Some(2)
+    );
+
+    let bob: User<(), ()> = user_manager
+        .create_user(
+            "bob".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    let (session, _, access) = session_manager.new_session(bob.id()).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+    assert_eq!(
+        session_manager.authenticate(&access).unwrap().user_id(),
+        bob.id()
+    );
+    session_manager.invalidate_session(session).unwrap();
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use chrono::{TimeDelta, Utc};
+use openidconnect::{
+    core::{
+        CoreIdToken, CoreIdTokenVerifier, CoreJsonWebKeySet, CoreProviderMetadata,
+        CoreUserInfoClaims,
+    },
+    AuthenticationMethodReference, ClientId, IssuerUrl, Nonce, SubjectIdentifier,
+};
+use serde_json::{Map, Value};
+use sheesh::{
+    clock::MockClock,
+    oidc::{OidcClaims, OidcProvider, OidcProviderConfig, SigningKey},
+    user::{PrivateUserMeta, PublicUserMeta, Role, User},
+    ErrorKind,
+};
+
+const ISSUER: &str = "https://auth.example.com";
+const CLIENT_ID: &str = "my-client";
+const SIGNING_KEY_PEM: &str = include_str!("fixtures/oidc_signing_key.pem");
+
+#[derive(Clone)]
+struct Profile {
+    email: String,
+}
+
+impl PublicUserMeta for Profile {}
+
+impl OidcClaims for Profile {
+    fn oidc_claims(&self) -> Map<String, Value> {
+        let mut claims = Map::new();
+        claims.insert("email".into(), Value::String(self.email.clone()));
+        claims.insert("email_verified".into(), Value::Bool(true));
+        // claims the provider sets itself, never passed on.
+        claims.insert("sub".into(), Value::String("1".into()));
+        claims.insert("preferred_username".into(), Value::String("mallory".into()));
+        claims
+    }
+}
+
+#[derive(Clone)]
+struct Secrets;
+
+impl PrivateUserMeta for Secrets {}
+
+fn provider() -> OidcProvider {
+    let key = SigningKey::from_pem("key-1", SIGNING_KEY_PEM).unwrap();
+    OidcProviderConfig::new(ISSUER)
+        .with_signing_key(key)
+        .init()
+        .unwrap()
+}
+
+fn user() -> User<Profile, Secrets> {
+    User::new(
+        42,
+        "alice".to_string(),
+        String::new(),
+        Role::from_str("user"),
+        Some(Profile {
+            email: "alice@example.com".to_string(),
+        }),
+        None,
+        Utc::now(),
+    )
+    .unwrap()
+}
+
+fn verifier(provider: &OidcProvider) -> CoreIdTokenVerifier<'static> {
+    let jwks: CoreJsonWebKeySet =
+        serde_json::from_str(&provider.jwks().to_json().unwrap()).unwrap();
+    CoreIdTokenVerifier::new_public_client(
+        ClientId::new(CLIENT_ID.to_string()),
+        IssuerUrl::new(ISSUER.to_string()).unwrap(),
+        jwks,
+    )
+}
+
+#[test]
+fn discovery_document_parses_as_provider_metadata() {
+    let provider = provider();
+    let document = provider
+        .discovery()
+        .claims_supported(&["email", "email_verified"])
+        .build();
+
+    let metadata: CoreProviderMetadata =
+        serde_json::from_str(&document.to_json().unwrap()).unwrap();
+
+    assert_eq!(metadata.issuer().as_str(), ISSUER);
+    assert_eq!(
+        metadata.jwks_uri().as_str(),
+        "https://auth.example.com/.well-known/jwks.json"
+    );
+    assert_eq!(
+        metadata.userinfo_endpoint().unwrap().as_str(),
+        "https://auth.example.com/userinfo"
+    );
+}
+
+#[test]
+fn id_token_verifies_against_published_jwks() {
+    let provider = provider();
+    let auth_time = Utc::now() - TimeDelta::minutes(1);
+
+    let token = provider
+        .id_token(
+            &user(),
+            CLIENT_ID,
+            Some("n-0S6_WzA2Mj"),
+            auth_time,
+            &["pwd"],
+        )
+        .unwrap();
+
+    let id_token: CoreIdToken = token.parse().unwrap();
+    let claims = id_token
+        .claims(
+            &verifier(&provider),
+            &Nonce::new("n-0S6_WzA2Mj".to_string()),
+        )
+        .unwrap();
+
+    assert_eq!(claims.subject().as_str(), "42");
+    assert_eq!(
+        claims.auth_time().unwrap().timestamp(),
+        auth_time.timestamp()
+    );
+    assert_eq!(
+        claims.auth_method_refs().unwrap(),
+        &vec![AuthenticationMethodReference::new("pwd".to_string())]
+    );
+}
+
+#[test]
+fn id_token_with_wrong_nonce_is_rejected() {
+    let provider = provider();
+    let token = provider
+        .id_token(&user(), CLIENT_ID, Some("expected"), Utc::now(), &["pwd"])
+        .unwrap();
+
+    let id_token: CoreIdToken = token.parse().unwrap();
+    let claims = id_token.claims(&verifier(&provider), &Nonce::new("other".to_string()));
+
+    assert!(claims.is_err());
+}
+
+#[test]
+fn id_token_is_dated_by_the_configured_clock() {
+    let provider = provider();
+    let issued = Utc::now() - TimeDelta::hours(1);
+    let stale = OidcProviderConfig::new(ISSUER)
+        .with_signing_key(SigningKey::from_pem("key-1", SIGNING_KEY_PEM).unwrap())
+        .with_clock(Arc::new(MockClock::new(issued)))
+        .init()
+        .unwrap();
+
+    let token = stale
+        .id_token(&user(), CLIENT_ID, None, issued, &["pwd"])
+        .unwrap();
+
+    // issued an hour ago with a ten minute lifetime.
+    let id_token: CoreIdToken = token.parse().unwrap();
+    let claims = id_token.claims(&verifier(&provider), |_: Option<&Nonce>| Ok(()));
+
+    assert!(claims.is_err());
+}
+
+#[test]
+fn init_rejects_an_id_token_ttl_that_is_not_positive() {
+    let key = SigningKey::from_pem("key-1", SIGNING_KEY_PEM).unwrap();
+    let err = OidcProviderConfig::new(ISSUER)
+        .with_signing_key(key)
+        .with_id_token_ttl(TimeDelta::zero())
+        .init()
+        .err()
+        .unwrap();
+
+    assert!(matches!(err.kind, ErrorKind::InvalidConfig("id token ttl")));
+}
+
+#[test]
+fn id_token_signed_by_unpublished_key_is_rejected() {
+    let provider = provider();
+    let rogue = OidcProviderConfig::new(ISSUER)
+        .with_signing_key(SigningKey::generate("key-1").unwrap())
+        .init()
+        .unwrap();
+
+    let token = rogue
+        .id_token(&user(), CLIENT_ID, None, Utc::now(), &["pwd"])
+        .unwrap();
+
+    let id_token: CoreIdToken = token.parse().unwrap();
+    let claims = id_token.claims(&verifier(&provider), |_: Option<&Nonce>| Ok(()));
+
+    assert!(claims.is_err());
+}
+
+#[test]
+fn userinfo_includes_public_meta_claims() {
+    let provider = provider();
+    let json = provider.userinfo(&user()).to_json().unwrap();
+    assert_eq!(json.matches("\"sub\"").count(), 1);
+    assert_eq!(json.matches("\"preferred_username\"").count(), 1);
+
+    let claims = CoreUserInfoClaims::from_json::<std::io::Error>(
+        json.as_bytes(),
+        Some(&SubjectIdentifier::new("42".to_string())),
+    )
+    .unwrap();
+
+    assert_eq!(claims.preferred_username().unwrap().as_str(), "alice");
+    assert_eq!(claims.email().unwrap().as_str(), "alice@example.com");
+    assert_eq!(claims.email_verified(), Some(true));
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use chrono::{TimeDelta, Utc};
+use common::pool;
+use sheesh::{
+    harness::{
+        sqlite::{SqliteHarnessOneTimeToken, SqliteHarnessUser},
+        DbHarness, DbHarnessUser,
+    },
+    one_time::{InMemoryDelivery, OneTimePurpose},
+    passwordless::PasswordlessConfig,
+    session::SessionManagerConfig,
+    user::{AccountStatus, Groups, PasswordChangeReason, Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+fn change_reason(kind: &ErrorKind) -> Option<PasswordChangeReason> {
+    match kind {
+        ErrorKind::PasswordChangeRequired(reason) => Some(*reason),
+        _ => None,
+    }
+}
+
+#[test]
+fn flagged_users_have_to_change_their_password() {
+    let (pool, _db) = pool("flag");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let mut user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    user.set_must_change_password(true);
+    user_manager.update_user(user.clone()).unwrap();
+
+    let err = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap_err();
+    assert_eq!(
+        change_reason(&err.kind),
+        Some(PasswordChangeReason::Required)
+    );
+
+    // a magic link does not get around the change either.
+    let delivery = InMemoryDelivery::new();
+    let passwordless = PasswordlessConfig::default()
+        .init(one_time, &delivery)
+        .unwrap();
+    passwordless.request_magic_link(&user).unwrap();
+    let link = delivery.last(user.id(), OneTimePurpose::MagicLink).unwrap();
+    let err = passwordless
+        .redeem_magic_link(&user_manager, &session_manager, &link.token)
+        .unwrap_err();
+    assert_eq!(
+        change_reason(&err.kind),
+        Some(PasswordChangeReason::Required)
+    );
+    assert!(session_manager
+        .get_user_sessions(user.id())
+        .unwrap()
+        .is_empty());
+
+    // a wrong password gets no further than it would at a normal login.
+    let err = user_manager
+        .login_with_password_change(
+            &session_manager,
+            &user,
+            "wrong",
+            "a brand new passphrase".to_string(),
+        )
+        .unwrap_err();
+    assert_eq!(change_reason(&err.kind), None);
+
+    user_manager
+        .login_with_password_change(
+            &session_manager,
+            &user,
+            "correct horse battery staple",
+            "a brand new passphrase".to_string(),
+        )
+        .unwrap();
+
+    let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert!(!user.must_change_password());
+    user_manager
+        .login(&session_manager, &user, "a brand new passphrase")
+        .unwrap();
+}
+
+#[test]
+fn expired_passwords_have_to_be_changed() {
+    let (pool, _db) = pool("expiry");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+
+    let user_manager = UserManagerConfig::default()
+        .with_password_max_age(TimeDelta::days(90))
+        .init(harness.user)
+        .unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let fresh: User<(), ()> = user_manager
+        .create_user(
+            "bob".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    user_manager
+        .login(&session_manager, &fresh, "correct horse battery staple")
+        .unwrap();
+
+    // the same password hash, last changed 100 days ago.
+    let stale: User<(), ()> = User::from_values(
+        fresh.id() + 1,
+        None,
+        "carol".to_string(),
+        fresh.secret().to_string(),
+        false,
+        Groups::new(),
+        Role::from_str("user"),
+        None,
+        AccountStatus::Active,
+        Some(Utc::now() - TimeDelta::days(100)),
+        false,
+        None,
+        None,
+    );
+    SqliteHarnessUser::new(pool).insert(&stale).unwrap();
+
+    let err = user_manager
+        .login(&session_manager, &stale, "correct horse battery staple")
+        .unwrap_err();
+    assert_eq!(
+        change_reason(&err.kind),
+        Some(PasswordChangeReason::Expired)
+    );
+
+    user_manager
+        .login_with_password_change(
+            &session_manager,
+            &stale,
+            "correct horse battery staple",
+            "a brand new passphrase".to_string(),
+        )
+        .unwrap();
+    let stale: User<(), ()> = user_manager.get_user(&stale.id()).unwrap().unwrap();
+    user_manager
+        .login(&session_manager, &stale, "a brand new passphrase")
+        .unwrap();
+}
@@ -0,0 +1,274 @@
+use r2d2_sqlite::SqliteConnectionManager;
+use sha1::{Digest, Sha1};
+use sheesh::{
+    breached::BreachedPasswords,
+    harness::DbHarness,
+    password_policy::{
+        strength_score, CharacterClass, PasswordPolicy, PasswordPolicyErrorKind, PasswordViolation,
+    },
+    user::{Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+struct TempFiles {
+    paths: Vec<std::path::PathBuf>,
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("sheesh_policy_{}_{}", name, std::process::id()))
+}
+
+fn sha1_hex(pwd: &str) -> String {
+    Sha1::digest(pwd.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
+
+// a corpus in the Pwned Passwords format, sorted by digest.
+fn corpus(name: &str, passwords: &[&str]) -> (std::path::PathBuf, TempFiles) {
+    let path = temp_path(name);
+    let mut lines: Vec<String> = passwords
+        .iter()
+        .enumerate()
+        .map(|(i, pwd)| format!("{}:{}", sha1_hex(pwd), i + 1))
+        .collect();
+    lines.sort();
+    std::fs::write(&path, lines.join("\r\n")).unwrap();
+
+    let mut index = path.clone().into_os_string();
+    index.push(".idx");
+    let files = TempFiles {
+        paths: vec![path.clone(), index.into()],
+    };
+    (path, files)
+}
+
+fn violations(policy: &PasswordPolicy, username: &str, pwd: &str) -> Vec<PasswordViolation> {
+    match policy.check(username, pwd) {
+        Ok(()) => Vec::new(),
+        Err(err) => err.violations().to_vec(),
+    }
+}
+
+#[test]
+fn default_policy_bounds_length_and_rejects_the_username() {
+    let policy = PasswordPolicy::default();
+
+    assert_eq!(
+        violations(&policy, "alice", ""),
+        vec![PasswordViolation::TooShort { min: 8 }]
+    );
+    assert_eq!(
+        violations(&policy, "alice", &"x".repeat(129)),
+        vec![PasswordViolation::TooLong { max: 128 }]
+    );
+    assert_eq!(
+        violations(&policy, "alice", "my name is ALICE"),
+        vec![PasswordViolation::ContainsUsername]
+    );
+    assert!(policy
+        .check("alice", "correct horse battery staple")
+        .is_ok());
+    assert!(PasswordPolicy::none().check("alice", "").is_ok());
+}
+
+#[test]
+fn every_violation_is_reported() {
+    let policy = PasswordPolicy::default()
+        .with_min_length(12)
+        .with_required_class(CharacterClass::Uppercase)
+        .with_required_class(CharacterClass::Digit)
+        .with_min_score(3);
+
+    assert_eq!(
+        violations(&policy, "alice", "password"),
+        vec![
+            PasswordViolation::TooShort { min: 12 },
+            PasswordViolation::MissingClass(CharacterClass::Uppercase),
+            PasswordViolation::MissingClass(CharacterClass::Digit),
+            PasswordViolation::TooWeak { score: 0, min: 3 },
+        ]
+    );
+    assert!(policy.check("alice", "Correct horse battery 9").is_ok());
+}
+
+#[test]
+fn strength_score_sees_through_common_patterns() {
+    assert_eq!(strength_score("password", &[]), 0);
+    assert_eq!(strength_score("P@ssw0rd", &[]), 0);
+    assert_eq!(strength_score("qwertyuiop", &[]), 0);
+    assert_eq!(strength_score("aaaaaaaaaaaa", &[]), 0);
+    assert_eq!(strength_score("abcdefgh1990", &[]), 1);
+    assert_eq!(strength_score("alice1990", &["alice"]), 0);
+    assert_eq!(strength_score("correct horse battery staple", &[]), 4);
+}
+
+#[test]
+fn breached_passwords_are_rejected() {
+    let (path, _files) = corpus(
+        "breached",
+        &["password", "123456", "letmein", "Summer2024!"],
+    );
+
+    let breached = BreachedPasswords::open(&path).unwrap();
+    assert!(breached.contains("Summer2024!").unwrap());
+    assert!(breached.contains("letmein").unwrap());
+    assert!(!breached.contains("correct horse battery staple").unwrap());
+    assert!(breached
+        .contains_digest(&sha1_hex("123456").to_lowercase())
+        .unwrap());
+
+    // the second open reads the saved index.
+    let policy =
+        PasswordPolicy::default().with_breached_passwords(BreachedPasswords::open(&path).unwrap());
+    assert_eq!(
+        violations(&policy, "alice", "Summer2024!"),
+        vec![PasswordViolation::Breached]
+    );
+    assert!(policy
+        .check("alice", "correct horse battery staple")
+        .is_ok());
+}
+
+#[test]
+fn unsorted_corpus_is_refused() {
+    let path = temp_path("unsorted");
+    let _files = TempFiles {
+        paths: vec![path.clone()],
+    };
+    std::fs::write(
+        &path,
+        "FFFFF00000000000000000000000000000000000:1\n0000000000000000000000000000000000000000:1\n",
+    )
+    .unwrap();
+
+    assert!(BreachedPasswords::open(&path).is_err());
+}
+
+#[test]
+fn user_manager_enforces_the_policy() {
+    let path = temp_path("users.db");
+    let _files = TempFiles {
+        paths: vec![path.clone()],
+    };
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file(&path)).unwrap();
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    let user_manager = UserManagerConfig::default()
+        .with_password_policy(PasswordPolicy::default().with_min_length(10))
+        .init(harness.user)
+        .unwrap();
+
+    let err = user_manager
+        .create_user::<(), ()>(
+            "alice".to_string(),
+            "short".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .err()
+        .unwrap();
+    match err.kind {
+        ErrorKind::PasswordPolicy(err) => match err.kind {
+            PasswordPolicyErrorKind::Violations(violations) => {
+                assert_eq!(violations, vec![PasswordViolation::TooShort { min: 10 }])
+            }
+            other => panic!("unexpected {:?}", other),
+        },
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    let err = user_manager
+        .update_password(user.clone(), "alice12345".to_string())
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::PasswordPolicy(_)));
+
+    user_manager
+        .update_password(user, "another long passphrase".to_string())
+        .unwrap();
+}
+
+#[test]
+fn recent_passwords_can_not_be_reused() {
+    let path = temp_path("history.db");
+    let _files = TempFiles {
+        paths: vec![path.clone()],
+    };
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file(&path)).unwrap();
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+
+    let user_manager = UserManagerConfig::default()
+        .with_password_history(3)
+        .init(harness.user)
+        .unwrap();
+
+    let passwords = [
+        "first long passphrase",
+        "second long passphrase",
+        "third long passphrase",
+        "fourth long passphrase",
+    ];
+    let mut user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            passwords[0].to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    let reused =
+        |user: &User<(), ()>, pwd: &str| match user_manager.check_password_change(user, pwd) {
+            Ok(()) => false,
+            Err(err) => match err.kind {
+                ErrorKind::PasswordPolicy(err) => err.violations() == [PasswordViolation::Reused],
+                other => panic!("unexpected {:?}", other),
+            },
+        };
+
+    // the current password counts as one of the three.
+    assert!(reused(&user, passwords[0]));
+
+    for pwd in &passwords[1..3] {
+        user_manager.update_password(user, pwd.to_string()).unwrap();
+        user = user_manager.get_user_by_username("alice").unwrap().unwrap();
+    }
+    assert!(reused(&user, passwords[0]));
+    assert!(reused(&user, passwords[1]));
+
+    user_manager
+        .update_password(user, passwords[3].to_string())
+        .unwrap();
+    let user: User<(), ()> = user_manager.get_user_by_username("alice").unwrap().unwrap();
+
+    // the oldest password fell out of the history and was pruned.
+    assert!(!reused(&user, passwords[0]));
+    assert!(reused(&user, passwords[1]));
+    let err = user_manager
+        .update_password(user, passwords[2].to_string())
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::PasswordPolicy(_)));
+}
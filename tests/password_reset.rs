@@ -0,0 +1,162 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use common::pool;
+use sheesh::{
+    auth_token::encode_token,
+    clock::MockClock,
+    harness::{sqlite::SqliteHarnessOneTimeToken, DbHarness},
+    one_time::{InMemoryDelivery, OneTimePurpose},
+    password_reset::PasswordResetConfig,
+    session::SessionManagerConfig,
+    user::{Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+#[test]
+fn reset_replaces_the_password_and_logs_out_once() {
+    let (pool, _db) = pool("reset");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let delivery = InMemoryDelivery::new();
+    let password_reset = PasswordResetConfig::default()
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    let (session, _, access) = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+
+    password_reset.request_reset(&user).unwrap();
+    let reset = delivery
+        .last(user.id(), OneTimePurpose::PasswordReset)
+        .unwrap();
+
+    // a password the policy rejects leaves the token and the sessions alone.
+    let err = password_reset
+        .reset_password(&user_manager, &session_manager, &reset.token, "short")
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::PasswordPolicy(_)));
+    assert!(session_manager.authenticate(&access).is_ok());
+
+    password_reset
+        .reset_password(
+            &user_manager,
+            &session_manager,
+            &reset.token,
+            "a brand new passphrase",
+        )
+        .unwrap();
+    assert!(session_manager.authenticate(&access).is_err());
+    let sessions = session_manager.get_user_sessions(user.id()).unwrap();
+    assert!(sessions
+        .iter()
+        .all(|session| session.access_token().is_none() && session.refresh_token().is_none()));
+
+    let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert!(user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .is_err());
+    user_manager
+        .login(&session_manager, &user, "a brand new passphrase")
+        .unwrap();
+
+    let err = password_reset
+        .reset_password(
+            &user_manager,
+            &session_manager,
+            &reset.token,
+            "yet another passphrase",
+        )
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+}
+
+#[test]
+fn expired_and_raced_reset_tokens_are_rejected() {
+    let (pool, _db) = pool("race");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let delivery = InMemoryDelivery::new();
+    let clock = MockClock::default();
+    let password_reset = PasswordResetConfig::default()
+        .with_clock(Arc::new(clock.clone()))
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "bob".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    password_reset.request_reset(&user).unwrap();
+    let expired = delivery
+        .last(user.id(), OneTimePurpose::PasswordReset)
+        .unwrap();
+    clock.advance(TimeDelta::minutes(16));
+    let err = password_reset
+        .reset_password(
+            &user_manager,
+            &session_manager,
+            &expired.token,
+            "a brand new passphrase",
+        )
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenExpired));
+
+    // of two resets with the same token only one gets through, the other changes nothing.
+    password_reset.request_reset(&user).unwrap();
+    let reset = delivery
+        .last(user.id(), OneTimePurpose::PasswordReset)
+        .unwrap();
+    let passwords = ["a brand new passphrase", "yet another passphrase"];
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = passwords
+            .iter()
+            .map(|pwd| {
+                let (user_manager, session_manager) = (&user_manager, &session_manager);
+                let (password_reset, reset) = (&password_reset, &reset);
+                scope.spawn(move || {
+                    password_reset.reset_password(user_manager, session_manager, &reset.token, pwd)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+
+    let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    let winner = passwords[results.iter().position(|result| result.is_ok()).unwrap()];
+    let loser = passwords[results.iter().position(|result| result.is_err()).unwrap()];
+    user_manager.login(&session_manager, &user, winner).unwrap();
+    assert!(user_manager.login(&session_manager, &user, loser).is_err());
+}
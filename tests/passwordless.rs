@@ -0,0 +1,195 @@
+mod common;
+
+use common::pool;
+use sheesh::{
+    auth_token::encode_token,
+    harness::{sqlite::SqliteHarnessOneTimeToken, DbHarness},
+    one_time::{InMemoryDelivery, OneTimePurpose},
+    passwordless::PasswordlessConfig,
+    session::SessionManagerConfig,
+    user::{Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+#[test]
+fn magic_link_logs_in_once() {
+    let (pool, _db) = pool("link");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let delivery = InMemoryDelivery::new();
+    let passwordless = PasswordlessConfig::default()
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    passwordless
+        .request_magic_link_by_username(&user_manager, "mallory")
+        .unwrap();
+    assert!(delivery.delivered().is_empty());
+
+    passwordless.request_magic_link(&user).unwrap();
+    let link = delivery.last(user.id(), OneTimePurpose::MagicLink).unwrap();
+
+    let (session, _, access) = passwordless
+        .redeem_magic_link(&user_manager, &session_manager, &link.token)
+        .unwrap();
+    assert_eq!(session.user_id(), user.id());
+    let access = encode_token(session.access_token().unwrap(), &access);
+    assert!(session_manager.authenticate(&access).is_ok());
+
+    let err = passwordless
+        .redeem_magic_link(&user_manager, &session_manager, &link.token)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+}
+
+#[test]
+fn login_code_is_dropped_after_too_many_attempts() {
+    let (pool, _db) = pool("code");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let delivery = InMemoryDelivery::new();
+    let passwordless = PasswordlessConfig::default()
+        .with_max_attempts(2)
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "bob".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    passwordless.request_code(&user).unwrap();
+    let code = delivery.last(user.id(), OneTimePurpose::LoginCode).unwrap();
+    assert_eq!(code.token.len(), 6);
+    assert!(code.token.chars().all(|c| c.is_ascii_digit()));
+
+    passwordless
+        .redeem_code(&user_manager, &session_manager, "bob", &code.token)
+        .unwrap();
+
+    passwordless.request_code(&user).unwrap();
+    let code = delivery.last(user.id(), OneTimePurpose::LoginCode).unwrap();
+    let wrong = if code.token == "000000" {
+        "111111"
+    } else {
+        "000000"
+    };
+
+    let err = passwordless
+        .redeem_code(&user_manager, &session_manager, "bob", wrong)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+    let err = passwordless
+        .redeem_code(&user_manager, &session_manager, "bob", wrong)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TooManyAttempts));
+
+    // the right code is of no use anymore once the attempts are used up.
+    let err = passwordless
+        .redeem_code(&user_manager, &session_manager, "bob", &code.token)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+
+    // guesses sent at the same time each take an attempt before they are checked.
+    passwordless.request_code(&user).unwrap();
+    let code = delivery.last(user.id(), OneTimePurpose::LoginCode).unwrap();
+    let wrong = if code.token == "000000" {
+        "111111"
+    } else {
+        "000000"
+    };
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (passwordless, user_manager, session_manager) =
+                    (&passwordless, &user_manager, &session_manager);
+                scope.spawn(move || {
+                    passwordless.redeem_code(user_manager, session_manager, "bob", wrong)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(results.iter().any(
+        |result| matches!(result, Err(err) if matches!(err.kind, ErrorKind::TooManyAttempts))
+    ));
+    let err = passwordless
+        .redeem_code(&user_manager, &session_manager, "bob", &code.token)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+}
+
+#[test]
+fn concurrent_redeems_of_one_link_log_in_once() {
+    let (pool, _db) = pool("race");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let delivery = InMemoryDelivery::new();
+    let passwordless = PasswordlessConfig::default()
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "carol".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+
+    passwordless.request_magic_link(&user).unwrap();
+    let link = delivery.last(user.id(), OneTimePurpose::MagicLink).unwrap();
+
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    passwordless.redeem_magic_link(&user_manager, &session_manager, &link.token)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    for result in results {
+        if let Err(err) = result {
+            assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::pool;
+use sheesh::{
+    auth_token::{encode_token, TokenType},
+    harness::{sqlite::SqliteHarnessToken, DbHarness, DbHarnessToken},
+    pepper::{self, Pepper},
+    session::SessionManagerConfig,
+    user::{Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+const KEY_1: &[u8] = b"an example key, use 32 random bytes";
+const KEY_2: &[u8] = b"the key that replaced the first one";
+const PWD: &str = "correct horse battery staple";
+
+#[test]
+fn passwords_move_to_the_current_pepper_key() {
+    let (pool, _db) = pool("rotation");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    // created before there was a pepper.
+    let user: User<(), ()> = UserManagerConfig::default()
+        .init(harness.user)
+        .unwrap()
+        .create_user(
+            "alice".to_string(),
+            PWD.to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(pepper::decode(user.secret()), None);
+
+    let key_ids = [
+        (Pepper::new(1, KEY_1), 1),
+        (Pepper::new(2, KEY_2).with_retired_key(1, KEY_1), 2),
+        (Pepper::new(2, KEY_2), 2),
+    ];
+    for (pepper, current) in key_ids {
+        let user_manager = UserManagerConfig::default()
+            .with_pepper(pepper)
+            .init(DbHarness::new_sqlite(pool.clone()).user)
+            .unwrap();
+        user_manager.login(&session_manager, &user, PWD).unwrap();
+
+        let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+        assert_eq!(pepper::decode(user.secret()).unwrap().0, current);
+    }
+
+    // without the key a peppered password can not be verified at all.
+    let err = UserManagerConfig::default()
+        .with_pepper(Pepper::new(3, "another key"))
+        .init(DbHarness::new_sqlite(pool).user)
+        .unwrap()
+        .login(&session_manager, &user, PWD)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::UnknownPepper));
+}
+
+#[test]
+fn access_tokens_are_stored_as_keyed_digests() {
+    let (pool, _db) = pool("access");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+
+    let user_manager = UserManagerConfig::default()
+        .with_pepper(Pepper::new(1, KEY_1))
+        .init(harness.user)
+        .unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .with_pepper(Pepper::new(1, KEY_1))
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user(
+            "alice".to_string(),
+            PWD.to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    let (session, refresh, access) = user_manager.login(&session_manager, &user, PWD).unwrap();
+
+    let stored = SqliteHarnessToken::new(pool.clone())
+        .read_access_token(session.access_token().unwrap())
+        .unwrap()
+        .unwrap();
+    match stored.token_type() {
+        TokenType::Access { token } => {
+            assert_eq!(pepper::decode(&token).unwrap().0, 1);
+            assert!(!token.contains(&access));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let encoded = encode_token(session.access_token().unwrap(), &access);
+    session_manager.authenticate(&encoded).unwrap();
+
+    // the same database without the pepper rejects the token.
+    let harness = DbHarness::new_sqlite(pool);
+    let err = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap()
+        .authenticate(&encoded)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::UnknownPepper));
+
+    session_manager
+        .create_new_refresh_token(session, user.id(), &refresh)
+        .unwrap();
+}
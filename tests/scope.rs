@@ -0,0 +1,95 @@
+mod common;
+
+use common::pool;
+use sheesh::{harness::DbHarness, scope::Scopes, session::SessionManagerConfig};
+
+#[test]
+fn access_tokens_need_every_required_scope() {
+    let (pool, _db) = pool("required");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let (session, _, access) = session_manager
+        .new_scoped_session(7, Scopes::from_slice(&["profile", "orders:read"]))
+        .unwrap();
+    let token_id = session.access_token().unwrap();
+
+    session_manager
+        .verify_access_token_with_scope(token_id, 7, &access, &["orders:read"])
+        .unwrap();
+    session_manager
+        .verify_access_token_with_scope(token_id, 7, &access, &["profile", "orders:read"])
+        .unwrap();
+
+    let err = session_manager
+        .verify_access_token_with_scope(token_id, 7, &access, &["orders:read", "orders:write"])
+        .unwrap_err();
+    assert_eq!(err.code(), "insufficient_scope");
+    assert_eq!(err.status(), 403);
+}
+
+#[test]
+fn refresh_narrows_scopes_but_never_widens_them() {
+    let (pool, _db) = pool("refresh");
+    let harness = DbHarness::new_sqlite(pool);
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let (session, refresh, _) = session_manager
+        .new_scoped_session(7, Scopes::from_slice(&["profile", "orders:read"]))
+        .unwrap();
+
+    let err = session_manager
+        .create_new_refresh_token_with_scopes(
+            session,
+            7,
+            &refresh,
+            Some(&Scopes::from_slice(&["profile", "orders:write"])),
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_scope");
+
+    // the rejected request left the refresh token usable.
+    let session = session_manager.get_session(session.id()).unwrap();
+    let (refresh, access) = session_manager
+        .create_new_refresh_token_with_scopes(
+            session,
+            7,
+            &refresh,
+            Some(&Scopes::from_slice(&["profile"])),
+        )
+        .unwrap();
+    let session = session_manager.get_session(session.id()).unwrap();
+    assert!(session_manager
+        .verify_access_token_with_scope(
+            session.access_token().unwrap(),
+            7,
+            &access,
+            &["orders:read"]
+        )
+        .is_err());
+
+    // the narrowed scope carries over, asking for the dropped scope again is widening.
+    let err = session_manager
+        .create_new_refresh_token_with_scopes(
+            session,
+            7,
+            &refresh,
+            Some(&Scopes::from_slice(&["profile", "orders:read"])),
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_scope");
+    let session = session_manager.get_session(session.id()).unwrap();
+    let (_, access) = session_manager
+        .create_new_refresh_token(session, 7, &refresh)
+        .unwrap();
+    let session = session_manager.get_session(session.id()).unwrap();
+    session_manager
+        .verify_access_token_with_scope(session.access_token().unwrap(), 7, &access, &["profile"])
+        .unwrap();
+}
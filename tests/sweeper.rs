@@ -0,0 +1,112 @@
+mod common;
+
+use chrono::{TimeDelta, Utc};
+use common::pool;
+use sheesh::{
+    auth_token::{AuthToken, TokenSubject, TokenType},
+    harness::{
+        sqlite::{SqliteHarnessSession, SqliteHarnessToken},
+        DbHarness, DbHarnessSession, DbHarnessToken,
+    },
+    scope::Scopes,
+    session::{Session, SessionManagerConfig},
+    sweeper::{SweepReport, SweeperConfig},
+};
+
+// a session whose tokens both expired an hour ago.
+fn expired_session(harness: &SqliteHarnessToken, sessions: &SqliteHarnessSession, id: i64) {
+    let expires = Utc::now() - TimeDelta::hours(1);
+    let refresh = AuthToken::from_values(
+        id,
+        TokenSubject::User(1),
+        TokenType::Refresh {
+            secret: "secret".to_string(),
+        },
+        expires,
+        true,
+        Scopes::new(),
+    );
+    let access = AuthToken::from_values(
+        id + 1,
+        TokenSubject::User(1),
+        TokenType::Access {
+            token: "digest".to_string(),
+        },
+        expires,
+        true,
+        Scopes::new(),
+    );
+    harness.insert(&refresh).unwrap();
+    harness.insert(&access).unwrap();
+    sessions
+        .insert(&Session::from_values(id, 1, Some(id), Some(id + 1)))
+        .unwrap();
+}
+
+#[test]
+fn sweep_removes_expired_tokens_and_dead_sessions() {
+    let (pool, _db) = pool("sweep");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+
+    let (live, _, _) = session_manager.new_session(1).unwrap();
+    let (logged_out, _, _) = session_manager.new_session(1).unwrap();
+    session_manager.invalidate_session(logged_out).unwrap();
+
+    let tokens = SqliteHarnessToken::new(pool.clone());
+    let sessions = SqliteHarnessSession::new(pool.clone());
+    for id in [100, 200, 300] {
+        expired_session(&tokens, &sessions, id);
+    }
+
+    // a batch of two needs several rounds for the six tokens and four sessions.
+    let sweeper = SweeperConfig::default()
+        .with_batch_size(2)
+        .init(sessions, tokens)
+        .unwrap();
+    assert_eq!(
+        sweeper.sweep().unwrap(),
+        SweepReport {
+            tokens: 6,
+            sessions: 4,
+        }
+    );
+    assert_eq!(sweeper.sweep().unwrap(), SweepReport::default());
+
+    let remaining = session_manager.get_user_sessions(1).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].id(), live.id());
+    let live = session_manager.get_session(live.id()).unwrap();
+    let tokens = SqliteHarnessToken::new(pool);
+    tokens
+        .read_refresh_token(live.refresh_token().unwrap())
+        .unwrap();
+    tokens
+        .read_access_token(live.access_token().unwrap())
+        .unwrap();
+}
+
+#[test]
+fn spawned_sweeper_sweeps_until_stopped() {
+    let (pool, _db) = pool("spawn");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    expired_session(&harness.token, &harness.session, 100);
+
+    let handle = SweeperConfig::default()
+        .with_interval(TimeDelta::hours(1))
+        .init(
+            SqliteHarnessSession::new(pool.clone()),
+            SqliteHarnessToken::new(pool.clone()),
+        )
+        .unwrap()
+        .spawn();
+    // the first sweep runs right away, stopping waits for it.
+    handle.stop();
+
+    assert!(harness.session.read_by_user(1).unwrap().is_empty());
+    assert_eq!(harness.token.delete_expired(Utc::now(), 10).unwrap(), 0);
+}
@@ -0,0 +1,355 @@
+mod common;
+
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use common::{pool, TestDb};
+use r2d2_sqlite::SqliteConnectionManager;
+use sheesh::{
+    auth_token::{encode_token, AuthToken},
+    harness::{
+        sqlite::{SqliteHarnessSession, SqliteHarnessToken, SqliteHarnessUser, SqliteTransaction},
+        DbHarness, DbHarnessSession, DbTransaction, HarnessError, HarnessErrorKind,
+    },
+    session::{Session, SessionManager, SessionManagerConfig},
+    user::{AccountStatus, Groups, PrivateUserMeta, PublicUserMeta, Role, User, UserManagerConfig},
+    ErrorKind,
+};
+
+// every transaction it opens fails at step `fail_at`, counted from 1 with the commit as the last step. 0 never fails.
+struct FaultySessions {
+    inner: SqliteHarnessSession,
+    fail_at: Arc<AtomicUsize>,
+}
+
+struct FaultyTransaction {
+    inner: SqliteTransaction,
+    fail_at: usize,
+    step: Cell<usize>,
+}
+
+impl FaultyTransaction {
+    fn step(&self) -> Result<(), HarnessError> {
+        self.step.set(self.step.get() + 1);
+        match self.step.get() == self.fail_at {
+            true => Err(HarnessError::new(
+                HarnessErrorKind::Unavailable,
+                "injected failure",
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+impl DbTransaction for FaultyTransaction {
+    fn insert_token(&self, token: &AuthToken) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.insert_token(token)
+    }
+
+    fn delete_access_token(&self, id: i64) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.delete_access_token(id)
+    }
+
+    fn delete_refresh_token(&self, id: i64) -> Result<bool, HarnessError> {
+        self.step()?;
+        self.inner.delete_refresh_token(id)
+    }
+
+    fn insert_session(&self, session: &Session) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.insert_session(session)
+    }
+
+    fn update_session(&self, session: &Session) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.update_session(session)
+    }
+
+    fn update_user<Pu, Pr>(&self, user: &User<Pu, Pr>) -> Result<usize, HarnessError>
+    where
+        Pu: PublicUserMeta,
+        Pr: PrivateUserMeta,
+    {
+        self.step()?;
+        self.inner.update_user(user)
+    }
+
+    fn insert_password_history(
+        &self,
+        user_id: i64,
+        secret: &str,
+        created: DateTime<Utc>,
+    ) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.insert_password_history(user_id, secret, created)
+    }
+
+    fn prune_password_history(
+        &self,
+        user_id: i64,
+        keep: usize,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.prune_password_history(user_id, keep, before)
+    }
+
+    fn delete_one_time_token(&self, id: i64) -> Result<bool, HarnessError> {
+        self.step()?;
+        self.inner.delete_one_time_token(id)
+    }
+
+    fn commit(self) -> Result<(), HarnessError> {
+        self.step()?;
+        self.inner.commit()
+    }
+}
+
+impl DbHarnessSession for FaultySessions {
+    type Transaction = FaultyTransaction;
+
+    fn transaction(&self) -> Result<FaultyTransaction, HarnessError> {
+        Ok(FaultyTransaction {
+            inner: self.inner.transaction()?,
+            fail_at: self.fail_at.load(Ordering::SeqCst),
+            step: Cell::new(0),
+        })
+    }
+
+    fn read(&self, id: i64) -> Result<Option<Session>, HarnessError> {
+        self.inner.read(id)
+    }
+
+    fn read_by_user(&self, user_id: i64) -> Result<Vec<Session>, HarnessError> {
+        self.inner.read_by_user(user_id)
+    }
+
+    fn read_by_access_token(&self, token_id: i64) -> Result<Option<Session>, HarnessError> {
+        self.inner.read_by_access_token(token_id)
+    }
+
+    fn read_by_refresh_token(&self, token_id: i64) -> Result<Option<Session>, HarnessError> {
+        self.inner.read_by_refresh_token(token_id)
+    }
+
+    fn update(&self, session: &Session) -> Result<(), HarnessError> {
+        self.inner.update(session)
+    }
+
+    fn insert(&self, session: &Session) -> Result<(), HarnessError> {
+        self.inner.insert(session)
+    }
+
+    fn delete(&self, id: i64) -> Result<(), HarnessError> {
+        self.inner.delete(id)
+    }
+
+    fn delete_dead(&self, limit: usize) -> Result<usize, HarnessError> {
+        self.inner.delete_dead(limit)
+    }
+}
+
+struct Setup {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+    fail_at: Arc<AtomicUsize>,
+    manager: SessionManager<sheesh::id::DefaultIdGenerator, FaultySessions, SqliteHarnessToken>,
+    _db: TestDb,
+}
+
+fn setup(name: &str) -> Setup {
+    let (pool, db) = pool(name);
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+
+    let fail_at = Arc::new(AtomicUsize::new(0));
+    let sessions = FaultySessions {
+        inner: harness.session,
+        fail_at: fail_at.clone(),
+    };
+    let manager = SessionManagerConfig::default()
+        .init(sessions, harness.token)
+        .unwrap();
+    Setup {
+        pool,
+        fail_at,
+        manager,
+        _db: db,
+    }
+}
+
+impl Setup {
+    // rows in the refresh_tokens, access_tokens and sessions tables.
+    fn rows(&self) -> [i64; 3] {
+        let connection = self.pool.get().unwrap();
+        ["refresh_tokens", "access_tokens", "sessions"].map(|table| {
+            connection
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        })
+    }
+
+    fn fail_at(&self, step: usize) {
+        self.fail_at.store(step, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn failed_login_leaves_no_tokens_behind() {
+    let setup = setup("login");
+
+    // insert refresh token, insert access token, insert session, commit.
+    for step in 1..=4 {
+        setup.fail_at(step);
+        assert!(setup.manager.new_session(1).is_err());
+        assert_eq!(setup.rows(), [0, 0, 0], "failed at step {}", step);
+    }
+
+    setup.fail_at(0);
+    setup.manager.new_session(1).unwrap();
+    assert_eq!(setup.rows(), [1, 1, 1]);
+}
+
+#[test]
+fn failed_rotation_keeps_the_current_tokens() {
+    let setup = setup("refresh");
+    let (session, refresh, access) = setup.manager.new_session(1).unwrap();
+
+    // delete the old refresh and access token, insert refresh token, insert access token, update session, commit.
+    for step in 1..=6 {
+        setup.fail_at(step);
+        let session = setup.manager.get_session(session.id()).unwrap();
+        assert!(setup
+            .manager
+            .create_new_refresh_token(session, 1, &refresh)
+            .is_err());
+        assert_eq!(setup.rows(), [1, 1, 1], "failed at step {}", step);
+        setup
+            .manager
+            .authenticate(&encode_token(session.access_token().unwrap(), &access))
+            .unwrap();
+    }
+
+    // the old tokens are gone with the rotation.
+    setup.fail_at(0);
+    let old = setup.manager.get_session(session.id()).unwrap();
+    setup
+        .manager
+        .create_new_refresh_token(old, 1, &refresh)
+        .unwrap();
+    assert_eq!(setup.rows(), [1, 1, 1]);
+    assert!(setup
+        .manager
+        .authenticate(&encode_token(old.access_token().unwrap(), &access))
+        .is_err());
+    assert!(setup
+        .manager
+        .get_session_by_refresh_token(old.refresh_token().unwrap())
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn replayed_refresh_tokens_rotate_once() {
+    let setup = setup("replay");
+    let (session, refresh, _) = setup.manager.new_session(1).unwrap();
+
+    // every thread read the session before any of them rotated.
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (manager, refresh) = (&setup.manager, &refresh);
+                scope.spawn(move || manager.create_new_refresh_token(session, 1, refresh))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert!(results
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .all(|err| matches!(err.kind, ErrorKind::NotAuthorized)));
+    // no second pair of tokens was left behind.
+    assert_eq!(setup.rows(), [1, 1, 1]);
+
+    let (_, access) = results.into_iter().find_map(Result::ok).unwrap();
+    let rotated = setup.manager.get_session(session.id()).unwrap();
+    setup
+        .manager
+        .authenticate(&encode_token(rotated.access_token().unwrap(), &access))
+        .unwrap();
+    assert!(setup
+        .manager
+        .create_new_refresh_token(rotated, 1, &refresh)
+        .is_err());
+}
+
+#[test]
+fn failed_logout_keeps_the_session_usable() {
+    let setup = setup("logout");
+    let (session, _, access) = setup.manager.new_session(1).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+
+    // delete refresh token, delete access token, update session, commit.
+    for step in 1..=4 {
+        setup.fail_at(step);
+        assert!(setup.manager.invalidate_session(session).is_err());
+        assert_eq!(setup.rows(), [1, 1, 1], "failed at step {}", step);
+        setup.manager.authenticate(&access).unwrap();
+    }
+
+    setup.fail_at(0);
+    setup.manager.invalidate_session(session).unwrap();
+    assert_eq!(setup.rows(), [0, 0, 1]);
+    assert!(setup.manager.authenticate(&access).is_err());
+}
+
+#[test]
+fn logout_kills_the_session_tokens() {
+    let setup = setup("user_logout");
+    let user_manager = UserManagerConfig::default()
+        .init(SqliteHarnessUser::new(setup.pool.clone()))
+        .unwrap();
+    let (session, refresh, access) = setup.manager.new_session(1).unwrap();
+    let access = encode_token(session.access_token().unwrap(), &access);
+    let user: User<(), ()> = User::from_values(
+        1,
+        Some(session.id()),
+        "alice".to_string(),
+        String::new(),
+        false,
+        Groups::new(),
+        Role::from_str("user"),
+        None,
+        AccountStatus::Active,
+        None,
+        false,
+        None,
+        None,
+    );
+
+    assert!(user_manager
+        .logout(&setup.manager, &user, "not the refresh token")
+        .is_err());
+    setup.manager.authenticate(&access).unwrap();
+
+    user_manager
+        .logout(&setup.manager, &user, &refresh)
+        .unwrap();
+    assert_eq!(setup.rows(), [0, 0, 1]);
+    assert!(setup.manager.authenticate(&access).is_err());
+    let session = setup.manager.get_session(session.id()).unwrap();
+    assert!(setup
+        .manager
+        .create_new_refresh_token(session, 1, &refresh)
+        .is_err());
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::pool;
+use sheesh::{
+    harness::{sqlite::SqliteHarnessOneTimeToken, DbHarness},
+    one_time::{InMemoryDelivery, OneTimePurpose},
+    session::SessionManagerConfig,
+    user::{AccountStatus, Role, User, UserManagerConfig},
+    verification::VerificationConfig,
+    ErrorKind,
+};
+
+#[test]
+fn signups_log_in_once_the_email_is_verified() {
+    let (pool, _db) = pool("signup");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let session_manager = SessionManagerConfig::default()
+        .init(harness.session, harness.token)
+        .unwrap();
+    let delivery = InMemoryDelivery::new();
+    let verification = VerificationConfig::default()
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user_with_email(
+            "alice".to_string(),
+            "alice@example.com".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(user.status(), AccountStatus::PendingVerification);
+    let err = user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind, ErrorKind::PendingVerification));
+
+    verification.request_verification(&user).unwrap();
+    let sent = delivery
+        .last(user.id(), OneTimePurpose::EmailVerification)
+        .unwrap();
+    assert_eq!(sent.email.as_deref(), Some("alice@example.com"));
+
+    verification
+        .verify_email(&user_manager, &sent.token)
+        .unwrap();
+    let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert_eq!(user.status(), AccountStatus::Active);
+    user_manager
+        .login(&session_manager, &user, "correct horse battery staple")
+        .unwrap();
+
+    let err = verification
+        .verify_email(&user_manager, &sent.token)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+
+    // users created without an address have nothing to verify.
+    let plain: User<(), ()> = user_manager
+        .create_user(
+            "bob".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(plain.status(), AccountStatus::Active);
+    assert_eq!(plain.email(), None);
+    let err = verification.request_verification(&plain).unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::MissingEmail));
+}
+
+#[test]
+fn email_changes_apply_once_confirmed() {
+    let (pool, _db) = pool("change");
+    let harness = DbHarness::new_sqlite(pool.clone());
+    harness.migrate().unwrap();
+    let one_time = SqliteHarnessOneTimeToken::new(pool);
+
+    let user_manager = UserManagerConfig::default().init(harness.user).unwrap();
+    let delivery = InMemoryDelivery::new();
+    let verification = VerificationConfig::default()
+        .init(one_time, &delivery)
+        .unwrap();
+
+    let user: User<(), ()> = user_manager
+        .create_user_with_email(
+            "carol".to_string(),
+            "carol@example.com".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    verification.request_verification(&user).unwrap();
+    let old = delivery
+        .last(user.id(), OneTimePurpose::EmailVerification)
+        .unwrap();
+
+    verification
+        .request_email_change(&user, "carol@example.org")
+        .unwrap();
+    let change = delivery
+        .last(user.id(), OneTimePurpose::EmailChange)
+        .unwrap();
+    assert_eq!(change.email.as_deref(), Some("carol@example.org"));
+    let unchanged: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert_eq!(unchanged.email(), Some("carol@example.com"));
+
+    verification
+        .confirm_email_change(&user_manager, &change.token)
+        .unwrap();
+    let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert_eq!(user.email(), Some("carol@example.org"));
+    assert_eq!(user.status(), AccountStatus::Active);
+
+    // the token sent to the previous address proves nothing about the new one.
+    let err = verification
+        .verify_email(&user_manager, &old.token)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+
+    let err = verification
+        .confirm_email_change(&user_manager, &change.token)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::TokenInvalid));
+
+    // another account took the address before the change was confirmed.
+    verification
+        .request_email_change(&user, "shared@example.com")
+        .unwrap();
+    let taken = delivery
+        .last(user.id(), OneTimePurpose::EmailChange)
+        .unwrap();
+    user_manager
+        .create_user_with_email::<(), ()>(
+            "dave".to_string(),
+            "shared@example.com".to_string(),
+            "correct horse battery staple".to_string(),
+            Role::from_str("user"),
+            None,
+            None,
+        )
+        .unwrap();
+    let err = verification
+        .confirm_email_change(&user_manager, &taken.token)
+        .unwrap_err();
+    assert_eq!(err.code(), "email_taken");
+    let user: User<(), ()> = user_manager.get_user(&user.id()).unwrap().unwrap();
+    assert_eq!(user.email(), Some("carol@example.org"));
+}